@@ -0,0 +1,203 @@
+//! A local HTTP API used to control OpenDeck from scripts, and when it is running in headless mode, where there is no window to control it with.
+//! Requests must carry the token stored in the `api-token` file in the config directory as a bearer token, so that other users and web pages cannot use it.
+
+use crate::events::frontend;
+use crate::shared::DEVICES;
+use crate::virtual_devices;
+
+use serde::Deserialize;
+use serde_json::json;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+pub const PORT: u16 = 57119;
+
+#[derive(Deserialize)]
+struct SwitchProfileBody {
+	profile: String,
+}
+
+#[derive(Deserialize)]
+struct OrientationBody {
+	orientation: u16,
+}
+
+#[derive(Deserialize)]
+struct BrightnessBody {
+	brightness: Option<u8>,
+}
+
+#[derive(Deserialize)]
+struct NudgeBody {
+	delta: i16,
+}
+
+#[derive(Deserialize)]
+struct VirtualDeviceBody {
+	#[serde(default)]
+	name: String,
+	rows: u8,
+	columns: u8,
+	#[serde(default)]
+	encoders: u8,
+}
+
+#[derive(Deserialize)]
+struct RotateBody {
+	ticks: i16,
+}
+
+fn respond(request: Request, status: u16, body: serde_json::Value) {
+	let response = Response::from_string(body.to_string()).with_status_code(status).with_header(Header {
+		field: "Content-Type".parse().unwrap(),
+		value: "application/json".parse().unwrap(),
+	});
+	let _ = request.respond(response);
+}
+
+/// Handle a request to the API, returning `None` if no endpoint matches it.
+async fn handle(method: &Method, segments: &[&str], body: &str) -> Result<Option<serde_json::Value>, anyhow::Error> {
+	let app = crate::APP_HANDLE.get().unwrap();
+	match (method, segments) {
+		(Method::Get, ["devices"]) => Ok(Some(serde_json::to_value(DEVICES.clone())?)),
+		(Method::Get, ["devices", device, "profiles"]) => {
+			let selected = crate::store::profiles::DEVICE_STORES.write().await.get_selected_profile(device)?;
+			Ok(Some(json!({ "profiles": crate::store::profiles::get_device_profiles(device)?, "selected": selected })))
+		}
+		(Method::Put, ["devices", device, "profile"]) => {
+			let body: SwitchProfileBody = serde_json::from_str(body)?;
+			frontend::profiles::set_selected_profile(device.to_string(), body.profile).await?;
+			Ok(Some(json!({})))
+		}
+		(Method::Get, ["stats", "images"]) => Ok(Some(serde_json::to_value(crate::elgato::image_write_stats())?)),
+		(Method::Put, ["devices", device, "orientation"]) => {
+			let body: OrientationBody = serde_json::from_str(body)?;
+			crate::events::inbound::devices::set_orientation(device, body.orientation).await?;
+			Ok(Some(json!({})))
+		}
+		(Method::Put, ["devices", device, "brightness"]) => {
+			let body: BrightnessBody = serde_json::from_str(body)?;
+			crate::brightness::set_device_brightness(device, body.brightness).await?;
+			Ok(Some(json!({})))
+		}
+		(Method::Post, ["devices", device, "brightness", "nudge"]) => {
+			let body: NudgeBody = serde_json::from_str(body)?;
+			crate::brightness::nudge_brightness(Some(device), body.delta).await?;
+			Ok(Some(json!({})))
+		}
+		(Method::Get, ["virtual-devices"]) => Ok(Some(serde_json::to_value(virtual_devices::get_virtual_devices()?)?)),
+		(Method::Post, ["virtual-devices"]) => {
+			let body: VirtualDeviceBody = serde_json::from_str(body)?;
+			Ok(Some(serde_json::to_value(
+				virtual_devices::create_virtual_device(body.name, body.rows, body.columns, body.encoders).await?,
+			)?))
+		}
+		(Method::Delete, ["virtual-devices", device]) => {
+			virtual_devices::remove_virtual_device(device).await?;
+			Ok(Some(json!({})))
+		}
+		(Method::Get, ["devices", device, "keys", position, "image"]) => Ok(Some(json!({ "image": virtual_devices::get_image(device, position.parse()?) }))),
+		(Method::Post, ["devices", device, "keys", position, action @ ("down" | "up" | "press")]) => {
+			let position = position.parse()?;
+			if *action != "up" {
+				virtual_devices::key(device, position, true).await?;
+			}
+			if *action != "down" {
+				virtual_devices::key(device, position, false).await?;
+			}
+			Ok(Some(json!({})))
+		}
+		(Method::Post, ["devices", device, "dials", index, "rotate"]) => {
+			let body: RotateBody = serde_json::from_str(body)?;
+			virtual_devices::dial_rotate(device, index.parse()?, body.ticks).await?;
+			Ok(Some(json!({})))
+		}
+		(Method::Post, ["devices", device, "dials", index, action @ ("down" | "up")]) => {
+			virtual_devices::dial(device, index.parse()?, *action == "down").await?;
+			Ok(Some(json!({})))
+		}
+		(Method::Get, ["plugins"]) => Ok(Some(serde_json::to_value(frontend::plugins::list_plugins(app.clone()).await?)?)),
+		(Method::Post, ["plugins", plugin, "reload"]) => {
+			frontend::plugins::reload_plugin(app.clone(), plugin.to_string()).await;
+			Ok(Some(json!({})))
+		}
+		(Method::Post, ["quit"]) => {
+			app.exit(0);
+			Ok(Some(json!({})))
+		}
+		_ => Ok(None),
+	}
+}
+
+/// Read the token that requests to the API must carry, generating it the first time the API is started.
+fn api_token() -> Result<String, anyhow::Error> {
+	let path = crate::shared::config_dir().join("api-token");
+	if let Ok(token) = std::fs::read_to_string(&path) {
+		if !token.trim().is_empty() {
+			return Ok(token.trim().to_owned());
+		}
+	}
+
+	let mut bytes = [0u8; 32];
+	getrandom::getrandom(&mut bytes).map_err(|error| anyhow::anyhow!("failed to generate API token: {error}"))?;
+	let token = bytes.iter().map(|byte| format!("{byte:02x}")).collect::<String>();
+	std::fs::create_dir_all(path.parent().unwrap())?;
+	std::fs::write(&path, &token)?;
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+		std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+	}
+	Ok(token)
+}
+
+/// Check that a request was made to the API directly and carries its token.
+/// The `Host` header is checked so that web pages cannot reach the API by rebinding a domain name to the loopback address.
+fn authorised(request: &Request, token: &str) -> bool {
+	let header = |name: &'static str| request.headers().iter().find(|header| header.field.equiv(name)).map(|header| header.value.as_str().trim());
+	let host_allowed = header("Host").is_some_and(|host| [format!("127.0.0.1:{PORT}"), format!("localhost:{PORT}")].iter().any(|allowed| allowed == host));
+	host_allowed && header("Authorization").and_then(|value| value.strip_prefix("Bearer ")) == Some(token)
+}
+
+/// Start the local API, which only accepts connections from the same machine.
+/// tiny_http is blocking, so requests are received on a thread of their own and handled on the async runtime from there.
+pub fn init_api() {
+	let token = match api_token() {
+		Ok(token) => token,
+		Err(error) => {
+			log::error!("Failed to read token for local API: {}", error);
+			return;
+		}
+	};
+	let server = match Server::http(("127.0.0.1", PORT)) {
+		Ok(server) => server,
+		Err(error) => {
+			log::error!("Failed to start local API: {}", error);
+			return;
+		}
+	};
+	log::info!("Local API listening on http://127.0.0.1:{}", PORT);
+
+	let runtime = tokio::runtime::Handle::current();
+	std::thread::spawn(move || {
+		while let Ok(mut request) = server.recv() {
+			if !authorised(&request, &token) {
+				respond(request, 401, json!({ "error": "unauthorised" }));
+				continue;
+			}
+
+			let url = urlencoding::decode(request.url()).map(|url| url.into_owned()).unwrap_or_default();
+			let path = url.split_once('?').map_or(url.as_str(), |(path, _)| path).to_owned();
+			let segments = path.split('/').filter(|segment| !segment.is_empty()).collect::<Vec<_>>();
+
+			let mut body = String::new();
+			let _ = request.as_reader().read_to_string(&mut body);
+
+			let method = request.method().clone();
+			match runtime.block_on(handle(&method, &segments, &body)) {
+				Ok(Some(value)) => respond(request, 200, value),
+				Ok(None) => respond(request, 404, json!({ "error": "not found" })),
+				Err(error) => respond(request, 400, json!({ "error": error.to_string() })),
+			}
+		}
+	});
+}
@@ -4,7 +4,7 @@ use std::collections::HashMap;
 
 use active_win_pos_rs::get_active_window;
 use once_cell::sync::Lazy;
-use tauri::{Emitter, Manager};
+use tauri::Emitter;
 use tokio::sync::RwLock;
 
 pub type ApplicationProfiles = HashMap<String, HashMap<String, String>>;
@@ -13,12 +13,6 @@ impl NotProfile for ApplicationProfiles {}
 pub static APPLICATIONS: RwLock<Vec<String>> = RwLock::const_new(Vec::new());
 pub static APPLICATION_PROFILES: Lazy<RwLock<Store<ApplicationProfiles>>> = Lazy::new(|| RwLock::const_new(Store::new("applications", &crate::shared::config_dir(), HashMap::new()).unwrap()));
 
-#[derive(Clone, serde::Serialize)]
-pub struct SwitchProfileEvent {
-	device: String,
-	profile: String,
-}
-
 pub fn init_application_watcher() {
 	tokio::spawn(async move {
 		let mut previous = String::new();
@@ -28,7 +22,7 @@ pub fn init_application_watcher() {
 				let mut applications = APPLICATIONS.write().await;
 				if !applications.contains(&win.app_name) && win.app_name.to_lowercase() != "opendeck" {
 					applications.push(win.app_name.clone());
-					let _ = app_handle.get_webview_window("main").unwrap().emit("applications", applications.clone());
+					let _ = app_handle.emit_to("main", "applications", applications.clone());
 				}
 				if win.app_name != previous {
 					let application_profiles = &APPLICATION_PROFILES.read().await.value;
@@ -38,13 +32,9 @@ pub fn init_application_watcher() {
 							if !crate::shared::DEVICES.contains_key(device) {
 								continue;
 							}
-							let _ = app_handle.get_webview_window("main").unwrap().emit(
-								"switch_profile",
-								SwitchProfileEvent {
-									device: device.clone(),
-									profile: profile.clone(),
-								},
-							);
+							if let Err(error) = crate::events::frontend::profiles::request_switch_profile(device.clone(), profile.clone()).await {
+								log::warn!("Failed to switch profile for application {}: {}", win.app_name, error);
+							}
 						}
 					}
 				}
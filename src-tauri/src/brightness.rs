@@ -0,0 +1,277 @@
+//! Brightness of devices, which is set for each device or follows the brightness in settings, and is scaled by a schedule that changes with the time of day.
+
+use crate::shared::{ActionInstance, DEVICES};
+use crate::store::{get_settings, profiles::DEVICE_STORES, ScheduledBrightness, Settings};
+
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use chrono::Timelike;
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use tokio::sync::Notify;
+
+/// Temporary adjustments to the brightness of devices, which last until the level in the brightness schedule or the brightness settings change.
+static NUDGES: Lazy<DashMap<String, i16>> = Lazy::new(DashMap::new);
+/// The brightness most recently written to each device.
+static APPLIED: Lazy<DashMap<String, u8>> = Lazy::new(DashMap::new);
+/// The settings brightness is calculated from, kept so that they are not read from disk each time the schedule is checked.
+static SETTINGS: Lazy<RwLock<Option<Arc<Settings>>>> = Lazy::new(|| RwLock::new(None));
+/// Notified when the settings change, so that the scheduler works out when it next needs to run from the new schedule.
+static SETTINGS_CHANGED: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// Get the settings brightness is calculated from, reading them from disk the first time.
+fn settings() -> Result<Arc<Settings>, anyhow::Error> {
+	if let Some(settings) = SETTINGS.read().unwrap().as_ref() {
+		return Ok(settings.clone());
+	}
+	let settings = Arc::new(get_settings()?.value);
+	*SETTINGS.write().unwrap() = Some(settings.clone());
+	Ok(settings)
+}
+
+/// Parse a time of day in the `HH:MM` format into minutes since midnight.
+fn parse_time(time: &str) -> Option<u32> {
+	let (hours, minutes) = time.trim().split_once(':')?;
+	let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+	(hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+/// Get the level of a brightness schedule at a time of day, in minutes since midnight, alongside the index of the entry in effect.
+/// After the time of each entry, the level fades linearly from that of the previous entry over `fade` minutes.
+fn scheduled_level(schedule: &[ScheduledBrightness], fade: u32, now: f64) -> Option<(usize, f64)> {
+	let mut entries = schedule
+		.iter()
+		.enumerate()
+		.filter_map(|(index, entry)| Some((parse_time(&entry.time)?, entry.level.min(100) as f64, index)))
+		.collect::<Vec<_>>();
+	entries.sort_by_key(|(time, ..)| *time);
+
+	// Before the first entry of the day, the last entry of the previous day is still in effect.
+	let position = entries.iter().rposition(|(time, ..)| *time as f64 <= now).unwrap_or(entries.len().checked_sub(1)?);
+	let (time, level, index) = entries[position];
+	let previous = entries[(position + entries.len() - 1) % entries.len()].1;
+	let elapsed = (now - time as f64).rem_euclid(24.0 * 60.0);
+	if elapsed >= fade as f64 {
+		Some((index, level))
+	} else {
+		Some((index, previous + (level - previous) * elapsed / fade as f64))
+	}
+}
+
+/// Get how many minutes from a time of day, in minutes since midnight, the level of a brightness schedule next changes, which is continually while it is fading.
+fn next_change(schedule: &[ScheduledBrightness], fade: u32, now: f64) -> Option<f64> {
+	let times = schedule.iter().filter_map(|entry| parse_time(&entry.time)).collect::<Vec<_>>();
+	let fading = times.iter().any(|time| (now - *time as f64).rem_euclid(24.0 * 60.0) < fade as f64);
+	if fading {
+		return Some(0.0);
+	}
+	times.iter().map(|time| (*time as f64 - now).rem_euclid(24.0 * 60.0)).min_by(f64::total_cmp)
+}
+
+fn minutes_since_midnight() -> f64 {
+	let now = chrono::Local::now();
+	now.hour() as f64 * 60.0 + now.minute() as f64 + now.second() as f64 / 60.0
+}
+
+/// Get the brightness that a device should currently be shown at.
+async fn target_brightness(device: &str, settings: &Settings) -> Result<u8, anyhow::Error> {
+	let base = DEVICE_STORES.read().await.read_config(device, |config| config.brightness)?.unwrap_or(settings.brightness).min(100) as f64;
+	let level = scheduled_level(&settings.brightnessschedule, settings.brightnessfade, minutes_since_midnight()).map_or(100.0, |(_, level)| level);
+	let nudge = NUDGES.get(device).map_or(0, |nudge| *nudge);
+	let brightness = ((base * level / 100.0).round() as i16 + nudge).clamp(0, 100) as u8;
+	Ok(crate::idle::limit_brightness(device, brightness))
+}
+
+/// Write the brightness that a device should currently be shown at to it if it has changed, or regardless if `force` is set.
+async fn apply(device: &str, settings: &Settings, force: bool) -> Result<(), anyhow::Error> {
+	let brightness = target_brightness(device, settings).await?;
+	if force || APPLIED.get(device).map(|applied| *applied) != Some(brightness) {
+		APPLIED.insert(device.to_owned(), brightness);
+		crate::events::outbound::devices::set_brightness(device, brightness).await?;
+	}
+	Ok(())
+}
+
+fn connected_devices() -> Vec<String> {
+	DEVICES.iter().map(|device| device.key().clone()).collect()
+}
+
+/// Write the current brightness of a device to it, such as when it is connected.
+pub async fn update_device(device: &str) -> Result<(), anyhow::Error> {
+	let settings = settings()?;
+	apply(device, &settings, true).await
+}
+
+/// Forget the state of a device when it is disconnected.
+pub fn forget_device(device: &str) {
+	APPLIED.remove(device);
+	NUDGES.remove(device);
+}
+
+/// Update the brightness of every device after the brightness settings may have changed, discarding nudges.
+pub async fn settings_changed() -> Result<(), anyhow::Error> {
+	NUDGES.clear();
+	let settings = Arc::new(get_settings()?.value);
+	*SETTINGS.write().unwrap() = Some(settings.clone());
+	SETTINGS_CHANGED.notify_one();
+	for device in connected_devices() {
+		apply(&device, &settings, false).await?;
+	}
+	Ok(())
+}
+
+/// Set the brightness of a device, or pass `None` for it to use the brightness in settings.
+pub async fn set_device_brightness(device: &str, brightness: Option<u8>) -> Result<(), anyhow::Error> {
+	if !DEVICES.contains_key(device) {
+		return Err(anyhow!("device {device} not found"));
+	}
+	DEVICE_STORES
+		.write()
+		.await
+		.update_config(device, |config| config.brightness = brightness.map(|brightness| brightness.min(100)))?;
+	NUDGES.remove(device);
+	update_device(device).await
+}
+
+/// Temporarily adjust the brightness of a device, or of every device if `device` is `None`, by an amount that may be negative.
+pub async fn nudge_brightness(device: Option<&str>, delta: i16) -> Result<(), anyhow::Error> {
+	let settings = settings()?;
+	let devices = match device {
+		Some(device) if !DEVICES.contains_key(device) => return Err(anyhow!("device {device} not found")),
+		Some(device) => vec![device.to_owned()],
+		None => connected_devices(),
+	};
+	for device in devices {
+		let current = target_brightness(&device, &settings).await? as i16;
+		// Only the part of the nudge that takes effect is kept, so that nudging in the other direction has an immediate effect.
+		let effective = (current + delta).clamp(0, 100) - current;
+		*NUDGES.entry(device.clone()).or_insert(0) += effective;
+		apply(&device, &settings, false).await?;
+	}
+	Ok(())
+}
+
+/// Discard the adjustments made to the brightness of a device, returning it to the brightness it would otherwise be shown at.
+pub async fn reset_nudge(device: &str) -> Result<(), anyhow::Error> {
+	NUDGES.remove(device);
+	let settings = settings()?;
+	apply(device, &settings, false).await
+}
+
+/// Handle the built-in brightness action, which adjusts the brightness of its device by the step in its settings when its key is pressed, or for each tick its dial is turned.
+pub async fn nudge_from_action(instance: &ActionInstance, ticks: i16) -> Result<(), anyhow::Error> {
+	let step = instance.settings["step"].as_i64().unwrap_or(10).clamp(-100, 100) as i16;
+	nudge_brightness(Some(&instance.context.device), step.saturating_mul(ticks)).await
+}
+
+/// Start applying the brightness schedule to devices as the time of day changes.
+/// The schedule is checked when an entry takes effect and regularly while fading between levels, or at least every minute in case the clock changes.
+pub fn init_brightness_scheduler() {
+	tokio::spawn(async {
+		let mut current_entry = None;
+		loop {
+			let delay = match settings() {
+				Ok(settings) => {
+					let now = minutes_since_midnight();
+					let entry = scheduled_level(&settings.brightnessschedule, settings.brightnessfade, now).map(|(index, _)| index);
+					if entry != current_entry {
+						NUDGES.clear();
+						current_entry = entry;
+					}
+
+					for device in connected_devices() {
+						if let Err(error) = apply(&device, &settings, false).await {
+							log::warn!("Failed to update brightness of device {}: {}", device, error);
+						}
+					}
+
+					let minutes = next_change(&settings.brightnessschedule, settings.brightnessfade, now).unwrap_or(1.0).clamp(0.0, 1.0);
+					Duration::from_secs_f64(minutes * 60.0).max(Duration::from_secs(2))
+				}
+				Err(error) => {
+					log::warn!("Failed to read brightness settings: {}", error);
+					Duration::from_secs(60)
+				}
+			};
+
+			tokio::select! {
+				_ = tokio::time::sleep(delay) => {}
+				_ = SETTINGS_CHANGED.notified() => {}
+			}
+		}
+	});
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn schedule(entries: &[(&str, u8)]) -> Vec<ScheduledBrightness> {
+		entries
+			.iter()
+			.map(|(time, level)| ScheduledBrightness {
+				time: (*time).to_owned(),
+				level: *level,
+			})
+			.collect()
+	}
+
+	fn at(hours: u32, minutes: u32) -> f64 {
+		(hours * 60 + minutes) as f64
+	}
+
+	#[test]
+	fn empty_schedule() {
+		assert_eq!(scheduled_level(&[], 60, at(12, 0)), None);
+		assert_eq!(next_change(&[], 60, at(12, 0)), None);
+	}
+
+	#[test]
+	fn before_first_entry() {
+		let schedule = schedule(&[("22:00", 20), ("07:00", 100)]);
+		// The last entry of the previous day is still in effect, and has finished fading long before.
+		assert_eq!(scheduled_level(&schedule, 60, at(3, 0)), Some((0, 20.0)));
+		assert_eq!(next_change(&schedule, 60, at(3, 0)), Some(240.0));
+		assert_eq!(scheduled_level(&schedule, 60, at(12, 0)), Some((1, 100.0)));
+		assert_eq!(next_change(&schedule, 60, at(12, 0)), Some(600.0));
+	}
+
+	#[test]
+	fn fade_across_midnight() {
+		let schedule = schedule(&[("08:00", 40), ("23:30", 100)]);
+		assert_eq!(scheduled_level(&schedule, 60, at(23, 30)), Some((1, 40.0)));
+		assert_eq!(scheduled_level(&schedule, 60, at(23, 45)), Some((1, 55.0)));
+		assert_eq!(scheduled_level(&schedule, 60, at(0, 0)), Some((1, 70.0)));
+		assert_eq!(scheduled_level(&schedule, 60, at(0, 30)), Some((1, 100.0)));
+		// The level changes continually while it is fading.
+		assert_eq!(next_change(&schedule, 60, at(0, 0)), Some(0.0));
+		assert_eq!(next_change(&schedule, 60, at(0, 30)), Some(450.0));
+	}
+
+	#[test]
+	fn without_fade() {
+		let schedule = schedule(&[("07:00", 100), ("22:00", 20)]);
+		assert_eq!(scheduled_level(&schedule, 0, at(6, 59)), Some((1, 20.0)));
+		assert_eq!(scheduled_level(&schedule, 0, at(7, 0)), Some((0, 100.0)));
+		assert_eq!(next_change(&schedule, 0, at(6, 59)), Some(1.0));
+		assert_eq!(next_change(&schedule, 0, at(7, 1)), Some(899.0));
+	}
+
+	#[test]
+	fn invalid_times() {
+		assert_eq!(parse_time("07:30"), Some(450));
+		assert_eq!(parse_time(" 7:05 "), Some(425));
+		for time in ["24:00", "12:60", "12", "noon", "-1:00", ""] {
+			assert_eq!(parse_time(time), None, "{time} was accepted");
+		}
+
+		// Entries with invalid times are ignored, and the indices of the others are kept.
+		let schedule = schedule(&[("25:00", 0), ("noon", 0), ("12:00", 150)]);
+		assert_eq!(scheduled_level(&schedule, 0, at(11, 0)), Some((2, 100.0)));
+		assert_eq!(next_change(&schedule, 0, at(11, 0)), Some(60.0));
+		assert_eq!(scheduled_level(&schedule[..2], 0, at(11, 0)), None);
+		assert_eq!(next_change(&schedule[..2], 0, at(11, 0)), None);
+	}
+}
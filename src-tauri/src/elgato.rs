@@ -1,41 +1,189 @@
 use crate::events::outbound::{encoder, keypad};
 
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
-use base64::Engine as _;
-use elgato_streamdeck::{info, AsyncStreamDeck, DeviceStateUpdate};
+use dashmap::DashSet;
+use elgato_streamdeck::{images::ImageRect, info, info::Kind, AsyncStreamDeck, DeviceStateUpdate};
+use image::{imageops::FilterType, DynamicImage};
 use once_cell::sync::Lazy;
-use tokio::sync::RwLock;
+use serde::Serialize;
+use tokio::sync::{Mutex, RwLock};
 
 static ELGATO_DEVICES: Lazy<RwLock<HashMap<String, AsyncStreamDeck>>> = Lazy::new(|| RwLock::new(HashMap::new()));
 
+/// The images shown on and waiting to be written to the keys of a device, so that writes can be coalesced and skipped when nothing changed.
+#[derive(Default)]
+struct ImageCache {
+	/// Hashes of the images shown on each key, where `None` is a cleared key.
+	shown: HashMap<u8, Option<u64>>,
+	/// The most recently requested image of each key that has not been written yet.
+	pending: HashMap<u8, Option<String>>,
+	/// Whether a task is writing the pending images to the device.
+	flushing: bool,
+	/// Held while writing to the device, so that clearing its screen cannot interleave with a batch of writes.
+	writing: Arc<Mutex<()>>,
+}
+
+static IMAGE_CACHES: Lazy<Mutex<HashMap<String, ImageCache>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+static IMAGES_WRITTEN: AtomicU64 = AtomicU64::new(0);
+static IMAGES_UNCHANGED: AtomicU64 = AtomicU64::new(0);
+static IMAGES_COALESCED: AtomicU64 = AtomicU64::new(0);
+
+/// Counts of key images written to devices and of writes that were skipped.
+#[derive(Serialize)]
+pub struct ImageWriteStats {
+	pub written: u64,
+	/// Writes skipped because the key already showed the same image.
+	pub unchanged: u64,
+	/// Writes skipped because a newer image for the same key was requested before they were written.
+	pub coalesced: u64,
+}
+
+pub fn image_write_stats() -> ImageWriteStats {
+	ImageWriteStats {
+		written: IMAGES_WRITTEN.load(Ordering::Relaxed),
+		unchanged: IMAGES_UNCHANGED.load(Ordering::Relaxed),
+		coalesced: IMAGES_COALESCED.load(Ordering::Relaxed),
+	}
+}
+
+/// Log the counts of key images written and skipped every minute while images are being written, to show how many writes are being saved.
+async fn log_image_write_stats() {
+	let mut last = 0;
+	loop {
+		tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+		let stats = image_write_stats();
+		let total = stats.written + stats.unchanged + stats.coalesced;
+		if total != last {
+			log::debug!("Key images written: {}, unchanged: {}, coalesced: {}", stats.written, stats.unchanged, stats.coalesced);
+			last = total;
+		}
+	}
+}
+
+/// Queue an image to be written to a key, replacing any image queued for it that has not been written yet.
 pub async fn update_image(context: &crate::shared::Context, image: Option<&str>) -> Result<(), anyhow::Error> {
-	if let Some(device) = ELGATO_DEVICES.read().await.get(&context.device) {
+	let mut caches = IMAGE_CACHES.lock().await;
+	let Some(cache) = caches.get_mut(&context.device) else { return Ok(()) };
+	if cache.pending.insert(context.position, image.map(str::to_owned)).is_some() {
+		IMAGES_COALESCED.fetch_add(1, Ordering::Relaxed);
+	}
+	if !cache.flushing {
+		cache.flushing = true;
+		tokio::spawn(flush_images(context.device.clone()));
+	}
+	Ok(())
+}
+
+fn hash_image(image: &str) -> u64 {
+	let mut hasher = std::collections::hash_map::DefaultHasher::new();
+	image.hash(&mut hasher);
+	hasher.finish()
+}
+
+/// Write the queued images of a device in batches until none are left, flushing the device once per batch.
+async fn flush_images(id: String) {
+	loop {
+		let Some(writing) = IMAGE_CACHES.lock().await.get(&id).map(|cache| cache.writing.clone()) else {
+			return;
+		};
+		let _writing = writing.lock().await;
+
+		let images = {
+			let mut caches = IMAGE_CACHES.lock().await;
+			let Some(cache) = caches.get_mut(&id) else { return };
+			if cache.pending.is_empty() {
+				cache.flushing = false;
+				return;
+			}
+			let mut images = vec![];
+			for (position, image) in std::mem::take(&mut cache.pending) {
+				let hash = image.as_deref().map(hash_image);
+				if cache.shown.insert(position, hash) == Some(hash) {
+					IMAGES_UNCHANGED.fetch_add(1, Ordering::Relaxed);
+				} else {
+					images.push((position, image));
+				}
+			}
+			images
+		};
+
+		if let Err(error) = write_images(&id, &images).await {
+			log::warn!("Failed to update device images: {}", error);
+			// What these keys show is unknown after a failed write, so they are written again the next time they are updated.
+			if let Some(cache) = IMAGE_CACHES.lock().await.get_mut(&id) {
+				for (position, _) in &images {
+					cache.shown.remove(position);
+				}
+			}
+		}
+	}
+}
+
+async fn write_images(id: &str, images: &[(u8, Option<String>)]) -> Result<(), anyhow::Error> {
+	if images.is_empty() {
+		return Ok(());
+	}
+	let devices = ELGATO_DEVICES.read().await;
+	let Some(device) = devices.get(id) else { return Ok(()) };
+	for (position, image) in images {
 		if let Some(image) = image {
-			let data = image.split_once(',').unwrap().1;
-			let bytes = base64::engine::general_purpose::STANDARD.decode(data)?;
-			device.set_button_image(context.position, image::load_from_memory(&bytes)?).await?;
+			device.set_button_image(*position, crate::render::load_image(image)?).await?;
 		} else {
-			device.clear_button_image(context.position).await?;
+			device.clear_button_image(*position).await?;
 		}
-		device.flush().await?;
+		IMAGES_WRITTEN.fetch_add(1, Ordering::Relaxed);
+	}
+	device.flush().await?;
+	Ok(())
+}
+
+pub async fn update_touchscreen(context: &crate::shared::Context, image: Option<DynamicImage>) -> Result<(), anyhow::Error> {
+	if let Some(device) = ELGATO_DEVICES.read().await.get(&context.device) {
+		let kind = device.kind();
+		let Some((width, height)) = kind.lcd_strip_size() else { return Ok(()) };
+		if kind.encoder_count() == 0 {
+			return Ok(());
+		}
+		let (width, height) = (width as u32 / kind.encoder_count() as u32, height as u32);
+		let image = image.unwrap_or_else(|| DynamicImage::new_rgb8(width, height)).resize_exact(width, height, FilterType::Triangle);
+		device.write_lcd((width * context.position as u32) as u16, 0, &ImageRect::from_image(image)?).await?;
 	}
 	Ok(())
 }
 
 pub async fn clear_screen(id: &str) -> Result<(), anyhow::Error> {
+	let Some(writing) = IMAGE_CACHES.lock().await.get(id).map(|cache| cache.writing.clone()) else {
+		return Ok(());
+	};
+	let _writing = writing.lock().await;
+	// Images queued before the screen was cleared are discarded rather than being written afterwards.
+	if let Some(cache) = IMAGE_CACHES.lock().await.get_mut(id) {
+		cache.pending.clear();
+		cache.shown.clear();
+	}
+
 	if let Some(device) = ELGATO_DEVICES.read().await.get(id) {
 		device.clear_all_button_images().await?;
 		device.flush().await?;
+		let kind = device.kind();
+		if let Some((width, height)) = kind.lcd_strip_size().filter(|_| kind.encoder_count() > 0) {
+			device.write_lcd(0, 0, &ImageRect::from_image(DynamicImage::new_rgb8(width as u32, height as u32))?).await?;
+		}
 	}
 	Ok(())
 }
 
-pub async fn set_brightness(brightness: u8) {
-	for (_id, device) in ELGATO_DEVICES.read().await.iter() {
-		let _ = device.set_brightness(brightness.clamp(0, 100)).await;
-		let _ = device.flush().await;
+pub async fn set_brightness(id: &str, brightness: u8) -> Result<(), anyhow::Error> {
+	if let Some(device) = ELGATO_DEVICES.read().await.get(id) {
+		device.set_brightness(brightness.clamp(0, 100)).await?;
+		device.flush().await?;
 	}
+	Ok(())
 }
 
 pub async fn reset_devices() {
@@ -45,11 +193,19 @@ pub async fn reset_devices() {
 	}
 }
 
-async fn init(device: AsyncStreamDeck, serial: String) {
-	let device_id = format!("sd-{serial}");
-	if ELGATO_DEVICES.read().await.contains_key(&device_id) {
-		return;
+/// Send a touch on the touchscreen to the encoder instance whose segment contains the touch point.
+async fn touch(device_id: &str, kind: Kind, x: u16, y: u16, hold: bool) -> Result<(), anyhow::Error> {
+	let Some((width, _)) = kind.lcd_strip_size() else { return Ok(()) };
+	if kind.encoder_count() == 0 {
+		return Ok(());
 	}
+	let segment_width = width as u16 / kind.encoder_count() as u16;
+	let index = (x / segment_width).min(kind.encoder_count() as u16 - 1);
+	encoder::touch_tap(device_id, index as u8, [x - index * segment_width, y], hold).await
+}
+
+async fn init(device: AsyncStreamDeck, connecting: Connecting) {
+	let device_id = format!("sd-{}", connecting.0);
 
 	let kind = device.kind();
 	let device_type = match kind.product_id() {
@@ -64,30 +220,35 @@ async fn init(device: AsyncStreamDeck, serial: String) {
 		info::PID_AJAZZ_AKP815 => 0,
 		_ => 2,
 	};
+	// The brightness of the device is set when it is registered.
 	let _ = device.clear_all_button_images().await;
-	if let Ok(settings) = crate::store::get_settings() {
-		let _ = device.set_brightness(settings.value.brightness).await;
-	}
 	let _ = device.flush().await;
+	let name = device.product().await.unwrap();
+
+	// The device must be available before it is registered so that instances appearing on it can update its displays.
+	let reader = device.get_reader();
+	IMAGE_CACHES.lock().await.insert(device_id.clone(), ImageCache::default());
+	ELGATO_DEVICES.write().await.insert(device_id.clone(), device);
+	drop(connecting);
+
 	crate::events::inbound::devices::register_device(
 		"",
 		crate::events::inbound::PayloadEvent {
 			payload: crate::shared::DeviceInfo {
 				id: device_id.clone(),
 				plugin: String::new(),
-				name: device.product().await.unwrap(),
+				name,
 				rows: kind.row_count(),
 				columns: kind.column_count(),
 				encoders: kind.encoder_count(),
 				r#type: device_type,
+				orientation: 0,
 			},
 		},
 	)
 	.await
 	.unwrap();
 
-	let reader = device.get_reader();
-	ELGATO_DEVICES.write().await.insert(device_id.clone(), device);
 	loop {
 		let updates = match reader.read(100.0).await {
 			Ok(updates) => updates,
@@ -100,6 +261,11 @@ async fn init(device: AsyncStreamDeck, serial: String) {
 				DeviceStateUpdate::EncoderTwist(dial, ticks) => encoder::dial_rotate(&device_id, dial, ticks.into()).await,
 				DeviceStateUpdate::EncoderDown(dial) => encoder::dial_press(&device_id, "dialDown", dial).await,
 				DeviceStateUpdate::EncoderUp(dial) => encoder::dial_press(&device_id, "dialUp", dial).await,
+				DeviceStateUpdate::TouchScreenPress(x, y) => touch(&device_id, kind, x, y, false).await,
+				DeviceStateUpdate::TouchScreenLongPress(x, y) => touch(&device_id, kind, x, y, true).await,
+				DeviceStateUpdate::TouchScreenSwipe(from, to) => crate::events::frontend::profiles::swipe(&device_id, to.0 < from.0).await,
+				// The touch points on either side of the Stream Deck Neo's info screen are treated as swipes in their direction.
+				DeviceStateUpdate::TouchPointUp(point) => crate::events::frontend::profiles::swipe(&device_id, point != 0).await,
 				_ => Ok(()),
 			} {
 				Ok(_) => (),
@@ -108,10 +274,30 @@ async fn init(device: AsyncStreamDeck, serial: String) {
 		}
 	}
 
-	ELGATO_DEVICES.write().await.remove(&device_id);
-	crate::events::inbound::devices::deregister_device("", crate::events::inbound::PayloadEvent { payload: device_id })
-		.await
-		.unwrap();
+	disconnect(&device_id).await;
+}
+
+/// Forget a device that has been disconnected and deregister it, unless this was already done when udev reported its removal.
+async fn disconnect(device_id: &str) {
+	if ELGATO_DEVICES.write().await.remove(device_id).is_none() {
+		return;
+	}
+	IMAGE_CACHES.lock().await.remove(device_id);
+	if let Err(error) = crate::events::inbound::devices::deregister_device("", crate::events::inbound::PayloadEvent { payload: device_id.to_owned() }).await {
+		log::warn!("Failed to deregister device {}: {}", device_id, error);
+	}
+}
+
+/// Serial numbers of devices that are being initialised, so that they are not initialised again before they are registered.
+static CONNECTING: Lazy<DashSet<String>> = Lazy::new(DashSet::new);
+
+/// Removes the serial number of a device from `CONNECTING` when dropped, so that it can be connected again even if initialising it panics.
+struct Connecting(String);
+
+impl Drop for Connecting {
+	fn drop(&mut self) {
+		CONNECTING.remove(&self.0);
+	}
 }
 
 /// Attempt to initialise all connected devices.
@@ -120,9 +306,13 @@ pub async fn initialise_devices() {
 	match elgato_streamdeck::new_hidapi() {
 		Ok(hid) => {
 			for (kind, serial) in elgato_streamdeck::asynchronous::list_devices_async(&hid) {
+				if ELGATO_DEVICES.read().await.contains_key(&format!("sd-{serial}")) || !CONNECTING.insert(serial.clone()) {
+					continue;
+				}
+				let connecting = Connecting(serial.clone());
 				match elgato_streamdeck::AsyncStreamDeck::connect(&hid, kind, &serial) {
 					Ok(device) => {
-						tokio::spawn(init(device, serial));
+						tokio::spawn(init(device, connecting));
 					}
 					Err(error) => log::warn!("Failed to connect to Elgato device: {error}"),
 				}
@@ -131,3 +321,69 @@ pub async fn initialise_devices() {
 		Err(error) => log::warn!("Failed to initialise hidapi: {error}"),
 	}
 }
+
+/// Initialise devices as they are connected, using udev events on Linux and polling elsewhere or if udev is unavailable.
+/// Disconnected devices are deregistered as soon as reading from them fails, or when udev reports that they were removed.
+pub async fn watch_devices() {
+	tokio::spawn(log_image_write_stats());
+	initialise_devices().await;
+
+	#[cfg(target_os = "linux")]
+	if let Err(error) = watch_udev().await {
+		log::warn!("Failed to watch for devices with udev, falling back to polling: {error}");
+	}
+
+	loop {
+		tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+		initialise_devices().await;
+	}
+}
+
+// The udev monitor socket is held across awaits in a spawned task, which requires it to be sendable between threads.
+#[cfg(target_os = "linux")]
+const _: fn() = || {
+	fn assert_send_sync<T: Send + Sync>() {}
+	assert_send_sync::<tokio::io::unix::AsyncFd<udev::MonitorSocket>>();
+};
+
+#[cfg(target_os = "linux")]
+async fn watch_udev() -> Result<(), anyhow::Error> {
+	let socket = tokio::io::unix::AsyncFd::new(udev::MonitorBuilder::new()?.match_subsystem("hidraw")?.listen()?)?;
+	loop {
+		let (added, removed) = {
+			let mut guard = socket.readable().await?;
+			let events = guard.get_inner().iter().map(|event| event.event_type()).collect::<Vec<_>>();
+			guard.clear_ready();
+			(events.contains(&udev::EventType::Add), events.contains(&udev::EventType::Remove))
+		};
+
+		if removed {
+			remove_disconnected_devices().await;
+		}
+		if added {
+			// Devices are given time for udev rules granting access to them to be applied before they are opened.
+			tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+			initialise_devices().await;
+		}
+	}
+}
+
+/// Deregister devices that are no longer connected, without waiting for reading from them to fail.
+#[cfg(target_os = "linux")]
+async fn remove_disconnected_devices() {
+	let hid = match elgato_streamdeck::new_hidapi() {
+		Ok(hid) => hid,
+		Err(error) => {
+			log::warn!("Failed to initialise hidapi: {error}");
+			return;
+		}
+	};
+	let connected = elgato_streamdeck::asynchronous::list_devices_async(&hid)
+		.into_iter()
+		.map(|(_, serial)| format!("sd-{serial}"))
+		.collect::<std::collections::HashSet<_>>();
+	let removed = ELGATO_DEVICES.read().await.keys().filter(|id| !connected.contains(*id)).cloned().collect::<Vec<_>>();
+	for device_id in removed {
+		disconnect(&device_id).await;
+	}
+}
@@ -1,9 +1,9 @@
 use super::Error;
 
-use crate::shared::{config_dir, Action, ActionContext, ActionInstance, Context};
+use crate::shared::{config_dir, Action, ActionContext, ActionInstance, Context, DialOptions, TriggerDescription};
 use crate::store::profiles::{acquire_locks_mut, get_instance_mut, get_slot_mut, save_profile, LocksMut};
 
-use tauri::{command, AppHandle, Emitter, Manager};
+use tauri::{command, AppHandle, Emitter};
 use tokio::fs::remove_dir_all;
 
 #[command]
@@ -15,7 +15,18 @@ pub async fn create_instance(app: AppHandle, action: Action, context: Context) -
 	let mut locks = acquire_locks_mut().await;
 	let slot = get_slot_mut(&context, &mut locks).await?;
 
+	// Wait steps only make sense as part of the sequence of a multi action.
+	if action.uuid == "opendeck.wait" && !slot.as_ref().is_some_and(|parent| crate::multi_actions::MULTI_ACTIONS.contains(&parent.action.uuid.as_str())) {
+		return Ok(None);
+	}
+
 	if let Some(parent) = slot {
+		if parent.action.uuid == "opendeck.gestureaction" {
+			// Each child of a gesture action is run by a different gesture, so no more can be added once every gesture is taken.
+			let Some(gesture) = crate::gestures::free_gesture(parent) else { return Ok(None) };
+			let index = parent.children.as_ref().and_then(|children| children.last()).map_or(1, |instance| instance.context.index + 1);
+			parent.settings["gestures"][index.to_string()] = gesture.into();
+		}
 		let Some(children) = &mut parent.children else { return Ok(None) };
 		let index = match children.last() {
 			None => 1,
@@ -29,18 +40,25 @@ pub async fn create_instance(app: AppHandle, action: Action, context: Context) -
 			current_state: 0,
 			settings: serde_json::Value::Object(serde_json::Map::new()),
 			children: None,
+			dial: Default::default(),
 		};
 		children.push(instance.clone());
 
-		if parent.action.uuid == "opendeck.toggleaction" && parent.states.len() < children.len() {
+		let toggle = parent.action.uuid == "opendeck.toggleaction" && parent.states.len() < children.len();
+		if toggle {
 			parent.states.push(crate::shared::ActionState {
 				image: "opendeck/toggle-action.png".to_owned(),
 				..Default::default()
 			});
-			let _ = update_state(&app, parent.context.clone(), &mut locks).await;
 		}
+		let parent = parent.context.clone();
 
 		save_profile(&context.device, &mut locks).await?;
+		if toggle {
+			let _ = update_state(&app, parent, locks).await;
+		} else {
+			drop(locks);
+		}
 		let _ = crate::events::outbound::will_appear::will_appear(&instance).await;
 
 		Ok(Some(instance))
@@ -51,24 +69,35 @@ pub async fn create_instance(app: AppHandle, action: Action, context: Context) -
 			states: action.states.clone(),
 			current_state: 0,
 			settings: serde_json::Value::Object(serde_json::Map::new()),
-			children: if matches!(action.uuid.as_str(), "opendeck.multiaction" | "opendeck.toggleaction") {
+			children: if matches!(
+				action.uuid.as_str(),
+				"opendeck.multiaction" | "opendeck.multiactionswitch" | "opendeck.toggleaction" | "opendeck.gestureaction"
+			) {
 				Some(vec![])
 			} else {
 				None
 			},
+			dial: Default::default(),
 		};
 
 		*slot = Some(instance.clone());
-		let slot = slot.clone();
+		if action.uuid == "opendeck.folder" {
+			if let Err(error) = crate::pages::add_folder_page(&context, &mut locks).await {
+				*get_slot_mut(&context, &mut locks).await? = None;
+				return Err(error.into());
+			}
+		}
+		let slot = get_slot_mut(&context, &mut locks).await?.clone();
 
 		save_profile(&context.device, &mut locks).await?;
+		drop(locks);
 		let _ = crate::events::outbound::will_appear::will_appear(&instance).await;
 
 		Ok(slot)
 	}
 }
 
-fn instance_images_dir(context: &ActionContext) -> std::path::PathBuf {
+pub fn instance_images_dir(context: &ActionContext) -> std::path::PathBuf {
 	config_dir()
 		.join("images")
 		.join(&context.device)
@@ -88,10 +117,18 @@ pub async fn move_instance(source: Context, destination: Context, retain: bool)
 	let Some(mut new) = src.clone() else {
 		return Ok(None);
 	};
+	// Back keys belong to the pages opened by folders, and folders open their own page, so neither can be copied or moved to another page.
+	if matches!(new.action.uuid.as_str(), "opendeck.folder" | "opendeck.back") {
+		let size = crate::pages::page_size(&source.device, &source.controller).max(1);
+		if retain || source.profile != destination.profile || source.position / size != destination.position / size {
+			return Ok(None);
+		}
+	}
 	new.context = ActionContext::from_context(destination.clone(), 0);
 	if let Some(children) = &mut new.children {
-		for (index, instance) in children.iter_mut().enumerate() {
-			instance.context = ActionContext::from_context(destination.clone(), index as u16 + 1);
+		// Children keep their indices, which the settings of gesture actions refer to them by.
+		for instance in children.iter_mut() {
+			instance.context = ActionContext::from_context(destination.clone(), instance.context.index);
 			for (i, state) in instance.states.iter_mut().enumerate() {
 				if !instance.action.states[i].image.is_empty() {
 					state.image = instance.action.states[i].image.clone();
@@ -130,15 +167,145 @@ pub async fn move_instance(source: Context, destination: Context, retain: bool)
 			let _ = remove_dir_all(instance_images_dir(&old.context)).await;
 		}
 		*src = None;
+		crate::events::outbound::devices::forget_images(&source).await;
 	}
 
-	let _ = crate::events::outbound::will_appear::will_appear(&new).await;
-
 	save_profile(&destination.device, &mut locks).await?;
+	drop(locks);
+
+	let _ = crate::events::outbound::will_appear::will_appear(&new).await;
 
 	Ok(Some(new))
 }
 
+/// Assign a gesture of a gesture action to one of its children, swapping gestures with the child that had it.
+#[command]
+pub async fn set_gesture(context: ActionContext, gesture: String) -> Result<(), Error> {
+	if !crate::gestures::GESTURES.contains(&gesture.as_str()) {
+		return Err(Error::new(format!("unknown gesture {gesture}")));
+	}
+
+	let mut locks = acquire_locks_mut().await;
+	let Some(parent) = get_slot_mut(&(&context).into(), &mut locks).await? else {
+		return Ok(());
+	};
+	let Some(gestures) = parent.settings["gestures"].as_object_mut() else {
+		return Ok(());
+	};
+	let index = context.index.to_string();
+	let previous = gestures.remove(&index);
+	if let Some(holder) = gestures.iter().find(|(_, value)| value.as_str() == Some(&gesture)).map(|(key, _)| key.clone()) {
+		match previous {
+			Some(previous) => gestures.insert(holder, previous),
+			None => gestures.remove(&holder),
+		};
+	}
+	gestures.insert(index, gesture.into());
+
+	save_profile(&context.device, &mut locks).await?;
+	Ok(())
+}
+
+/// Set how long the key of a gesture action must be held for, and how soon it must be pressed again to make a double press, in milliseconds.
+#[command]
+pub async fn set_gesture_delays(context: Context, hold_delay: u64, double_delay: u64) -> Result<(), Error> {
+	let mut locks = acquire_locks_mut().await;
+	let Some(instance) = get_slot_mut(&context, &mut locks).await? else {
+		return Ok(());
+	};
+	if instance.action.uuid != "opendeck.gestureaction" {
+		return Ok(());
+	}
+	instance.settings["holddelay"] = hold_delay.into();
+	instance.settings["doubledelay"] = double_delay.into();
+
+	save_profile(&context.device, &mut locks).await?;
+	Ok(())
+}
+
+/// Set whether a step of a multi action presses its child, only sends it keyDown or only sends it keyUp, and how long to wait before it in milliseconds.
+#[command]
+pub async fn set_multi_action_step(context: ActionContext, delay: u64, mode: String) -> Result<(), Error> {
+	if !crate::multi_actions::STEP_MODES.contains(&mode.as_str()) {
+		return Err(Error::new(format!("unknown step mode {mode}")));
+	}
+
+	let mut locks = acquire_locks_mut().await;
+	let Some(parent) = get_slot_mut(&(&context).into(), &mut locks).await? else {
+		return Ok(());
+	};
+	if !crate::multi_actions::MULTI_ACTIONS.contains(&parent.action.uuid.as_str()) {
+		return Ok(());
+	}
+	parent.settings["steps"][context.index.to_string()] = serde_json::json!({ "delay": delay, "mode": mode });
+
+	save_profile(&context.device, &mut locks).await?;
+	Ok(())
+}
+
+/// Move a child of a multi action switch to its first or second sequence, which run on alternate presses.
+#[command]
+pub async fn set_multi_action_sequence(context: ActionContext, sequence: u16) -> Result<(), Error> {
+	if sequence > 1 {
+		return Err(Error::new(format!("unknown sequence {sequence}")));
+	}
+
+	let mut locks = acquire_locks_mut().await;
+	let Some(parent) = get_slot_mut(&(&context).into(), &mut locks).await? else {
+		return Ok(());
+	};
+	if parent.action.uuid != "opendeck.multiactionswitch" {
+		return Ok(());
+	}
+	parent.settings["sequences"][context.index.to_string()] = sequence.into();
+
+	save_profile(&context.device, &mut locks).await?;
+	Ok(())
+}
+
+/// Replace the settings of an instance of a built-in action, which has no property inspector of its own to set them.
+#[command]
+pub async fn set_instance_settings(context: ActionContext, settings: serde_json::Value) -> Result<(), Error> {
+	let mut locks = acquire_locks_mut().await;
+	let Some(instance) = get_instance_mut(&context, &mut locks).await? else {
+		return Ok(());
+	};
+	if instance.action.plugin != "opendeck" {
+		return Ok(());
+	}
+	instance.settings = settings;
+
+	save_profile(&context.device, &mut locks).await?;
+	Ok(())
+}
+
+/// Set how the rotation of the dial of an instance on an encoder is sent to its plugin.
+#[command]
+pub async fn set_dial_options(context: ActionContext, options: DialOptions) -> Result<(), Error> {
+	if context.controller != "Encoder" {
+		return Err(Error::new(format!("{context} is not on an encoder")));
+	}
+	let mut locks = acquire_locks_mut().await;
+	let Some(instance) = get_instance_mut(&context, &mut locks).await? else {
+		return Ok(());
+	};
+	instance.dial = Some(options);
+
+	save_profile(&context.device, &mut locks).await?;
+	Ok(())
+}
+
+/// Get the descriptions of the interactions of an encoder instance, as set by its plugin or otherwise from its manifest.
+#[command]
+pub async fn get_trigger_description(context: ActionContext) -> Result<Option<TriggerDescription>, Error> {
+	if let Some(description) = crate::render::layouts::FEEDBACK.read().await.get(&context).and_then(|feedback| feedback.trigger_description.clone()) {
+		return Ok(Some(description));
+	}
+	let mut locks = acquire_locks_mut().await;
+	let instance = get_instance_mut(&context, &mut locks).await?;
+	Ok(instance.and_then(|instance| instance.action.encoder.as_ref()?.trigger_description.clone()))
+}
+
 #[command]
 pub async fn remove_instance(context: ActionContext) -> Result<(), Error> {
 	let mut locks = acquire_locks_mut().await;
@@ -146,8 +313,21 @@ pub async fn remove_instance(context: ActionContext) -> Result<(), Error> {
 	let Some(instance) = slot else {
 		return Ok(());
 	};
+	// The instances shown on the device in place of others once the locks are released, and the toggle action whose state changed.
+	let mut shown = vec![];
+	let mut toggle = None;
 
 	if instance.context == context {
+		// Back keys are removed along with the folders that open their pages.
+		if instance.action.uuid == "opendeck.back" {
+			return Ok(());
+		}
+		if instance.action.uuid == "opendeck.folder" {
+			let folder = instance.clone();
+			shown = crate::pages::remove_folder_page(&folder, &mut locks).await?;
+		}
+		let slot = get_slot_mut(&(&context).into(), &mut locks).await?;
+		let Some(instance) = slot else { return Ok(()) };
 		let _ = crate::events::outbound::will_appear::will_disappear(instance, true).await;
 		if let Some(children) = &instance.children {
 			for child in children {
@@ -157,6 +337,7 @@ pub async fn remove_instance(context: ActionContext) -> Result<(), Error> {
 		}
 		let _ = remove_dir_all(instance_images_dir(&instance.context)).await;
 		*slot = None;
+		crate::events::outbound::devices::forget_images(&(&context).into()).await;
 	} else {
 		let children = instance.children.as_mut().unwrap();
 		for (index, instance) in children.iter().enumerate() {
@@ -167,18 +348,34 @@ pub async fn remove_instance(context: ActionContext) -> Result<(), Error> {
 				break;
 			}
 		}
+		for key in ["gestures", "steps", "sequences"] {
+			if let Some(children) = instance.settings.get_mut(key).and_then(serde_json::Value::as_object_mut) {
+				children.remove(&context.index.to_string());
+			}
+		}
 		if instance.action.uuid == "opendeck.toggleaction" {
 			if instance.current_state as usize >= children.len() {
 				instance.current_state = if children.is_empty() { 0 } else { children.len() as u16 - 1 };
 			}
 			if !children.is_empty() {
 				instance.states.pop();
-				let _ = update_state(crate::APP_HANDLE.get().unwrap(), instance.context.clone(), &mut locks).await;
+				toggle = Some(instance.context.clone());
 			}
 		}
 	}
 
+	// A gesture being told apart on the key may have been made with the instance that was removed.
+	crate::gestures::forget(&(&context).into()).await;
 	save_profile(&context.device, &mut locks).await?;
+	match toggle {
+		Some(toggle) => {
+			let _ = update_state(crate::APP_HANDLE.get().unwrap(), toggle, locks).await;
+		}
+		None => drop(locks),
+	}
+	for instance in &shown {
+		crate::events::outbound::will_appear::slot_will_appear(instance).await;
+	}
 
 	Ok(())
 }
@@ -189,15 +386,22 @@ struct UpdateStateEvent {
 	contents: Option<ActionInstance>,
 }
 
-pub async fn update_state(app: &AppHandle, context: ActionContext, locks: &mut LocksMut<'_>) -> Result<(), anyhow::Error> {
-	let window = app.get_webview_window("main").unwrap();
-	window.emit(
-		"update_state",
-		UpdateStateEvent {
-			contents: get_instance_mut(&context, locks).await?.cloned(),
-			context,
-		},
-	)?;
+/// Show the current state of an instance on its device and in the frontend.
+/// The locks are released before the instance is rendered, so that other keys and devices are not blocked while it is drawn, so any changes should be saved first.
+pub async fn update_state(app: &AppHandle, context: ActionContext, mut locks: LocksMut<'_>) -> Result<(), anyhow::Error> {
+	let contents = get_instance_mut(&context, &mut locks).await?.cloned();
+	drop(locks);
+	if let Some(instance) = &contents {
+		if instance.context.controller == "Encoder" {
+			if let Err(error) = crate::render::layouts::update_touchscreen(instance).await {
+				log::warn!("Failed to update device touchscreen: {}", error);
+			}
+		} else if let Err(error) = crate::render::keys::update_key(instance, false).await {
+			log::warn!("Failed to update device image: {}", error);
+		}
+	}
+
+	app.emit_to("main", "update_state", UpdateStateEvent { contents, context })?;
 	Ok(())
 }
 
@@ -207,21 +411,17 @@ pub async fn set_state(instance: ActionInstance, state: u16) -> Result<(), Error
 	let reference = get_instance_mut(&instance.context, &mut locks).await?.unwrap();
 	*reference = instance.clone();
 	save_profile(&instance.context.device, &mut locks).await?;
+	let selected = locks.device_stores.get_selected_profile(&instance.context.device)? == instance.context.profile;
+	drop(locks);
+	if selected {
+		if let Err(error) = crate::render::keys::update_key(&instance, false).await {
+			log::warn!("Failed to update device image: {}", error);
+		}
+	}
 	crate::events::outbound::states::title_parameters_did_change(&instance, state).await?;
 	Ok(())
 }
 
-#[command]
-pub async fn update_image(context: Context, image: String) {
-	if Some(&context.profile) != crate::store::profiles::DEVICE_STORES.write().await.get_selected_profile(&context.device).ok().as_ref() {
-		return;
-	}
-
-	if let Err(error) = crate::events::outbound::devices::update_image(context, Some(image)).await {
-		log::warn!("Failed to update device image: {}", error);
-	}
-}
-
 #[derive(Clone, serde::Serialize)]
 struct KeyMovedEvent {
 	context: Context,
@@ -229,7 +429,6 @@ struct KeyMovedEvent {
 }
 
 pub async fn key_moved(app: &AppHandle, context: Context, pressed: bool) -> Result<(), anyhow::Error> {
-	let window = app.get_webview_window("main").unwrap();
-	window.emit("key_moved", KeyMovedEvent { context, pressed })?;
+	app.emit_to("main", "key_moved", KeyMovedEvent { context, pressed })?;
 	Ok(())
 }
@@ -8,7 +8,7 @@ use crate::shared::{Action, DeviceInfo, CATEGORIES, DEVICES};
 
 use std::collections::HashMap;
 
-use tauri::{command, Emitter, Manager};
+use tauri::{command, Emitter};
 
 #[derive(Debug, serde_with::SerializeDisplay, serde::Deserialize)]
 pub struct Error {
@@ -51,9 +51,64 @@ pub async fn get_devices() -> dashmap::DashMap<String, DeviceInfo> {
 	DEVICES.clone()
 }
 
+#[command]
+pub fn get_image_write_stats() -> crate::elgato::ImageWriteStats {
+	crate::elgato::image_write_stats()
+}
+
+#[command]
+pub async fn set_device_orientation(device: String, orientation: u16) -> Result<(), Error> {
+	Ok(crate::events::inbound::devices::set_orientation(&device, orientation).await?)
+}
+
+#[command]
+pub async fn get_device_brightness(device: String) -> Result<Option<u8>, Error> {
+	Ok(crate::store::profiles::DEVICE_STORES.read().await.read_config(&device, |config| config.brightness)?)
+}
+
+#[command]
+pub async fn set_device_brightness(device: String, brightness: Option<u8>) -> Result<(), Error> {
+	Ok(crate::brightness::set_device_brightness(&device, brightness).await?)
+}
+
+#[command]
+pub async fn nudge_brightness(device: Option<String>, delta: i16) -> Result<(), Error> {
+	Ok(crate::brightness::nudge_brightness(device.as_deref(), delta).await?)
+}
+
+#[command]
+pub async fn get_device_idle(device: String) -> Result<crate::store::profiles::IdleConfig, Error> {
+	Ok(crate::store::profiles::DEVICE_STORES.read().await.read_config(&device, |config| config.idle.clone())?)
+}
+
+#[command]
+pub async fn set_device_idle(device: String, config: crate::store::profiles::IdleConfig) -> Result<(), Error> {
+	Ok(crate::idle::set_config(&device, config).await?)
+}
+
+#[command]
+pub fn get_virtual_devices() -> Result<Vec<crate::virtual_devices::VirtualDevice>, Error> {
+	Ok(crate::virtual_devices::get_virtual_devices()?)
+}
+
+#[command]
+pub async fn create_virtual_device(name: String, rows: u8, columns: u8, encoders: u8) -> Result<crate::virtual_devices::VirtualDevice, Error> {
+	Ok(crate::virtual_devices::create_virtual_device(name, rows, columns, encoders).await?)
+}
+
+#[command]
+pub async fn remove_virtual_device(id: String) -> Result<(), Error> {
+	Ok(crate::virtual_devices::remove_virtual_device(&id).await?)
+}
+
+#[command]
+pub async fn press_virtual_key(device: String, position: u8, pressed: bool) -> Result<(), Error> {
+	Ok(crate::virtual_devices::key(&device, position, pressed).await?)
+}
+
 pub async fn update_devices() {
 	let app = crate::APP_HANDLE.get().unwrap();
-	let _ = app.get_webview_window("main").unwrap().emit("devices", DEVICES.clone());
+	let _ = app.emit_to("main", "devices", DEVICES.clone());
 }
 
 #[command]
@@ -114,6 +114,10 @@ pub async fn install_plugin(app: AppHandle, url: Option<String>, file: Option<St
 	}
 	let _ = tokio::fs::remove_dir_all(config_dir.join("temp")).await;
 
+	if let Err(error) = crate::plugins::install_profiles(&actual).await {
+		log::warn!("Failed to install profiles bundled with plugin {}: {}", id, error);
+	}
+
 	use tauri_plugin_aptabase::EventTracker;
 	let _ = app.track_event("plugin_installed", Some(serde_json::json!({ "id": id.strip_suffix(".sdPlugin").unwrap_or(&id) })));
 
@@ -1,9 +1,22 @@
 use super::Error;
 
-use crate::shared::DEVICES;
-use crate::store::profiles::{acquire_locks_mut, get_device_profiles, PROFILE_STORES};
+use crate::events::outbound::will_appear::{slot_will_appear, slot_will_disappear};
+use crate::pages::{current_page, page_instances, reset_page, show_page};
+use crate::shared::{ActionInstance, DEVICES};
+use crate::store::profiles::{acquire_locks_mut, get_device_profiles, DEVICE_STORES, PROFILE_STORES};
 
-use tauri::{command, AppHandle, Emitter, Manager};
+use anyhow::anyhow;
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use tauri::{command, AppHandle, Emitter};
+
+/// The profile that was selected on each device before its current profile, which the profile switching action can return to.
+static PREVIOUS_PROFILES: Lazy<DashMap<String, String>> = Lazy::new(DashMap::new);
+
+/// Get the profile that was selected on a device before its current profile, if it has been switched since OpenDeck started.
+pub fn previous_profile(device: &str) -> Option<String> {
+	PREVIOUS_PROFILES.get(device).map(|profile| profile.clone())
+}
 
 #[command]
 pub fn get_profiles(device: &str) -> Result<Vec<String>, Error> {
@@ -23,57 +36,153 @@ pub async fn get_selected_profile(device: String) -> Result<crate::shared::Profi
 	Ok(profile.value.clone())
 }
 
-#[allow(clippy::flat_map_identity)]
 #[command]
 pub async fn set_selected_profile(device: String, id: String) -> Result<(), Error> {
 	let mut locks = acquire_locks_mut().await;
-	if !DEVICES.contains_key(&device) {
+	let Some(info) = DEVICES.get(&device).map(|device| device.value().clone()) else {
 		return Err(Error::new(format!("device {device} not found")));
-	}
+	};
 
 	let selected_profile = locks.device_stores.get_selected_profile(&device)?;
 
 	if selected_profile != id {
-		let old_profile = &locks.profile_stores.get_profile_store(&DEVICES.get(&device).unwrap(), &selected_profile)?.value;
-		for instance in old_profile.keys.iter().flatten().chain(&mut old_profile.sliders.iter().flatten()) {
-			if !matches!(instance.action.uuid.as_str(), "opendeck.multiaction" | "opendeck.toggleaction") {
-				let _ = crate::events::outbound::will_appear::will_disappear(instance, false).await;
-			} else {
-				for child in instance.children.as_ref().unwrap() {
-					let _ = crate::events::outbound::will_appear::will_disappear(child, false).await;
-				}
-			}
+		let old_profile = &locks.profile_stores.get_profile_store(&info, &selected_profile)?.value;
+		for instance in page_instances(old_profile, current_page(&device)) {
+			slot_will_disappear(instance).await;
 		}
 		let _ = crate::events::outbound::devices::clear_screen(device.clone()).await;
+		// Each profile is shown from its first page, rather than from any folder that was open.
+		reset_page(&device);
+		PREVIOUS_PROFILES.insert(device.clone(), selected_profile.clone());
 	}
 
 	// We must use the mutable version of get_profile_store in order to create the store if it does not exist.
-	let store = locks.profile_stores.get_profile_store_mut(&DEVICES.get(&device).unwrap(), &id).await?;
-	let new_profile = &store.value;
-	for instance in new_profile.keys.iter().flatten().chain(&mut new_profile.sliders.iter().flatten()) {
-		if !matches!(instance.action.uuid.as_str(), "opendeck.multiaction" | "opendeck.toggleaction") {
-			let _ = crate::events::outbound::will_appear::will_appear(instance).await;
-		} else {
-			for child in instance.children.as_ref().unwrap() {
-				let _ = crate::events::outbound::will_appear::will_appear(child).await;
-			}
-		}
-	}
+	let store = locks.profile_stores.get_profile_store_mut(&info, &id).await?;
+	let shown = page_instances(&store.value, current_page(&device)).cloned().collect::<Vec<_>>();
 	store.save()?;
 
 	locks.device_stores.set_selected_profile(&device, id)?;
+	// The instances of the new profile are drawn without holding the locks, so that other devices are not blocked while they are rendered.
+	drop(locks);
+	for instance in &shown {
+		slot_will_appear(instance).await;
+	}
 
 	Ok(())
 }
 
+/// Get the page of the selected profile shown on a device.
+#[command]
+pub fn get_page(device: &str) -> u8 {
+	current_page(device)
+}
+
+/// Show a page of the selected profile on a device, such as to open a folder from the frontend.
+#[command]
+pub async fn set_page(device: String, page: u8) -> Result<(), Error> {
+	Ok(show_page(&device, page).await?)
+}
+
+/// Add an empty page to the end of the selected profile of a device, returning its index.
+#[command]
+pub async fn add_page(device: String) -> Result<u8, Error> {
+	Ok(crate::pages::add_page(&device).await?)
+}
+
+#[command]
+pub async fn remove_page(device: String, page: u8) -> Result<(), Error> {
+	Ok(crate::pages::remove_page(&device, page).await?)
+}
+
 #[command]
 pub async fn delete_profile(device: String, profile: String) {
 	let mut profile_stores = PROFILE_STORES.write().await;
 	profile_stores.delete_profile(&device, &profile);
 }
 
+#[command]
+pub async fn get_known_devices() -> Result<Vec<crate::shared::DeviceInfo>, Error> {
+	Ok(crate::store::remap::known_devices().await?)
+}
+
+#[command]
+pub async fn remap_profile(
+	source_device: String,
+	source_profile: String,
+	destination_device: String,
+	destination_profile: String,
+	strategy: crate::store::remap::RemapStrategy,
+) -> Result<crate::store::remap::RemapResult, Error> {
+	Ok(crate::store::remap::remap_profile(&source_device, &source_profile, &destination_device, &destination_profile, strategy).await?)
+}
+
+#[derive(Clone, serde::Serialize)]
+struct SwitchProfileEvent {
+	device: String,
+	profile: String,
+}
+
+/// Switch the selected profile of a device from the backend, such as at the request of a plugin or the application watcher.
+/// The frontend is asked to perform the switch so that its view stays consistent, unless OpenDeck is running in headless mode.
+pub async fn request_switch_profile(device: String, profile: String) -> Result<(), anyhow::Error> {
+	if *crate::shared::HEADLESS {
+		set_selected_profile(device, profile).await?;
+	} else {
+		crate::APP_HANDLE.get().unwrap().emit_to("main", "switch_profile", SwitchProfileEvent { device, profile })?;
+	}
+	Ok(())
+}
+
+/// Get the profile before or after the selected profile of a device in its list of profiles, wrapping around at either end.
+async fn adjacent_profile(device: &str, forward: bool) -> Result<Option<String>, anyhow::Error> {
+	let profiles = get_device_profiles(device)?;
+	let selected_profile = DEVICE_STORES.write().await.get_selected_profile(device)?;
+	let Some(index) = profiles.iter().position(|profile| *profile == selected_profile) else {
+		return Ok(None);
+	};
+	let index = if forward { (index + 1) % profiles.len() } else { (index + profiles.len() - 1) % profiles.len() };
+	Ok(Some(profiles[index].clone()))
+}
+
+/// Handle a swipe gesture on a device, moving forwards or backwards through its profiles or the pages of its profile, depending on the settings.
+pub async fn swipe(device: &str, forward: bool) -> Result<(), anyhow::Error> {
+	if crate::idle::input(device).await {
+		return Ok(());
+	}
+	match crate::store::get_settings()?.value.swipeaction.as_str() {
+		"profile" => {}
+		"page" => return crate::pages::step_page(device, forward).await,
+		_ => return Ok(()),
+	}
+
+	let Some(profile) = adjacent_profile(device, forward).await? else { return Ok(()) };
+	request_switch_profile(device.to_owned(), profile).await
+}
+
+/// Switch the profile of a device as set in the settings of an instance of the profile switching action.
+/// The device defaults to that of the instance, and the profile is either chosen, the next or previous in the list, or the profile selected before the current one.
+pub async fn switch_profile_action(instance: &ActionInstance) -> Result<(), anyhow::Error> {
+	let settings = &instance.settings;
+	let device = settings["device"].as_str().filter(|device| !device.is_empty()).unwrap_or(&instance.context.device).to_owned();
+	if !DEVICES.contains_key(&device) {
+		return Err(anyhow!("device {device} is not connected"));
+	}
+
+	let profile = match settings["mode"].as_str().unwrap_or("profile") {
+		"next" => adjacent_profile(&device, true).await?,
+		"previous" => adjacent_profile(&device, false).await?,
+		"back" => previous_profile(&device),
+		_ => settings["profile"].as_str().map(str::to_owned),
+	};
+	let Some(profile) = profile else { return Ok(()) };
+	if !get_device_profiles(&device)?.contains(&profile) {
+		return Err(anyhow!("profile {profile} not found on device {device}"));
+	}
+
+	request_switch_profile(device, profile).await
+}
+
 pub async fn rerender_images(app: &AppHandle) -> Result<(), anyhow::Error> {
-	let window = app.get_webview_window("main").unwrap();
-	window.emit("rerender_images", ())?;
+	app.emit_to("main", "rerender_images", ())?;
 	Ok(())
 }
@@ -23,7 +23,6 @@ pub async fn set_settings(_app: tauri::AppHandle, settings: crate::store::Settin
 		false => _app.autolaunch().disable(),
 	};
 
-	crate::events::outbound::devices::set_brightness(settings.brightness).await?;
 	let mut store = match crate::store::get_settings() {
 		Ok(store) => store,
 		Err(error) => return Err(error.into()),
@@ -31,6 +30,8 @@ pub async fn set_settings(_app: tauri::AppHandle, settings: crate::store::Settin
 
 	store.value = settings;
 	store.save()?;
+
+	crate::brightness::settings_changed().await?;
 	Ok(())
 }
 
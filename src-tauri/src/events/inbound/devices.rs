@@ -1,3 +1,4 @@
+use crate::pages::page_instances;
 use crate::plugins::DEVICE_NAMESPACES;
 use crate::shared::DEVICES;
 use crate::store::profiles::get_device_profiles;
@@ -19,17 +20,35 @@ pub async fn register_device(uuid: &str, mut event: PayloadEvent<crate::shared::
 		}
 
 		event.payload.plugin = uuid.to_owned();
+		// Devices register the layout of their keys in their usual orientation, which is rotated to match how they are mounted.
+		event.payload.orientation = crate::store::profiles::DEVICE_STORES.read().await.read_config(&event.payload.id, |config| config.orientation)?;
+		if event.payload.orientation % 180 == 90 {
+			(event.payload.rows, event.payload.columns) = (event.payload.columns, event.payload.rows);
+		}
 		let _ = crate::events::outbound::devices::device_did_connect(&event.payload.id, (&event.payload).into()).await;
 		DEVICES.insert(event.payload.id.clone(), event.payload.clone());
 		crate::events::frontend::update_devices().await;
+		let info = event.payload.clone();
+		if let Err(error) = crate::store::profiles::DEVICE_STORES.write().await.update_config(&event.payload.id, |config| config.info = Some(info)) {
+			log::warn!("Failed to record metadata of device {}: {}", event.payload.id, error);
+		}
+		crate::idle::register_device(&event.payload.id);
+		if let Err(error) = crate::brightness::update_device(&event.payload.id).await {
+			log::warn!("Failed to set brightness of device {}: {}", event.payload.id, error);
+		}
 
 		let mut locks = crate::store::profiles::acquire_locks_mut().await;
+		crate::pages::reset_page(&event.payload.id);
 		let selected_profile = locks.device_stores.get_selected_profile(&event.payload.id)?;
-		let profile = locks.profile_stores.get_profile_store(&DEVICES.get(&event.payload.id).unwrap(), &selected_profile)?;
-		for instance in profile.value.keys.iter().flatten().chain(profile.value.sliders.iter().flatten()) {
-			let _ = crate::events::outbound::will_appear::will_appear(instance).await;
+		let profile = locks.profile_stores.get_profile_store(&event.payload, &selected_profile)?;
+		let shown = page_instances(&profile.value, 0).cloned().collect::<Vec<_>>();
+		drop(locks);
+		for instance in &shown {
+			crate::events::outbound::will_appear::slot_will_appear(instance).await;
 		}
 
+		crate::plugins::install_device_profiles(&event.payload.id).await;
+
 		Ok(())
 	} else {
 		Err(anyhow::anyhow!("plugin {uuid} is not registered for device namespace {}", &event.payload.id[..2]))
@@ -44,10 +63,11 @@ pub async fn deregister_device(uuid: &str, event: PayloadEvent<String>) -> Resul
 
 		let mut locks = crate::store::profiles::acquire_locks_mut().await;
 
+		let device = DEVICES.get(&event.payload).unwrap().value().clone();
 		let selected_profile = locks.device_stores.get_selected_profile(&event.payload)?;
-		let profile = locks.profile_stores.get_profile_store(&DEVICES.get(&event.payload).unwrap(), &selected_profile)?;
-		for instance in profile.value.keys.iter().flatten().chain(profile.value.sliders.iter().flatten()) {
-			let _ = crate::events::outbound::will_appear::will_disappear(instance, false).await;
+		let profile = locks.profile_stores.get_profile_store(&device, &selected_profile)?;
+		for instance in page_instances(&profile.value, crate::pages::current_page(&event.payload)) {
+			crate::events::outbound::will_appear::slot_will_disappear(instance).await;
 		}
 
 		if let Ok(profiles) = get_device_profiles(&event.payload) {
@@ -60,6 +80,10 @@ pub async fn deregister_device(uuid: &str, event: PayloadEvent<String>) -> Resul
 
 		let _ = crate::events::outbound::devices::device_did_disconnect(&event.payload).await;
 		DEVICES.remove(&event.payload);
+		crate::brightness::forget_device(&event.payload);
+		crate::idle::forget_device(&event.payload);
+		crate::events::outbound::encoder::forget_device(&event.payload);
+		crate::events::outbound::devices::forget_device(&event.payload).await;
 		crate::events::frontend::update_devices().await;
 
 		Ok(())
@@ -68,6 +92,32 @@ pub async fn deregister_device(uuid: &str, event: PayloadEvent<String>) -> Resul
 	}
 }
 
+/// Set the orientation of a device, registering it again so that plugins and the frontend see the rotated layout.
+pub async fn set_orientation(device: &str, orientation: u16) -> Result<(), anyhow::Error> {
+	if ![0, 90, 180, 270].contains(&orientation) {
+		return Err(anyhow::anyhow!("orientation must be 0, 90, 180 or 270 degrees"));
+	}
+	let Some(info) = DEVICES.get(device).map(|device| device.value().clone()) else {
+		return Err(anyhow::anyhow!("device {device} not found"));
+	};
+	if info.orientation == orientation {
+		return Ok(());
+	}
+
+	crate::store::profiles::DEVICE_STORES.write().await.update_config(device, |config| config.orientation = orientation)?;
+	let (rows, columns) = info.physical_size();
+	deregister_device(&info.plugin, PayloadEvent { payload: info.id.clone() }).await?;
+	let _ = crate::events::outbound::devices::clear_screen(info.id.clone()).await;
+	let plugin = info.plugin.clone();
+	register_device(
+		&plugin,
+		PayloadEvent {
+			payload: crate::shared::DeviceInfo { rows, columns, ..info },
+		},
+	)
+	.await
+}
+
 #[derive(Deserialize)]
 pub struct PressPayload {
 	pub device: String,
@@ -101,7 +151,36 @@ pub async fn encoder_up(event: PayloadEvent<PressPayload>) -> Result<(), anyhow:
 	crate::events::outbound::encoder::dial_press(&event.payload.device, "dialUp", event.payload.position).await
 }
 
-pub async fn rerender_images(_event: PayloadEvent<String>) -> Result<(), anyhow::Error> {
+#[derive(Deserialize)]
+pub struct TouchPayload {
+	pub device: String,
+	pub position: u8,
+	#[serde(rename = "tapPos", default)]
+	pub tap_pos: [u16; 2],
+	#[serde(default)]
+	pub hold: bool,
+}
+
+pub async fn touch_tap(event: PayloadEvent<TouchPayload>) -> Result<(), anyhow::Error> {
+	crate::events::outbound::encoder::touch_tap(&event.payload.device, event.payload.position, event.payload.tap_pos, event.payload.hold).await
+}
+
+pub async fn rerender_images(event: PayloadEvent<String>) -> Result<(), anyhow::Error> {
+	if let Some(device) = DEVICES.get(&event.payload).map(|device| device.value().clone()) {
+		let mut locks = crate::store::profiles::acquire_locks_mut().await;
+		let selected_profile = locks.device_stores.get_selected_profile(&device.id)?;
+		let profile = locks.profile_stores.get_profile_store(&device, &selected_profile)?;
+		let shown = page_instances(&profile.value, crate::pages::current_page(&device.id)).cloned().collect::<Vec<_>>();
+		drop(locks);
+		for instance in &shown {
+			if instance.context.controller == "Encoder" {
+				let _ = crate::render::layouts::update_touchscreen(instance).await;
+			} else {
+				let _ = crate::render::keys::update_key(instance, false).await;
+			}
+		}
+	}
+
 	crate::events::frontend::profiles::rerender_images(crate::APP_HANDLE.get().unwrap()).await?;
 	Ok(())
 }
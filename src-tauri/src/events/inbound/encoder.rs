@@ -0,0 +1,81 @@
+use super::ContextAndPayloadEvent;
+
+use crate::render::layouts::{update_touchscreen, FEEDBACK};
+use crate::shared::{ActionContext, ActionInstance, TriggerDescription};
+use crate::store::profiles::{acquire_locks_mut, get_instance_mut};
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tauri::Emitter;
+
+#[derive(Deserialize)]
+pub struct SetFeedbackLayoutPayload {
+	layout: String,
+}
+
+/// Get a copy of an instance and whether it is currently visible on its device.
+async fn get_instance_visibility(context: &ActionContext) -> Result<Option<(ActionInstance, bool)>, anyhow::Error> {
+	let mut locks = acquire_locks_mut().await;
+	let Some(instance) = get_instance_mut(context, &mut locks).await?.cloned() else { return Ok(None) };
+	let visible = locks.device_stores.get_selected_profile(&context.device)? == context.profile;
+	Ok(Some((instance, visible)))
+}
+
+pub async fn set_feedback(event: ContextAndPayloadEvent<Map<String, Value>>) -> Result<(), anyhow::Error> {
+	let Some((instance, visible)) = get_instance_visibility(&event.context).await? else {
+		return Ok(());
+	};
+
+	{
+		let mut feedback = FEEDBACK.write().await;
+		let values = &mut feedback.entry(event.context.clone()).or_default().values;
+		for (key, value) in event.payload {
+			// Objects are merged into existing objects so that plugins can update individual properties of a layout item.
+			if let (Some(Value::Object(existing)), Value::Object(new)) = (values.get_mut(&key), &value) {
+				existing.extend(new.clone());
+				continue;
+			}
+			values.insert(key, value);
+		}
+	}
+
+	if visible {
+		update_touchscreen(&instance).await?;
+	}
+
+	Ok(())
+}
+
+pub async fn set_feedback_layout(event: ContextAndPayloadEvent<SetFeedbackLayoutPayload>) -> Result<(), anyhow::Error> {
+	let Some((instance, visible)) = get_instance_visibility(&event.context).await? else {
+		return Ok(());
+	};
+
+	let layout = if event.payload.layout.starts_with('$') {
+		event.payload.layout
+	} else {
+		// Plugins may only use layouts from their own directory, which may be a symbolic link to where they are developed.
+		let plugin_dir = crate::shared::config_dir().join("plugins").join(&instance.action.plugin);
+		let path = plugin_dir.join(event.payload.layout.trim());
+		let (Ok(plugin_dir), Ok(path)) = (plugin_dir.canonicalize(), path.canonicalize()) else {
+			return Err(anyhow::anyhow!("layout {} not found", event.payload.layout));
+		};
+		if !path.starts_with(&plugin_dir) {
+			return Err(anyhow::anyhow!("layout {} is outside the directory of plugin {}", event.payload.layout, instance.action.plugin));
+		}
+		path.to_string_lossy().into_owned()
+	};
+	FEEDBACK.write().await.entry(event.context.clone()).or_default().layout = Some(layout);
+
+	if visible {
+		update_touchscreen(&instance).await?;
+	}
+
+	Ok(())
+}
+
+pub async fn set_trigger_description(event: ContextAndPayloadEvent<TriggerDescription>) -> Result<(), anyhow::Error> {
+	FEEDBACK.write().await.entry(event.context.clone()).or_default().trigger_description = Some(event.payload);
+	crate::APP_HANDLE.get().unwrap().emit_to("main", "trigger_description_changed", &event.context)?;
+	Ok(())
+}
@@ -1,6 +1,8 @@
 use super::{ContextAndPayloadEvent, ContextEvent, PayloadEvent};
 
-use tauri::{Emitter, Manager};
+use crate::render::Overlay;
+
+use tauri::Emitter;
 
 use serde::Deserialize;
 
@@ -36,25 +38,66 @@ pub async fn send_to_plugin(event: ContextAndPayloadEvent<serde_json::Value>) ->
 }
 
 pub async fn show_alert(event: ContextEvent) -> Result<(), anyhow::Error> {
+	crate::events::outbound::devices::show_overlay((&event.context).into(), Overlay::Alert).await?;
 	let app = crate::APP_HANDLE.get().unwrap();
-	app.get_webview_window("main").unwrap().emit("show_alert", event.context)?;
+	app.emit_to("main", "show_alert", event.context)?;
 	Ok(())
 }
 
 pub async fn show_ok(event: ContextEvent) -> Result<(), anyhow::Error> {
+	crate::events::outbound::devices::show_overlay((&event.context).into(), Overlay::Ok).await?;
 	let app = crate::APP_HANDLE.get().unwrap();
-	app.get_webview_window("main").unwrap().emit("show_ok", event.context)?;
+	app.emit_to("main", "show_ok", event.context)?;
 	Ok(())
 }
 
-#[derive(Clone, serde::Serialize, Deserialize)]
+#[derive(Deserialize)]
 pub struct SwitchProfileEvent {
 	device: String,
 	profile: String,
 }
 
-pub async fn switch_profile(event: SwitchProfileEvent) -> Result<(), anyhow::Error> {
-	let app_handle = crate::APP_HANDLE.get().unwrap();
-	app_handle.get_webview_window("main").unwrap().emit("switch_profile", event)?;
-	Ok(())
+pub async fn switch_profile(uuid: &str, event: SwitchProfileEvent) -> Result<(), anyhow::Error> {
+	// As with switchToProfile, plugins may only switch to the profiles bundled with them.
+	let bundled = event.profile.rsplit_once('/').is_some_and(|(_, name)| crate::plugins::bundled_profile_id(uuid, name) == event.profile);
+	if !bundled || !crate::store::profiles::get_device_profiles(&event.device)?.contains(&event.profile) {
+		return Err(anyhow::anyhow!("plugin {} does not have a profile {}", uuid, event.profile));
+	}
+	crate::events::frontend::profiles::request_switch_profile(event.device, event.profile).await
+}
+
+#[derive(Deserialize)]
+pub struct SwitchToProfilePayload {
+	/// The name of a profile bundled with the plugin, or nothing to switch back to the profile selected before the current one.
+	profile: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SwitchToProfileEvent {
+	pub context: String,
+	device: String,
+	payload: SwitchToProfilePayload,
+}
+
+pub async fn switch_to_profile(event: SwitchToProfileEvent) -> Result<(), anyhow::Error> {
+	let profile = match event.payload.profile {
+		// Plugins may only switch to the profiles bundled with them, which are namespaced under their UUID.
+		Some(name) => {
+			let profile = crate::plugins::bundled_profile_id(&event.context, &name);
+			if !crate::store::profiles::get_device_profiles(&event.device)?.contains(&profile) {
+				return Err(anyhow::anyhow!("plugin {} does not have a profile named {}", event.context, name));
+			}
+			profile
+		}
+		None => {
+			let Some(profile) = crate::events::frontend::profiles::previous_profile(&event.device) else {
+				return Ok(());
+			};
+			if !crate::store::profiles::get_device_profiles(&event.device)?.contains(&profile) {
+				return Ok(());
+			}
+			profile
+		}
+	};
+	crate::events::frontend::profiles::request_switch_profile(event.device, profile).await
 }
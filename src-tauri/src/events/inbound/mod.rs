@@ -1,4 +1,5 @@
 pub(crate) mod devices;
+mod encoder;
 mod misc;
 mod settings;
 mod states;
@@ -49,6 +50,7 @@ pub enum InboundEventType {
 	EncoderChange(PayloadEvent<devices::TicksPayload>),
 	EncoderDown(PayloadEvent<devices::PressPayload>),
 	EncoderUp(PayloadEvent<devices::PressPayload>),
+	TouchTap(PayloadEvent<devices::TouchPayload>),
 	SetSettings(ContextAndPayloadEvent<serde_json::Value>),
 	GetSettings(ContextEvent),
 	SetGlobalSettings(ContextAndPayloadEvent<serde_json::Value, String>),
@@ -58,11 +60,15 @@ pub enum InboundEventType {
 	SetTitle(ContextAndPayloadEvent<states::SetTitlePayload>),
 	SetImage(ContextAndPayloadEvent<states::SetImagePayload>),
 	SetState(ContextAndPayloadEvent<states::SetStatePayload>),
+	SetFeedback(ContextAndPayloadEvent<serde_json::Map<String, serde_json::Value>>),
+	SetFeedbackLayout(ContextAndPayloadEvent<encoder::SetFeedbackLayoutPayload>),
+	SetTriggerDescription(ContextAndPayloadEvent<crate::shared::TriggerDescription>),
 	ShowAlert(ContextEvent),
 	ShowOk(ContextEvent),
 	SendToPropertyInspector(ContextAndPayloadEvent<serde_json::Value>),
 	SendToPlugin(ContextAndPayloadEvent<serde_json::Value>),
 	SwitchProfile(misc::SwitchProfileEvent),
+	SwitchToProfile(misc::SwitchToProfileEvent),
 }
 
 pub async fn process_incoming_message(data: Result<Message, Error>, uuid: &str) {
@@ -78,6 +84,9 @@ pub async fn process_incoming_message(data: Result<Message, Error>, uuid: &str)
 			InboundEventType::SetTitle(event) => Some(&event.context),
 			InboundEventType::SetImage(event) => Some(&event.context),
 			InboundEventType::SetState(event) => Some(&event.context),
+			InboundEventType::SetFeedback(event) => Some(&event.context),
+			InboundEventType::SetFeedbackLayout(event) => Some(&event.context),
+			InboundEventType::SetTriggerDescription(event) => Some(&event.context),
 			InboundEventType::ShowAlert(event) => Some(&event.context),
 			InboundEventType::ShowOk(event) => Some(&event.context),
 			InboundEventType::SendToPropertyInspector(event) => Some(&event.context),
@@ -98,6 +107,10 @@ pub async fn process_incoming_message(data: Result<Message, Error>, uuid: &str)
 			if event.context != uuid {
 				return;
 			}
+		} else if let InboundEventType::SwitchToProfile(event) = &decoded {
+			if event.context != uuid {
+				return;
+			}
 		}
 
 		if let Err(error) = match decoded {
@@ -109,6 +122,7 @@ pub async fn process_incoming_message(data: Result<Message, Error>, uuid: &str)
 			InboundEventType::EncoderChange(event) => devices::encoder_change(event).await,
 			InboundEventType::EncoderDown(event) => devices::encoder_down(event).await,
 			InboundEventType::EncoderUp(event) => devices::encoder_up(event).await,
+			InboundEventType::TouchTap(event) => devices::touch_tap(event).await,
 			InboundEventType::SetSettings(event) => settings::set_settings(event, false).await,
 			InboundEventType::GetSettings(event) => settings::get_settings(event, false).await,
 			InboundEventType::SetGlobalSettings(event) => settings::set_global_settings(event, false).await,
@@ -118,11 +132,15 @@ pub async fn process_incoming_message(data: Result<Message, Error>, uuid: &str)
 			InboundEventType::SetTitle(event) => states::set_title(event).await,
 			InboundEventType::SetImage(event) => states::set_image(event).await,
 			InboundEventType::SetState(event) => states::set_state(event).await,
+			InboundEventType::SetFeedback(event) => encoder::set_feedback(event).await,
+			InboundEventType::SetFeedbackLayout(event) => encoder::set_feedback_layout(event).await,
+			InboundEventType::SetTriggerDescription(event) => encoder::set_trigger_description(event).await,
 			InboundEventType::ShowAlert(event) => misc::show_alert(event).await,
 			InboundEventType::ShowOk(event) => misc::show_ok(event).await,
 			InboundEventType::SendToPropertyInspector(event) => misc::send_to_property_inspector(event).await,
 			InboundEventType::SendToPlugin(_) => Ok(()),
-			InboundEventType::SwitchProfile(event) => misc::switch_profile(event).await,
+			InboundEventType::SwitchProfile(event) => misc::switch_profile(uuid, event).await,
+			InboundEventType::SwitchToProfile(event) => misc::switch_to_profile(event).await,
 		} {
 			if !error.to_string().contains("closed connection") {
 				warn!("Failed to process incoming event from plugin: {}", error);
@@ -33,9 +33,9 @@ pub async fn set_title(event: ContextAndPayloadEvent<SetTitlePayload>) -> Result
 				state.text = event.payload.title.clone().unwrap_or(instance.action.states[index].text.clone());
 			}
 		}
-		update_state(crate::APP_HANDLE.get().unwrap(), instance.context.clone(), &mut locks).await?;
+		save_profile(&event.context.device, &mut locks).await?;
+		update_state(crate::APP_HANDLE.get().unwrap(), event.context, locks).await?;
 	}
-	save_profile(&event.context.device, &mut locks).await?;
 
 	Ok(())
 }
@@ -67,9 +67,9 @@ pub async fn set_image(mut event: ContextAndPayloadEvent<SetImagePayload>) -> Re
 				state.image = event.payload.image.clone().unwrap_or(instance.action.states[index].image.clone());
 			}
 		}
-		update_state(crate::APP_HANDLE.get().unwrap(), instance.context.clone(), &mut locks).await?;
+		save_profile(&event.context.device, &mut locks).await?;
+		update_state(crate::APP_HANDLE.get().unwrap(), event.context, locks).await?;
 	}
-	save_profile(&event.context.device, &mut locks).await?;
 
 	Ok(())
 }
@@ -79,9 +79,9 @@ pub async fn set_state(event: ContextAndPayloadEvent<SetStatePayload>) -> Result
 
 	if let Some(instance) = get_instance_mut(&event.context, &mut locks).await? {
 		instance.current_state = event.payload.state;
-		update_state(crate::APP_HANDLE.get().unwrap(), instance.context.clone(), &mut locks).await?;
+		save_profile(&event.context.device, &mut locks).await?;
+		update_state(crate::APP_HANDLE.get().unwrap(), event.context, locks).await?;
 	}
-	save_profile(&event.context.device, &mut locks).await?;
 
 	Ok(())
 }
@@ -0,0 +1,133 @@
+use super::send_to_plugin;
+
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct DidReceiveDeepLinkPayload {
+	url: String,
+}
+
+#[derive(Serialize)]
+struct DidReceiveDeepLinkEvent {
+	event: &'static str,
+	payload: DidReceiveDeepLinkPayload,
+}
+
+/// Split a deep link of the form `opendeck://plugins/message/<uuid>/<path>` into the plugin it is addressed to and the URL it receives.
+/// Returns `None` for other deep links.
+fn parse(url: &str) -> Result<Option<(String, String)>, anyhow::Error> {
+	let Some(message) = url.trim().strip_prefix("opendeck://plugins/message/") else {
+		return Ok(None);
+	};
+	let (uuid, path) = match message.find(['/', '?', '#']) {
+		Some(index) => message.split_at(index),
+		None => (message, ""),
+	};
+	let uuid = urlencoding::decode(uuid)?;
+	if uuid.is_empty() {
+		return Err(anyhow::anyhow!("deep link {url} does not specify a plugin"));
+	}
+	// The plugin is looked up in the plugins directory, so it must not be able to name anything outside of it.
+	if uuid.contains(['/', '\\']) || uuid.contains("..") {
+		return Err(anyhow::anyhow!("deep link {url} does not specify a valid plugin"));
+	}
+
+	let plugin = if uuid.to_lowercase().ends_with(".sdplugin") {
+		uuid.into_owned()
+	} else {
+		format!("{uuid}.sdPlugin")
+	};
+	let path = if path.starts_with('/') { path.to_owned() } else { format!("/{path}") };
+	Ok(Some((plugin, path)))
+}
+
+/// Deliver a deep link of the form `opendeck://plugins/message/<uuid>/<path>` to the plugin it is addressed to.
+/// The plugin receives the URL with the prefix omitted, e.g. `/<path>`. Other deep links are ignored.
+pub async fn did_receive_deep_link(url: &str) -> Result<(), anyhow::Error> {
+	let Some((plugin, path)) = parse(url)? else { return Ok(()) };
+	if !crate::shared::config_dir().join("plugins").join(&plugin).exists() {
+		return Err(anyhow::anyhow!("plugin {plugin} is not installed"));
+	}
+
+	log::debug!("Delivering deep link {} to plugin {}", url, plugin);
+	send_to_plugin(
+		&plugin,
+		&DidReceiveDeepLinkEvent {
+			event: "didReceiveDeepLink",
+			payload: DidReceiveDeepLinkPayload { url: path },
+		},
+	)
+	.await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parsed(url: &str) -> Option<(String, String)> {
+		parse(url).unwrap()
+	}
+
+	#[test]
+	fn bare_uuid() {
+		assert_eq!(
+			parsed("opendeck://plugins/message/com.example.plugin"),
+			Some(("com.example.plugin.sdPlugin".to_owned(), "/".to_owned()))
+		);
+		assert_eq!(
+			parsed(" opendeck://plugins/message/com.example.plugin/some/path?query=1 "),
+			Some(("com.example.plugin.sdPlugin".to_owned(), "/some/path?query=1".to_owned()))
+		);
+	}
+
+	#[test]
+	fn plugin_suffix() {
+		assert_eq!(
+			parsed("opendeck://plugins/message/com.example.plugin.sdPlugin/path"),
+			Some(("com.example.plugin.sdPlugin".to_owned(), "/path".to_owned()))
+		);
+		// The suffix is kept as it was written, as that is how the directory of the plugin is named.
+		assert_eq!(
+			parsed("opendeck://plugins/message/com.example.plugin.SDPLUGIN"),
+			Some(("com.example.plugin.SDPLUGIN".to_owned(), "/".to_owned()))
+		);
+		assert_eq!(
+			parsed("opendeck://plugins/message/com%2Eexample%2Eplugin"),
+			Some(("com.example.plugin.sdPlugin".to_owned(), "/".to_owned()))
+		);
+	}
+
+	#[test]
+	fn query_and_fragment_only() {
+		assert_eq!(
+			parsed("opendeck://plugins/message/com.example.plugin?query=1"),
+			Some(("com.example.plugin.sdPlugin".to_owned(), "/?query=1".to_owned()))
+		);
+		assert_eq!(
+			parsed("opendeck://plugins/message/com.example.plugin#fragment"),
+			Some(("com.example.plugin.sdPlugin".to_owned(), "/#fragment".to_owned()))
+		);
+	}
+
+	#[test]
+	fn invalid_uuids() {
+		for url in [
+			"opendeck://plugins/message/",
+			"opendeck://plugins/message//path",
+			"opendeck://plugins/message/?query=1",
+			"opendeck://plugins/message/..",
+			"opendeck://plugins/message/%2E%2E%2Fconfig",
+			"opendeck://plugins/message/..%2F..%2Fexample",
+			"opendeck://plugins/message/com.example%5C..%5Cplugin",
+		] {
+			assert!(parse(url).is_err(), "{url} was accepted");
+		}
+	}
+
+	#[test]
+	fn other_links() {
+		assert_eq!(parsed("opendeck://plugins/install/com.example.plugin"), None);
+		assert_eq!(parsed("https://example.com/plugins/message/com.example.plugin"), None);
+		assert_eq!(parsed(""), None);
+	}
+}
@@ -1,8 +1,16 @@
 use super::{send_to_all_plugins, send_to_plugin};
 
 use crate::plugins::{info_param::DeviceInfo, DEVICE_NAMESPACES};
+use crate::render::Overlay;
+use crate::shared::{Context, DEVICES};
 
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use image::DynamicImage;
+use once_cell::sync::Lazy;
 use serde::Serialize;
+use tokio::sync::Mutex;
 
 #[derive(Serialize)]
 #[allow(non_snake_case)]
@@ -43,7 +51,127 @@ struct SetImageEvent {
 	image: Option<String>,
 }
 
-pub async fn update_image(context: crate::shared::Context, image: Option<String>) -> Result<(), anyhow::Error> {
+/// The images most recently sent to each key, so that they can be restored after an overlay is shown.
+static KEY_IMAGES: Lazy<Mutex<HashMap<Context, Option<String>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+/// The images most recently sent to each touchscreen segment, so that they can be restored after an overlay is shown.
+static TOUCHSCREEN_IMAGES: Lazy<Mutex<HashMap<Context, Option<DynamicImage>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+/// The overlays currently shown over each key or touchscreen segment, alongside a number identifying the request that showed them.
+static OVERLAYS: Lazy<Mutex<HashMap<Context, (Overlay, u64)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static OVERLAY_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Forget the image and overlay recorded for a key or touchscreen segment, such as when the instance in it is removed.
+pub async fn forget_images(context: &Context) {
+	KEY_IMAGES.lock().await.remove(context);
+	TOUCHSCREEN_IMAGES.lock().await.remove(context);
+	OVERLAYS.lock().await.remove(context);
+}
+
+/// Forget the images and overlays recorded for every key and touchscreen segment of a device, as they are no longer shown.
+pub async fn forget_device(device: &str) {
+	KEY_IMAGES.lock().await.retain(|context, _| context.device != device);
+	TOUCHSCREEN_IMAGES.lock().await.retain(|context, _| context.device != device);
+	OVERLAYS.lock().await.retain(|context, _| context.device != device);
+}
+
+/// Show an overlay over a key or touchscreen segment for the duration configured in settings, and then restore its previous image.
+pub async fn show_overlay(context: Context, overlay: Overlay) -> Result<(), anyhow::Error> {
+	let id = OVERLAY_COUNTER.fetch_add(1, Ordering::Relaxed);
+	OVERLAYS.lock().await.insert(context.clone(), (overlay, id));
+	redraw(context.clone()).await?;
+
+	let duration = crate::store::get_settings()?.value.feedbackduration;
+	tokio::spawn(async move {
+		tokio::time::sleep(std::time::Duration::from_millis(duration)).await;
+		let mut overlays = OVERLAYS.lock().await;
+		// A newer overlay may have replaced this one, in which case it is responsible for restoring the image.
+		if overlays.get(&context).is_some_and(|(_, current)| *current == id) {
+			overlays.remove(&context);
+			drop(overlays);
+			if let Err(error) = redraw(context).await {
+				log::warn!("Failed to restore image after overlay: {}", error);
+			}
+		}
+	});
+
+	Ok(())
+}
+
+/// Resend the most recent image of a key or touchscreen segment if its profile is still selected.
+async fn redraw(context: Context) -> Result<(), anyhow::Error> {
+	if crate::store::profiles::DEVICE_STORES.write().await.get_selected_profile(&context.device)? != context.profile {
+		return Ok(());
+	}
+	if context.controller == "Encoder" {
+		let image = TOUCHSCREEN_IMAGES.lock().await.get(&context).cloned().flatten();
+		update_touchscreen(context, image).await
+	} else {
+		let image = KEY_IMAGES.lock().await.get(&context).cloned().flatten();
+		update_image(context, image).await
+	}
+}
+
+pub async fn update_image(context: Context, image: Option<String>) -> Result<(), anyhow::Error> {
+	if context.controller == "Encoder" {
+		let image = match image {
+			Some(image) => Some(crate::render::load_image(&image)?),
+			None => None,
+		};
+		return update_touchscreen(context, image).await;
+	}
+
+	KEY_IMAGES.lock().await.insert(context.clone(), image.clone());
+	let image = match OVERLAYS.lock().await.get(&context) {
+		Some((overlay, _)) => {
+			let base = match &image {
+				Some(image) => Some(crate::render::load_image(image)?),
+				None => None,
+			};
+			Some(crate::render::to_data_url(&crate::render::apply_overlay(base.as_ref(), *overlay))?)
+		}
+		None => image,
+	};
+
+	// The keys of an idle device showing a screensaver are redrawn from their most recent images when it wakes.
+	if crate::idle::showing_screensaver(&context.device) {
+		return Ok(());
+	}
+	// Keys on pages that are not shown are drawn when their page is opened.
+	let Some(position) = crate::pages::device_slot(&context) else { return Ok(()) };
+	write_key_image(Context { position, ..context }, image).await
+}
+
+/// Redraw every key of a device from its most recent image, such as after a screensaver covered them.
+pub async fn redraw_keys(device: &str) -> Result<(), anyhow::Error> {
+	let Some((rows, columns)) = DEVICES.get(device).map(|device| (device.rows, device.columns)) else {
+		return Ok(());
+	};
+	let profile = crate::store::profiles::DEVICE_STORES.write().await.get_selected_profile(device)?;
+	for slot in (0..rows as u16 * columns as u16).map_while(|slot| u8::try_from(slot).ok()) {
+		let context = Context {
+			device: device.to_owned(),
+			profile: profile.clone(),
+			controller: "Keypad".to_owned(),
+			position: crate::pages::profile_position(device, "Keypad", slot),
+		};
+		let image = KEY_IMAGES.lock().await.get(&context).cloned().flatten();
+		update_image(context, image).await?;
+	}
+	Ok(())
+}
+
+/// Write an image to a key of a device, at its slot on the device, without recording it as the image of the key.
+pub async fn write_key_image(context: Context, image: Option<String>) -> Result<(), anyhow::Error> {
+	// Keys are drawn in the layout as the device is mounted, which is rotated back to the device's usual orientation to be written.
+	let (position, orientation) = DEVICES
+		.get(&context.device)
+		.map_or((Some(context.position), 0), |device| (device.physical_position(context.position), device.orientation));
+	let Some(position) = position else { return Ok(()) };
+	let image = match image {
+		Some(image) if orientation != 0 => Some(crate::render::rotate_image(&image, orientation)?),
+		image => image,
+	};
+	let context = Context { position, ..context };
+
 	if let Some(plugin) = DEVICE_NAMESPACES.read().await.get(&context.device[..2]) {
 		send_to_plugin(
 			plugin,
@@ -57,12 +185,48 @@ pub async fn update_image(context: crate::shared::Context, image: Option<String>
 		.await?;
 	} else if context.device.starts_with("sd-") {
 		crate::elgato::update_image(&context, image.as_deref()).await?;
+	} else if context.device.starts_with("vd-") {
+		crate::virtual_devices::update_image(&context.device, context.position, image.as_deref());
+	}
+
+	Ok(())
+}
+
+pub async fn update_touchscreen(context: Context, image: Option<DynamicImage>) -> Result<(), anyhow::Error> {
+	TOUCHSCREEN_IMAGES.lock().await.insert(context.clone(), image.clone());
+	let image = match OVERLAYS.lock().await.get(&context) {
+		Some((overlay, _)) => Some(crate::render::apply_overlay(image.as_ref(), *overlay)),
+		None => image,
+	};
+	let Some(position) = crate::pages::device_slot(&context) else { return Ok(()) };
+	let context = Context { position, ..context };
+
+	if let Some(plugin) = DEVICE_NAMESPACES.read().await.get(&context.device[..2]) {
+		let image = match image {
+			Some(image) => Some(crate::render::to_data_url(&image)?),
+			None => None,
+		};
+		send_to_plugin(
+			plugin,
+			&SetImageEvent {
+				event: "setTouchscreenImage",
+				device: context.device,
+				position: Some(context.position),
+				image,
+			},
+		)
+		.await?;
+	} else if context.device.starts_with("sd-") {
+		crate::elgato::update_touchscreen(&context, image).await?;
 	}
 
 	Ok(())
 }
 
 pub async fn clear_screen(device: String) -> Result<(), anyhow::Error> {
+	// The keys shown next are drawn afresh as they appear, so the images of those shown until now are not restored.
+	forget_device(&device).await;
+
 	if let Some(plugin) = DEVICE_NAMESPACES.read().await.get(&device[..2]) {
 		send_to_plugin(
 			plugin,
@@ -76,6 +240,8 @@ pub async fn clear_screen(device: String) -> Result<(), anyhow::Error> {
 		.await?;
 	} else if device.starts_with("sd-") {
 		crate::elgato::clear_screen(&device).await?;
+	} else if device.starts_with("vd-") {
+		crate::virtual_devices::clear_screen(&device);
 	}
 
 	Ok(())
@@ -88,22 +254,23 @@ struct SetBrightnessEvent {
 	brightness: u8,
 }
 
-pub async fn set_brightness(brightness: u8) -> Result<(), anyhow::Error> {
-	let namespaces = DEVICE_NAMESPACES.read().await;
-	for device in crate::shared::DEVICES.iter() {
-		if let Some(plugin) = namespaces.get(&device.id[..2]) {
-			send_to_plugin(
-				plugin,
-				&SetBrightnessEvent {
-					event: "setBrightness",
-					device: device.id.clone(),
-					brightness,
-				},
-			)
-			.await?;
-		}
+pub async fn set_brightness(device: &str, brightness: u8) -> Result<(), anyhow::Error> {
+	if !DEVICES.contains_key(device) {
+		return Err(anyhow::anyhow!("device {device} not found"));
+	}
+	if let Some(plugin) = DEVICE_NAMESPACES.read().await.get(&device[..2]) {
+		send_to_plugin(
+			plugin,
+			&SetBrightnessEvent {
+				event: "setBrightness",
+				device: device.to_owned(),
+				brightness,
+			},
+		)
+		.await?;
+	} else if device.starts_with("sd-") {
+		crate::elgato::set_brightness(device, brightness).await?;
 	}
-	crate::elgato::set_brightness(brightness).await;
 
 	Ok(())
 }
@@ -3,6 +3,10 @@ use super::{send_to_plugin, Coordinates};
 use crate::shared::ActionContext;
 use crate::store::profiles::{acquire_locks_mut, get_instance_mut};
 
+use std::time::{Duration, Instant};
+
+use dashmap::{DashMap, DashSet};
+use once_cell::sync::Lazy;
 use serde::Serialize;
 
 #[derive(Serialize)]
@@ -38,67 +42,282 @@ struct DialPressEvent {
 	payload: DialPressPayload,
 }
 
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct TouchTapPayload {
+	controller: &'static str,
+	settings: serde_json::Value,
+	coordinates: Coordinates,
+	tapPos: [u16; 2],
+	hold: bool,
+}
+
+#[derive(Serialize)]
+struct TouchTapEvent {
+	event: &'static str,
+	action: String,
+	context: ActionContext,
+	device: String,
+	payload: TouchTapPayload,
+}
+
+/// The dials of each device that are pressed down, which are reported as pressed while they are rotated.
+static PRESSED: Lazy<DashSet<(String, u8)>> = Lazy::new(DashSet::new);
+/// When each dial was last rotated, which rotations are accelerated by how soon after it they come.
+static LAST_ROTATED: Lazy<DashMap<(String, u8), Instant>> = Lazy::new(DashMap::new);
+/// The ticks gathered for each instance on a dial while waiting to send them together in one event, alongside whether the dial was pressed when they started.
+static PENDING_TICKS: Lazy<DashMap<ActionContext, (i16, bool)>> = Lazy::new(DashMap::new);
+
+/// Forget the state of the dials of a device once it has disconnected.
+pub fn forget_device(device: &str) {
+	PRESSED.retain(|(id, _)| id != device);
+	LAST_ROTATED.retain(|(id, _), _| id != device);
+	PENDING_TICKS.retain(|context, _| context.device != device);
+}
+
+/// Get how much to multiply ticks by from how long it has been since the dial was last rotated, if it has been rotated before.
+fn acceleration(interval: Option<Duration>) -> i16 {
+	match interval {
+		Some(interval) if interval < Duration::from_millis(25) => 4,
+		Some(interval) if interval < Duration::from_millis(60) => 2,
+		_ => 1,
+	}
+}
+
+/// Multiply ticks by how quickly the dial is being rotated, from how soon after its last rotation they come at `now`.
+fn accelerate(dial: &(String, u8), ticks: i16, now: Instant) -> i16 {
+	let interval = LAST_ROTATED.insert(dial.clone(), now).map(|last| now.duration_since(last));
+	ticks.saturating_mul(acceleration(interval))
+}
+
+/// Send dialRotate to an instance on a dial, if it is still there.
+async fn send_dial_rotate(context: &ActionContext, ticks: i16, pressed: bool) -> Result<(), anyhow::Error> {
+	let mut locks = acquire_locks_mut().await;
+	let Some(instance) = get_instance_mut(context, &mut locks).await? else { return Ok(()) };
+
+	send_to_plugin(
+		&instance.action.plugin,
+		&DialRotateEvent {
+			event: "dialRotate",
+			action: instance.action.uuid.clone(),
+			context: instance.context.clone(),
+			device: instance.context.device.clone(),
+			payload: DialRotatePayload {
+				settings: instance.settings.clone(),
+				coordinates: Coordinates::new(&instance.context),
+				ticks,
+				pressed,
+			},
+		},
+	)
+	.await
+}
+
 pub async fn dial_rotate(device: &str, index: u8, ticks: i16) -> Result<(), anyhow::Error> {
+	if crate::idle::input(device).await {
+		return Ok(());
+	}
 	let mut locks = acquire_locks_mut().await;
 	let selected_profile = locks.device_stores.get_selected_profile(device)?;
 	let context = ActionContext {
 		device: device.to_owned(),
 		profile: selected_profile.to_owned(),
 		controller: "Encoder".to_owned(),
-		position: index,
+		position: crate::pages::profile_position(device, "Encoder", index),
 		index: 0,
 	};
 	let Some(instance) = get_instance_mut(&context, &mut locks).await? else { return Ok(()) };
+	if instance.action.uuid == "opendeck.pagedial" {
+		drop(locks);
+		return crate::pages::step_page(device, ticks > 0).await;
+	}
+	if instance.action.uuid == "opendeck.brightness" {
+		let instance = instance.clone();
+		drop(locks);
+		return crate::brightness::nudge_from_action(&instance, ticks).await;
+	}
+	let options = instance.dial.unwrap_or_default();
+	drop(locks);
+
+	let dial = (device.to_owned(), index);
+	let pressed = PRESSED.contains(&dial);
+	let ticks = if options.acceleration { accelerate(&dial, ticks, Instant::now()) } else { ticks };
+	if options.coalesce == 0 {
+		return send_dial_rotate(&context, ticks, pressed).await;
+	}
+
+	// The first rotation of a burst waits for the rest of it, and later rotations are added to the ticks it sends.
+	// The burst is sent to the instance and with the pressed state it started with, even if the page or the dial's press changes before it is sent.
+	let mut first = false;
+	PENDING_TICKS
+		.entry(context.clone())
+		.and_modify(|(pending, _)| *pending = pending.saturating_add(ticks))
+		.or_insert_with(|| {
+			first = true;
+			(ticks, pressed)
+		});
+	if first {
+		let window = Duration::from_millis(options.coalesce as u64);
+		tokio::spawn(async move {
+			tokio::time::sleep(window).await;
+			let Some((_, (ticks, pressed))) = PENDING_TICKS.remove(&context) else { return };
+			if ticks == 0 {
+				return;
+			}
+			if let Err(error) = send_dial_rotate(&context, ticks, pressed).await {
+				log::warn!("Failed to send rotation of dial {} of {}: {}", index, context.device, error);
+			}
+		});
+	}
+	Ok(())
+}
+
+pub async fn dial_press(device: &str, event: &'static str, index: u8) -> Result<(), anyhow::Error> {
+	// Presses are tracked even when they only wake the device, so that rotating a dial that is still held reports it as pressed.
+	if event == "dialDown" {
+		PRESSED.insert((device.to_owned(), index));
+	} else {
+		PRESSED.remove(&(device.to_owned(), index));
+	}
+	let woke = match event {
+		"dialDown" => crate::idle::press(device, "Encoder", index).await,
+		_ => crate::idle::release(device, "Encoder", index).await,
+	};
+	if woke {
+		return Ok(());
+	}
+	let mut locks = acquire_locks_mut().await;
+	let selected_profile = locks.device_stores.get_selected_profile(device)?;
+	let context = ActionContext {
+		device: device.to_owned(),
+		profile: selected_profile.to_owned(),
+		controller: "Encoder".to_owned(),
+		position: crate::pages::profile_position(device, "Encoder", index),
+		index: 0,
+	};
+	let Some(instance) = get_instance_mut(&context, &mut locks).await? else { return Ok(()) };
+	if instance.action.uuid == "opendeck.pagedial" {
+		// Pressing the dial returns to the first page.
+		drop(locks);
+		return if event == "dialUp" { crate::pages::show_page(device, 0).await } else { Ok(()) };
+	}
+	if instance.action.uuid == "opendeck.brightness" {
+		// Pressing the dial discards the adjustments made by turning it.
+		drop(locks);
+		return if event == "dialUp" { crate::brightness::reset_nudge(device).await } else { Ok(()) };
+	}
 
 	send_to_plugin(
 		&instance.action.plugin,
-		&DialRotateEvent {
-			event: "dialRotate",
+		&DialPressEvent {
+			event,
 			action: instance.action.uuid.clone(),
 			context: instance.context.clone(),
 			device: instance.context.device.clone(),
-			payload: DialRotatePayload {
+			payload: DialPressPayload {
+				controller: "Encoder",
 				settings: instance.settings.clone(),
-				coordinates: Coordinates {
-					row: instance.context.position / 3,
-					column: instance.context.position % 3,
-				},
-				ticks,
-				pressed: false,
+				coordinates: Coordinates::new(&instance.context),
 			},
 		},
 	)
 	.await
 }
 
-pub async fn dial_press(device: &str, event: &'static str, index: u8) -> Result<(), anyhow::Error> {
+pub async fn touch_tap(device: &str, index: u8, position: [u16; 2], hold: bool) -> Result<(), anyhow::Error> {
+	if crate::idle::input(device).await {
+		return Ok(());
+	}
 	let mut locks = acquire_locks_mut().await;
 	let selected_profile = locks.device_stores.get_selected_profile(device)?;
 	let context = ActionContext {
 		device: device.to_owned(),
 		profile: selected_profile.to_owned(),
 		controller: "Encoder".to_owned(),
-		position: index,
+		position: crate::pages::profile_position(device, "Encoder", index),
 		index: 0,
 	};
 	let Some(instance) = get_instance_mut(&context, &mut locks).await? else { return Ok(()) };
 
 	send_to_plugin(
 		&instance.action.plugin,
-		&DialPressEvent {
-			event,
+		&TouchTapEvent {
+			event: "touchTap",
 			action: instance.action.uuid.clone(),
 			context: instance.context.clone(),
 			device: instance.context.device.clone(),
-			payload: DialPressPayload {
+			payload: TouchTapPayload {
 				controller: "Encoder",
 				settings: instance.settings.clone(),
-				coordinates: Coordinates {
-					row: instance.context.position / 3,
-					column: instance.context.position % 3,
-				},
+				coordinates: Coordinates::new(&instance.context),
+				tapPos: position,
+				hold,
 			},
 		},
 	)
 	.await
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn acceleration_by_interval() {
+		assert_eq!(acceleration(None), 1);
+		assert_eq!(acceleration(Some(Duration::ZERO)), 4);
+		assert_eq!(acceleration(Some(Duration::from_millis(24))), 4);
+		assert_eq!(acceleration(Some(Duration::from_millis(25))), 2);
+		assert_eq!(acceleration(Some(Duration::from_millis(59))), 2);
+		assert_eq!(acceleration(Some(Duration::from_millis(60))), 1);
+		assert_eq!(acceleration(Some(Duration::from_secs(5))), 1);
+	}
+
+	#[test]
+	fn accelerate_quick_rotations() {
+		let dial = ("sd-accelerate".to_owned(), 0);
+		let start = Instant::now();
+		let at = |millis| start + Duration::from_millis(millis);
+		// The first rotation of a dial has nothing to be quicker than.
+		assert_eq!(accelerate(&dial, 3, at(0)), 3);
+		assert_eq!(accelerate(&dial, 3, at(10)), 12);
+		assert_eq!(accelerate(&dial, -2, at(34)), -8);
+		assert_eq!(accelerate(&dial, 3, at(74)), 6);
+		assert_eq!(accelerate(&dial, i16::MAX, at(75)), i16::MAX);
+		assert_eq!(accelerate(&dial, i16::MIN, at(76)), i16::MIN);
+
+		// Other dials are accelerated separately.
+		assert_eq!(accelerate(&("sd-accelerate".to_owned(), 1), 3, at(77)), 3);
+	}
+
+	#[test]
+	fn accelerate_after_pause() {
+		let dial = ("sd-accelerate-pause".to_owned(), 0);
+		let start = Instant::now();
+		assert_eq!(accelerate(&dial, 1, start), 1);
+		assert_eq!(accelerate(&dial, 1, start + Duration::from_millis(60)), 1);
+		assert_eq!(accelerate(&dial, 1, start + Duration::from_millis(1060)), 1);
+	}
+
+	#[test]
+	fn forget_device_forgets_dials() {
+		let dial = ("sd-accelerate-forget".to_owned(), 0);
+		let context = ActionContext {
+			device: dial.0.clone(),
+			profile: "Default".to_owned(),
+			controller: "Encoder".to_owned(),
+			position: 0,
+			index: 0,
+		};
+		let start = Instant::now();
+		accelerate(&dial, 1, start);
+		PRESSED.insert(dial.clone());
+		PENDING_TICKS.insert(context.clone(), (1, false));
+
+		forget_device(&dial.0);
+		assert!(!LAST_ROTATED.contains_key(&dial));
+		assert!(!PRESSED.contains(&dial));
+		assert!(!PENDING_TICKS.contains_key(&context));
+		assert_eq!(accelerate(&dial, 1, start + Duration::from_millis(1)), 1);
+	}
+}
@@ -1,9 +1,7 @@
-use std::time::Duration;
-
 use super::{send_to_plugin, GenericInstancePayload};
 
 use crate::events::frontend::instances::{key_moved, update_state};
-use crate::shared::{ActionContext, Context};
+use crate::shared::{ActionContext, ActionInstance, Context};
 use crate::store::profiles::{acquire_locks_mut, get_slot_mut, save_profile};
 
 use serde::Serialize;
@@ -17,60 +15,78 @@ struct KeyEvent {
 	payload: GenericInstancePayload,
 }
 
+/// Send a keyDown or keyUp event to an instance, advancing its state before keyUp unless the action disables automatic states.
+pub async fn send_key_event(instance: &mut ActionInstance, down: bool) -> Result<(), anyhow::Error> {
+	if !down && instance.states.len() == 2 && !instance.action.disable_automatic_states {
+		instance.current_state = (instance.current_state + 1) % (instance.states.len() as u16);
+	}
+	send_to_plugin(
+		&instance.action.plugin,
+		&KeyEvent {
+			event: if down { "keyDown" } else { "keyUp" },
+			action: instance.action.uuid.clone(),
+			context: instance.context.clone(),
+			device: instance.context.device.clone(),
+			payload: GenericInstancePayload::new(instance),
+		},
+	)
+	.await
+}
+
 pub async fn key_down(device: &str, key: u8) -> Result<(), anyhow::Error> {
+	// Devices report the positions of keys in their usual orientation, rather than in the layout as they are mounted.
+	// Keys reported outside of the device's layout, such as by a misbehaving plugin, are ignored.
+	let Some(key) = crate::shared::DEVICES.get(device).map_or(Some(key), |device| device.logical_position(key)) else {
+		return Ok(());
+	};
+	if crate::idle::press(device, "Keypad", key).await {
+		return Ok(());
+	}
 	let mut locks = acquire_locks_mut().await;
 	let selected_profile = locks.device_stores.get_selected_profile(device)?;
 	let context = Context {
 		device: device.to_owned(),
 		profile: selected_profile.to_owned(),
 		controller: "Keypad".to_owned(),
-		position: key,
+		position: crate::pages::profile_position(device, "Keypad", key),
 	};
 
-	let _ = key_moved(crate::APP_HANDLE.get().unwrap(), context.clone(), true).await;
+	// The frontend is not running in tests, which drive keys without the app.
+	if let Some(app) = crate::APP_HANDLE.get() {
+		let _ = key_moved(app, context.clone(), true).await;
+	}
 
 	let Some(instance) = get_slot_mut(&context, &mut locks).await? else { return Ok(()) };
-	if instance.action.uuid == "opendeck.multiaction" {
-		for child in instance.children.as_mut().unwrap() {
-			send_to_plugin(
-				&child.action.plugin,
-				&KeyEvent {
-					event: "keyDown",
-					action: child.action.uuid.clone(),
-					context: child.context.clone(),
-					device: child.context.device.clone(),
-					payload: GenericInstancePayload::new(child),
-				},
-			)
-			.await?;
-
-			tokio::time::sleep(Duration::from_millis(100)).await;
-
-			if child.states.len() == 2 && !child.action.disable_automatic_states {
-				child.current_state = (child.current_state + 1) % (child.states.len() as u16);
-			}
-
-			send_to_plugin(
-				&child.action.plugin,
-				&KeyEvent {
-					event: "keyUp",
-					action: child.action.uuid.clone(),
-					context: child.context.clone(),
-					device: child.context.device.clone(),
-					payload: GenericInstancePayload::new(child),
-				},
-			)
-			.await?;
-
-			tokio::time::sleep(Duration::from_millis(100)).await;
-		}
-
-		let contexts = instance.children.as_ref().unwrap().iter().map(|x| x.context.clone()).collect::<Vec<_>>();
-		for child in contexts {
-			let _ = update_state(crate::APP_HANDLE.get().unwrap(), child, &mut locks).await;
+	// The key is drawn pressed without holding the locks, so that other keys and devices are not blocked while it is rendered.
+	let rendered = instance.clone();
+	drop(locks);
+	let _ = crate::render::keys::update_key(&rendered, true).await;
+	let mut locks = acquire_locks_mut().await;
+	let Some(instance) = get_slot_mut(&context, &mut locks).await? else { return Ok(()) };
+	if instance.action.uuid == "opendeck.hotkey" {
+		// The hotkey action is only available on Linux, but profiles containing it may have been copied from there.
+		#[cfg(target_os = "linux")]
+		{
+			let instance = instance.clone();
+			drop(locks);
+			crate::hotkeys::press(&instance).await?;
 		}
-
-		save_profile(device, &mut locks).await?;
+		return Ok(());
+	} else if crate::pages::PAGE_ACTIONS.contains(&instance.action.uuid.as_str()) || instance.action.uuid == "opendeck.switchprofile" {
+		// Page and profile switching actions act when they are released, so that the release is not sent to the key in their slot on the new page.
+		return Ok(());
+	} else if instance.action.uuid == "opendeck.brightness" {
+		let instance = instance.clone();
+		drop(locks);
+		return crate::brightness::nudge_from_action(&instance, 1).await;
+	} else if instance.action.uuid == "opendeck.runcommand" {
+		crate::run_command::run(instance);
+	} else if instance.action.uuid == "opendeck.gestureaction" {
+		let config = crate::gestures::GestureConfig::from_instance(instance);
+		drop(locks);
+		crate::gestures::press(context, config).await;
+	} else if crate::multi_actions::MULTI_ACTIONS.contains(&instance.action.uuid.as_str()) {
+		crate::multi_actions::press(instance);
 	} else if instance.action.uuid == "opendeck.toggleaction" {
 		let children = instance.children.as_ref().unwrap();
 		if children.is_empty() {
@@ -106,21 +122,56 @@ pub async fn key_down(device: &str, key: u8) -> Result<(), anyhow::Error> {
 }
 
 pub async fn key_up(device: &str, key: u8) -> Result<(), anyhow::Error> {
+	let Some(key) = crate::shared::DEVICES.get(device).map_or(Some(key), |device| device.logical_position(key)) else {
+		return Ok(());
+	};
+	if crate::idle::release(device, "Keypad", key).await {
+		return Ok(());
+	}
 	let mut locks = acquire_locks_mut().await;
 	let selected_profile = locks.device_stores.get_selected_profile(device)?;
 	let context = Context {
 		device: device.to_owned(),
 		profile: selected_profile.to_owned(),
 		controller: "Keypad".to_owned(),
-		position: key,
+		position: crate::pages::profile_position(device, "Keypad", key),
 	};
 
-	let _ = key_moved(crate::APP_HANDLE.get().unwrap(), context.clone(), false).await;
+	if let Some(app) = crate::APP_HANDLE.get() {
+		let _ = key_moved(app, context.clone(), false).await;
+	}
 
-	let slot = get_slot_mut(&context, &mut locks).await?;
-	let Some(instance) = slot else { return Ok(()) };
+	let Some(instance) = get_slot_mut(&context, &mut locks).await? else { return Ok(()) };
+	let rendered = instance.clone();
+	drop(locks);
+	let _ = crate::render::keys::update_key(&rendered, false).await;
+	let mut locks = acquire_locks_mut().await;
+	let Some(instance) = get_slot_mut(&context, &mut locks).await? else { return Ok(()) };
 
-	if instance.action.uuid == "opendeck.toggleaction" {
+	if instance.action.uuid == "opendeck.hotkey" {
+		#[cfg(target_os = "linux")]
+		{
+			let context = instance.context.clone();
+			drop(locks);
+			crate::hotkeys::release(&context).await?;
+		}
+		return Ok(());
+	} else if crate::pages::PAGE_ACTIONS.contains(&instance.action.uuid.as_str()) {
+		let instance = instance.clone();
+		drop(locks);
+		return crate::pages::navigate(&instance).await;
+	} else if instance.action.uuid == "opendeck.switchprofile" {
+		let instance = instance.clone();
+		drop(locks);
+		return crate::events::frontend::profiles::switch_profile_action(&instance).await;
+	} else if instance.action.uuid == "opendeck.runcommand" || instance.action.uuid == "opendeck.brightness" {
+		return Ok(());
+	} else if instance.action.uuid == "opendeck.gestureaction" {
+		let config = crate::gestures::GestureConfig::from_instance(instance);
+		drop(locks);
+		crate::gestures::release(context, config).await;
+		return Ok(());
+	} else if instance.action.uuid == "opendeck.toggleaction" {
 		let index = instance.current_state as usize;
 		let children = instance.children.as_ref().unwrap();
 		if children.is_empty() {
@@ -139,7 +190,7 @@ pub async fn key_up(device: &str, key: u8) -> Result<(), anyhow::Error> {
 		)
 		.await?;
 		instance.current_state = ((index + 1) % instance.children.as_ref().unwrap().len()) as u16;
-	} else if instance.action.uuid != "opendeck.multiaction" {
+	} else if !crate::multi_actions::MULTI_ACTIONS.contains(&instance.action.uuid.as_str()) {
 		if instance.states.len() == 2 && !instance.action.disable_automatic_states {
 			instance.current_state = (instance.current_state + 1) % (instance.states.len() as u16);
 		}
@@ -156,8 +207,11 @@ pub async fn key_up(device: &str, key: u8) -> Result<(), anyhow::Error> {
 		.await?;
 	};
 
-	let _ = update_state(crate::APP_HANDLE.get().unwrap(), instance.context.clone(), &mut locks).await;
+	let context = instance.context.clone();
 	save_profile(device, &mut locks).await?;
+	if let Some(app) = crate::APP_HANDLE.get() {
+		let _ = update_state(app, context, locks).await;
+	}
 
 	Ok(())
 }
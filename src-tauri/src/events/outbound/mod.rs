@@ -1,3 +1,4 @@
+pub mod deep_link;
 pub mod devices;
 pub mod encoder;
 pub mod keypad;
@@ -15,6 +16,26 @@ struct Coordinates {
 	column: u8,
 }
 
+impl Coordinates {
+	/// Get the coordinates of the slot of its device that an instance is in, from the layout of the device.
+	fn new(context: &crate::shared::ActionContext) -> Self {
+		// Instances on pages other than the first have the coordinates of the slot that they are shown in.
+		let slot = context.position % crate::pages::page_size(&context.device, &context.controller).max(1);
+		match &context.controller[..] {
+			// Encoders are in a single row below the keys.
+			"Encoder" => Self { row: 0, column: slot },
+			_ => {
+				// The columns of a rotated device are those of the layout as it is mounted, so these coordinates follow its orientation.
+				let columns = crate::shared::DEVICES.get(&context.device).map_or(1, |device| device.columns.max(1));
+				Self {
+					row: slot / columns,
+					column: slot % columns,
+				}
+			}
+		}
+	}
+}
+
 #[derive(Serialize)]
 #[allow(non_snake_case)]
 struct GenericInstancePayload {
@@ -27,23 +48,9 @@ struct GenericInstancePayload {
 
 impl GenericInstancePayload {
 	fn new(instance: &crate::shared::ActionInstance) -> Self {
-		let coordinates = match &instance.context.controller[..] {
-			"Encoder" => Coordinates {
-				row: 0,
-				column: instance.context.position,
-			},
-			_ => {
-				let columns = crate::shared::DEVICES.get(&instance.context.device).unwrap().columns;
-				Coordinates {
-					row: instance.context.position / columns,
-					column: instance.context.position % columns,
-				}
-			}
-		};
-
 		Self {
 			settings: instance.settings.clone(),
-			coordinates,
+			coordinates: Coordinates::new(&instance.context),
 			controller: instance.context.controller.clone(),
 			state: instance.current_state,
 			isInMultiAction: instance.context.index != 0,
@@ -38,6 +38,7 @@ struct TitleParameters {
 pub async fn title_parameters_did_change(instance: &ActionInstance, state: u16) -> Result<(), anyhow::Error> {
 	let instance = instance.clone();
 	let state = instance.states[state as usize].clone();
+	let coordinates = Coordinates::new(&instance.context);
 
 	send_to_plugin(
 		&instance.action.plugin,
@@ -48,10 +49,7 @@ pub async fn title_parameters_did_change(instance: &ActionInstance, state: u16)
 			device: instance.context.device,
 			payload: TitleParametersDidChangePayload {
 				settings: instance.settings,
-				coordinates: Coordinates {
-					row: instance.context.position / 3,
-					column: instance.context.position % 3,
-				},
+				coordinates,
 				state: instance.current_state,
 				title: state.text,
 				titleParameters: TitleParameters {
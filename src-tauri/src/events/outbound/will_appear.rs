@@ -11,6 +11,8 @@ struct AppearEvent {
 	payload: GenericInstancePayload,
 }
 
+/// Send willAppear to an instance and draw it on its device.
+/// Callers should not hold the profile locks while awaiting this, as the instance is rendered.
 pub async fn will_appear(instance: &ActionInstance) -> Result<(), anyhow::Error> {
 	send_to_plugin(
 		&instance.action.plugin,
@@ -22,7 +24,17 @@ pub async fn will_appear(instance: &ActionInstance) -> Result<(), anyhow::Error>
 			payload: GenericInstancePayload::new(instance),
 		},
 	)
-	.await
+	.await?;
+
+	if instance.context.controller == "Encoder" {
+		if let Err(error) = crate::render::layouts::update_touchscreen(instance).await {
+			log::warn!("Failed to update device touchscreen: {}", error);
+		}
+	} else if let Err(error) = crate::render::keys::update_key(instance, false).await {
+		log::warn!("Failed to update device image: {}", error);
+	}
+
+	Ok(())
 }
 
 pub async fn will_disappear(instance: &ActionInstance, clear_on_device: bool) -> Result<(), anyhow::Error> {
@@ -38,11 +50,46 @@ pub async fn will_disappear(instance: &ActionInstance, clear_on_device: bool) ->
 	)
 	.await?;
 
-	if clear_on_device {
-		if let Err(error) = crate::events::outbound::devices::update_image((&instance.context).into(), None).await {
-			log::warn!("Failed to clear device image: {}", error);
+	crate::render::layouts::FEEDBACK.write().await.remove(&instance.context);
+
+	// Children of multi actions and toggle actions do not have their own images on the device.
+	if instance.context.index == 0 {
+		let context = (&instance.context).into();
+		if clear_on_device {
+			crate::render::animation::stop(&context).await;
+			if let Err(error) = crate::events::outbound::devices::update_image(context, None).await {
+				log::warn!("Failed to clear device image: {}", error);
+			}
+		} else {
+			crate::render::animation::pause(&context).await;
 		}
 	}
 
 	Ok(())
 }
+
+/// Send willAppear to the instance in a slot, or to its children if it is a multi action, toggle action or gesture action, which has no plugin of its own.
+/// As with `will_appear`, callers should not hold the profile locks while awaiting this.
+pub async fn slot_will_appear(instance: &ActionInstance) {
+	if let Some(children) = &instance.children {
+		let _ = crate::render::keys::update_key(instance, false).await;
+		for child in children {
+			let _ = will_appear(child).await;
+		}
+	} else {
+		let _ = will_appear(instance).await;
+	}
+}
+
+/// Send willDisappear to the instance in a slot as it stops being shown, or to its children if it is a multi action, toggle action or gesture action.
+pub async fn slot_will_disappear(instance: &ActionInstance) {
+	if let Some(children) = &instance.children {
+		crate::render::animation::pause(&(&instance.context).into()).await;
+		crate::gestures::forget(&(&instance.context).into()).await;
+		for child in children {
+			let _ = will_disappear(child, false).await;
+		}
+	} else {
+		let _ = will_disappear(instance, false).await;
+	}
+}
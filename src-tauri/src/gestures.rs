@@ -0,0 +1,251 @@
+//! Gesture actions, which run one of their children when their key is tapped, another when it is held and another when it is pressed twice.
+//! Gestures are timed in the backend so that they behave the same on every device.
+
+use crate::events::frontend::instances::update_state;
+use crate::events::outbound::keypad::send_key_event;
+use crate::shared::{ActionInstance, Context};
+use crate::store::profiles::{acquire_locks_mut, get_slot_mut, save_profile};
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use tokio::sync::Mutex;
+
+pub const GESTURES: [&str; 3] = ["tap", "hold", "double"];
+
+/// The children of a gesture action that each gesture runs, and how gestures are timed.
+pub struct GestureConfig {
+	tap: Option<u16>,
+	hold: Option<u16>,
+	double: Option<u16>,
+	hold_delay: Duration,
+	double_delay: Duration,
+}
+
+impl GestureConfig {
+	/// Read the config of a gesture action from its settings, where `gestures` maps the index of each child to its gesture.
+	pub fn from_instance(instance: &ActionInstance) -> Self {
+		let child = |gesture: &str| -> Option<u16> {
+			let gestures = instance.settings["gestures"].as_object()?;
+			let (index, _) = gestures.iter().find(|(_, value)| value.as_str() == Some(gesture))?;
+			let index = index.parse().ok()?;
+			instance.children.as_ref()?.iter().any(|child| child.context.index == index).then_some(index)
+		};
+		Self {
+			tap: child("tap"),
+			hold: child("hold"),
+			double: child("double"),
+			hold_delay: Duration::from_millis(instance.settings["holddelay"].as_u64().unwrap_or(500)),
+			double_delay: Duration::from_millis(instance.settings["doubledelay"].as_u64().unwrap_or(250)),
+		}
+	}
+}
+
+/// Get the first gesture of a gesture action that does not have a child assigned to it.
+pub fn free_gesture(instance: &ActionInstance) -> Option<&'static str> {
+	let assigned = instance.settings["gestures"].as_object();
+	GESTURES
+		.into_iter()
+		.find(|gesture| !assigned.is_some_and(|assigned| assigned.values().any(|value| value.as_str() == Some(*gesture))))
+}
+
+enum Phase {
+	/// The key is down and it is not yet known which gesture it is.
+	Pressed,
+	/// The key was released and may be pressed again to make a double press.
+	Released,
+	/// The key is down and the child of the gesture has been sent a keyDown event.
+	Running(u16),
+}
+
+struct Tracker {
+	phase: Phase,
+	/// Incremented on every change so that timers can tell whether they have been superseded.
+	generation: u64,
+}
+
+static TRACKERS: Lazy<Mutex<HashMap<Context, Tracker>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Send key events to a child of the gesture action on a key.
+async fn run(context: &Context, index: Option<u16>, events: &[bool]) {
+	let Some(index) = index else { return };
+	let result: Result<(), anyhow::Error> = async {
+		let mut locks = acquire_locks_mut().await;
+		// The profile may have been switched away from while waiting to tell gestures apart.
+		if locks.device_stores.get_selected_profile(&context.device)? != context.profile {
+			return Ok(());
+		}
+		let Some(instance) = get_slot_mut(context, &mut locks).await? else { return Ok(()) };
+		let Some(child) = instance.children.as_mut().and_then(|children| children.iter_mut().find(|child| child.context.index == index)) else {
+			return Ok(());
+		};
+		for down in events {
+			send_key_event(child, *down).await?;
+		}
+		let child = child.context.clone();
+		save_profile(&context.device, &mut locks).await?;
+		if let Some(app) = crate::APP_HANDLE.get() {
+			let _ = update_state(app, child, locks).await;
+		}
+		Ok(())
+	}
+	.await;
+	if let Err(error) = result {
+		log::warn!("Failed to run gesture of key {}.{}: {}", context.device, context.position, error);
+	}
+}
+
+/// Start running the hold gesture on a key after the hold delay, if the key is still down by then.
+fn start_hold_timer(context: Context, generation: u64, delay: Duration, hold: u16) {
+	tokio::spawn(async move {
+		tokio::time::sleep(delay).await;
+		let mut trackers = TRACKERS.lock().await;
+		let Some(tracker) = trackers.get_mut(&context) else { return };
+		if tracker.generation != generation || !matches!(tracker.phase, Phase::Pressed) {
+			return;
+		}
+		tracker.phase = Phase::Running(hold);
+		drop(trackers);
+		run(&context, Some(hold), &[true]).await;
+	});
+}
+
+/// Run the tap gesture on a key after the double press delay, if the key has not been pressed again by then.
+fn start_tap_timer(context: Context, generation: u64, delay: Duration, tap: Option<u16>) {
+	tokio::spawn(async move {
+		tokio::time::sleep(delay).await;
+		let mut trackers = TRACKERS.lock().await;
+		if !trackers.get(&context).is_some_and(|tracker| tracker.generation == generation) {
+			return;
+		}
+		trackers.remove(&context);
+		drop(trackers);
+		run(&context, tap, &[true, false]).await;
+	});
+}
+
+/// Stop telling apart the gesture being made on a key, such as when it stops being shown or its gesture action is removed, which also cancels its timers.
+pub async fn forget(context: &Context) {
+	TRACKERS.lock().await.remove(context);
+}
+
+/// Handle a key of a gesture action being pressed.
+pub async fn press(context: Context, config: GestureConfig) {
+	let mut trackers = TRACKERS.lock().await;
+	let generation = GENERATION.fetch_add(1, Ordering::Relaxed);
+
+	if let (Some(Tracker { phase: Phase::Released, .. }), Some(double)) = (trackers.get(&context), config.double) {
+		trackers.insert(
+			context.clone(),
+			Tracker {
+				phase: Phase::Running(double),
+				generation,
+			},
+		);
+		drop(trackers);
+		run(&context, Some(double), &[true]).await;
+		return;
+	}
+
+	// Without a hold or double press gesture, taps do not need to wait to be told apart from them.
+	if config.hold.is_none() && config.double.is_none() {
+		if let Some(tap) = config.tap {
+			trackers.insert(
+				context.clone(),
+				Tracker {
+					phase: Phase::Running(tap),
+					generation,
+				},
+			);
+			drop(trackers);
+			run(&context, Some(tap), &[true]).await;
+		}
+		return;
+	}
+
+	trackers.insert(context.clone(), Tracker { phase: Phase::Pressed, generation });
+	if let Some(hold) = config.hold {
+		start_hold_timer(context, generation, config.hold_delay, hold);
+	}
+}
+
+/// Handle a key of a gesture action being released.
+pub async fn release(context: Context, config: GestureConfig) {
+	let mut trackers = TRACKERS.lock().await;
+	let Some(tracker) = trackers.get_mut(&context) else { return };
+	match tracker.phase {
+		Phase::Running(index) => {
+			trackers.remove(&context);
+			drop(trackers);
+			run(&context, Some(index), &[false]).await;
+		}
+		Phase::Pressed if config.double.is_some() => {
+			let generation = GENERATION.fetch_add(1, Ordering::Relaxed);
+			*tracker = Tracker { phase: Phase::Released, generation };
+			drop(trackers);
+			start_tap_timer(context, generation, config.double_delay, config.tap);
+		}
+		Phase::Pressed => {
+			trackers.remove(&context);
+			drop(trackers);
+			run(&context, config.tap, &[true, false]).await;
+		}
+		Phase::Released => {}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::virtual_devices::key;
+	use crate::virtual_devices::tests::{action, builtin, connect, events, new_events, place};
+
+	use std::time::Duration;
+
+	use serde_json::json;
+
+	#[tokio::test(start_paused = true)]
+	async fn gesture_timing() {
+		let device = connect("gestures", 1);
+		place(
+			&device.id,
+			0,
+			builtin("opendeck.gestureaction").await,
+			json!({ "gestures": { "1": "tap", "2": "hold", "3": "double" }, "holddelay": 500, "doubledelay": 250 }),
+			Some(vec![action("com.example.test.tap"), action("com.example.test.hold"), action("com.example.test.double")]),
+		)
+		.await;
+		let mut seen = 0;
+
+		// A tap is only run once the key has not been pressed again within the double press delay.
+		key(&device.id, 0, true).await.unwrap();
+		key(&device.id, 0, false).await.unwrap();
+		tokio::time::sleep(Duration::from_millis(200)).await;
+		assert_eq!(new_events(&device.id, &mut seen).await, events(&[]));
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		assert_eq!(new_events(&device.id, &mut seen).await, events(&[(1, "keyDown"), (1, "keyUp")]));
+
+		// A hold is pressed down once the hold delay has passed, and released with the key.
+		key(&device.id, 0, true).await.unwrap();
+		tokio::time::sleep(Duration::from_millis(450)).await;
+		assert_eq!(new_events(&device.id, &mut seen).await, events(&[]));
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		assert_eq!(new_events(&device.id, &mut seen).await, events(&[(2, "keyDown")]));
+		key(&device.id, 0, false).await.unwrap();
+		assert_eq!(new_events(&device.id, &mut seen).await, events(&[(2, "keyUp")]));
+
+		// A second press within the double press delay runs the double press rather than a tap.
+		key(&device.id, 0, true).await.unwrap();
+		key(&device.id, 0, false).await.unwrap();
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		key(&device.id, 0, true).await.unwrap();
+		assert_eq!(new_events(&device.id, &mut seen).await, events(&[(3, "keyDown")]));
+		key(&device.id, 0, false).await.unwrap();
+		tokio::time::sleep(Duration::from_secs(1)).await;
+		assert_eq!(new_events(&device.id, &mut seen).await, events(&[(3, "keyUp")]));
+
+		crate::shared::DEVICES.remove(&device.id);
+	}
+}
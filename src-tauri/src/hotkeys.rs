@@ -0,0 +1,312 @@
+//! The built-in hotkey action, which presses key combinations and types text through a virtual keyboard created with uinput.
+//! Text is typed as if on a US keyboard layout, as uinput sends the codes of keys rather than the characters they produce.
+
+use crate::shared::{ActionContext, ActionInstance};
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use evdev::uinput::{VirtualDevice, VirtualDeviceBuilder};
+use evdev::{AttributeSet, EventType, InputEvent, Key};
+use once_cell::sync::Lazy;
+use tokio::sync::OnceCell;
+
+/// The virtual keyboard, which is created when it is first used, or the reason it could not be created.
+/// Creation is not retried, so that a keyboard that cannot be created does not delay every press.
+static KEYBOARD: OnceCell<Result<Mutex<VirtualDevice>, String>> = OnceCell::const_new();
+/// The keys held down by each instance whose key is pressed, which are released when it is released.
+static HELD: Lazy<Mutex<HashMap<ActionContext, Vec<Key>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+const LETTERS: [Key; 26] = [
+	Key::KEY_A,
+	Key::KEY_B,
+	Key::KEY_C,
+	Key::KEY_D,
+	Key::KEY_E,
+	Key::KEY_F,
+	Key::KEY_G,
+	Key::KEY_H,
+	Key::KEY_I,
+	Key::KEY_J,
+	Key::KEY_K,
+	Key::KEY_L,
+	Key::KEY_M,
+	Key::KEY_N,
+	Key::KEY_O,
+	Key::KEY_P,
+	Key::KEY_Q,
+	Key::KEY_R,
+	Key::KEY_S,
+	Key::KEY_T,
+	Key::KEY_U,
+	Key::KEY_V,
+	Key::KEY_W,
+	Key::KEY_X,
+	Key::KEY_Y,
+	Key::KEY_Z,
+];
+const DIGITS: [Key; 10] = [Key::KEY_0, Key::KEY_1, Key::KEY_2, Key::KEY_3, Key::KEY_4, Key::KEY_5, Key::KEY_6, Key::KEY_7, Key::KEY_8, Key::KEY_9];
+const FUNCTION_KEYS: [Key; 24] = [
+	Key::KEY_F1,
+	Key::KEY_F2,
+	Key::KEY_F3,
+	Key::KEY_F4,
+	Key::KEY_F5,
+	Key::KEY_F6,
+	Key::KEY_F7,
+	Key::KEY_F8,
+	Key::KEY_F9,
+	Key::KEY_F10,
+	Key::KEY_F11,
+	Key::KEY_F12,
+	Key::KEY_F13,
+	Key::KEY_F14,
+	Key::KEY_F15,
+	Key::KEY_F16,
+	Key::KEY_F17,
+	Key::KEY_F18,
+	Key::KEY_F19,
+	Key::KEY_F20,
+	Key::KEY_F21,
+	Key::KEY_F22,
+	Key::KEY_F23,
+	Key::KEY_F24,
+];
+/// The characters typed by keys other than letters and digits, without and with shift held.
+const SYMBOLS: [(char, char, Key); 11] = [
+	('-', '_', Key::KEY_MINUS),
+	('=', '+', Key::KEY_EQUAL),
+	('[', '{', Key::KEY_LEFTBRACE),
+	(']', '}', Key::KEY_RIGHTBRACE),
+	(';', ':', Key::KEY_SEMICOLON),
+	('\'', '"', Key::KEY_APOSTROPHE),
+	('`', '~', Key::KEY_GRAVE),
+	('\\', '|', Key::KEY_BACKSLASH),
+	(',', '<', Key::KEY_COMMA),
+	('.', '>', Key::KEY_DOT),
+	('/', '?', Key::KEY_SLASH),
+];
+/// The characters typed by the digit keys with shift held, from 0 to 9.
+const SHIFTED_DIGITS: [char; 10] = [')', '!', '@', '#', '$', '%', '^', '&', '*', '('];
+
+/// Get a key from its name in a key combination, such as "ctrl", "a", "f5" or "volumeup".
+fn key_by_name(name: &str) -> Option<Key> {
+	let name = name.trim().to_lowercase();
+	let mut chars = name.chars();
+	if let (Some(character), None) = (chars.next(), chars.next()) {
+		return key_for_char(character).map(|(key, _)| key);
+	}
+	if let Some(number) = name.strip_prefix('f').and_then(|number| number.parse::<usize>().ok()) {
+		return FUNCTION_KEYS.get(number.checked_sub(1)?).copied();
+	}
+	Some(match name.as_str() {
+		"ctrl" | "control" => Key::KEY_LEFTCTRL,
+		"shift" => Key::KEY_LEFTSHIFT,
+		"alt" => Key::KEY_LEFTALT,
+		"altgr" => Key::KEY_RIGHTALT,
+		"super" | "meta" | "win" | "cmd" => Key::KEY_LEFTMETA,
+		"enter" | "return" => Key::KEY_ENTER,
+		"esc" | "escape" => Key::KEY_ESC,
+		"tab" => Key::KEY_TAB,
+		"space" => Key::KEY_SPACE,
+		"backspace" => Key::KEY_BACKSPACE,
+		"delete" | "del" => Key::KEY_DELETE,
+		"insert" | "ins" => Key::KEY_INSERT,
+		"home" => Key::KEY_HOME,
+		"end" => Key::KEY_END,
+		"pageup" | "pgup" => Key::KEY_PAGEUP,
+		"pagedown" | "pgdn" => Key::KEY_PAGEDOWN,
+		"up" => Key::KEY_UP,
+		"down" => Key::KEY_DOWN,
+		"left" => Key::KEY_LEFT,
+		"right" => Key::KEY_RIGHT,
+		"printscreen" | "print" => Key::KEY_SYSRQ,
+		"capslock" => Key::KEY_CAPSLOCK,
+		"numlock" => Key::KEY_NUMLOCK,
+		"scrolllock" => Key::KEY_SCROLLLOCK,
+		"pause" => Key::KEY_PAUSE,
+		"menu" => Key::KEY_COMPOSE,
+		"volumeup" => Key::KEY_VOLUMEUP,
+		"volumedown" => Key::KEY_VOLUMEDOWN,
+		"mute" => Key::KEY_MUTE,
+		"playpause" => Key::KEY_PLAYPAUSE,
+		"nexttrack" => Key::KEY_NEXTSONG,
+		"previoustrack" => Key::KEY_PREVIOUSSONG,
+		"stop" => Key::KEY_STOPCD,
+		_ => return None,
+	})
+}
+
+/// Get the key that types a character, and whether shift must be held to type it.
+fn key_for_char(character: char) -> Option<(Key, bool)> {
+	match character {
+		'a'..='z' => Some((LETTERS[character as usize - 'a' as usize], false)),
+		'A'..='Z' => Some((LETTERS[character as usize - 'A' as usize], true)),
+		'0'..='9' => Some((DIGITS[character as usize - '0' as usize], false)),
+		' ' => Some((Key::KEY_SPACE, false)),
+		'\n' => Some((Key::KEY_ENTER, false)),
+		'\t' => Some((Key::KEY_TAB, false)),
+		_ => {
+			if let Some(digit) = SHIFTED_DIGITS.iter().position(|shifted| *shifted == character) {
+				return Some((DIGITS[digit], true));
+			}
+			SYMBOLS.iter().find_map(|(plain, shifted, key)| {
+				if character == *plain {
+					Some((*key, false))
+				} else if character == *shifted {
+					Some((*key, true))
+				} else {
+					None
+				}
+			})
+		}
+	}
+}
+
+/// Create the virtual keyboard, waiting until the system is ready to read from it.
+fn create_keyboard() -> Result<VirtualDevice, String> {
+	let mut supported = AttributeSet::<Key>::new();
+	for code in Key::KEY_ESC.code()..=Key::KEY_MICMUTE.code() {
+		supported.insert(Key::new(code));
+	}
+	let create = || -> std::io::Result<VirtualDevice> { VirtualDeviceBuilder::new()?.name("OpenDeck Virtual Keyboard").with_keys(&supported)?.build() };
+	let device = create().map_err(|error| format!("failed to create virtual keyboard, which needs write access to /dev/uinput, granted before OpenDeck is started: {error}"))?;
+	// Events sent straight after the device is created are missed, as the system has not started reading from it yet.
+	std::thread::sleep(Duration::from_millis(200));
+	Ok(device)
+}
+
+/// Get the virtual keyboard, creating it on the blocking thread pool if it has not been created yet.
+/// Callers should not hold the profile locks while awaiting this, as creating the keyboard takes a moment.
+async fn keyboard() -> Result<&'static Mutex<VirtualDevice>, anyhow::Error> {
+	KEYBOARD
+		.get_or_init(|| async {
+			let result = tokio::task::spawn_blocking(create_keyboard).await.unwrap_or_else(|error| Err(error.to_string())).map(Mutex::new);
+			if let Err(error) = &result {
+				log::warn!("Hotkeys will not work: {}", error);
+			}
+			result
+		})
+		.await
+		.as_ref()
+		.map_err(|error| anyhow!("{error}"))
+}
+
+/// Press or release keys on the virtual keyboard.
+async fn emit(keys: &[Key], pressed: bool) -> Result<(), anyhow::Error> {
+	let mut keyboard = keyboard().await?.lock().unwrap();
+	for key in keys {
+		keyboard.emit(&[InputEvent::new(EventType::KEY, key.code(), pressed as i32)])?;
+	}
+	Ok(())
+}
+
+/// Type text on the virtual keyboard in the background, pausing between characters so that applications do not miss any.
+fn type_text(text: String) {
+	tokio::spawn(async move {
+		for character in text.chars() {
+			let Some((key, shift)) = key_for_char(character) else {
+				log::warn!("Cannot type character {character:?} with the virtual keyboard");
+				continue;
+			};
+			let keys = if shift { vec![Key::KEY_LEFTSHIFT, key] } else { vec![key] };
+			let result = match emit(&keys, true).await {
+				Ok(()) => emit(&keys.into_iter().rev().collect::<Vec<_>>(), false).await,
+				error => error,
+			};
+			if let Err(error) = result {
+				log::warn!("Failed to type text: {}", error);
+				return;
+			}
+			tokio::time::sleep(Duration::from_millis(5)).await;
+		}
+	});
+}
+
+/// Handle the key of an instance of the hotkey action being pressed, pressing its key combination or typing its text.
+/// Callers should not hold the profile locks while awaiting this, as the virtual keyboard may need to be created first.
+pub async fn press(instance: &ActionInstance) -> Result<(), anyhow::Error> {
+	if instance.settings["mode"].as_str() == Some("text") {
+		type_text(instance.settings["text"].as_str().unwrap_or_default().to_owned());
+		return Ok(());
+	}
+
+	let keys = instance.settings["keys"]
+		.as_str()
+		.unwrap_or_default()
+		.split('+')
+		.filter(|name| !name.trim().is_empty())
+		.map(|name| key_by_name(name).ok_or_else(|| anyhow!("unknown key {}", name.trim())))
+		.collect::<Result<Vec<_>, _>>()?;
+	emit(&keys, true).await?;
+	HELD.lock().unwrap().insert(instance.context.clone(), keys);
+	Ok(())
+}
+
+/// Handle the key of an instance of the hotkey action being released, releasing the keys it held down in reverse order.
+pub async fn release(context: &ActionContext) -> Result<(), anyhow::Error> {
+	let Some(keys) = HELD.lock().unwrap().remove(context) else { return Ok(()) };
+	emit(&keys.into_iter().rev().collect::<Vec<_>>(), false).await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn keys_by_name() {
+		assert_eq!(key_by_name("a"), Some(Key::KEY_A));
+		assert_eq!(key_by_name(" Z "), Some(Key::KEY_Z));
+		assert_eq!(key_by_name("7"), Some(Key::KEY_7));
+		assert_eq!(key_by_name("-"), Some(Key::KEY_MINUS));
+		assert_eq!(key_by_name("Ctrl"), Some(Key::KEY_LEFTCTRL));
+		assert_eq!(key_by_name("control"), Some(Key::KEY_LEFTCTRL));
+		assert_eq!(key_by_name("super"), Some(Key::KEY_LEFTMETA));
+		assert_eq!(key_by_name("pgdn"), Some(Key::KEY_PAGEDOWN));
+		assert_eq!(key_by_name("volumeup"), Some(Key::KEY_VOLUMEUP));
+	}
+
+	#[test]
+	fn function_keys_by_name() {
+		assert_eq!(key_by_name("f1"), Some(Key::KEY_F1));
+		assert_eq!(key_by_name("F12"), Some(Key::KEY_F12));
+		assert_eq!(key_by_name("f24"), Some(Key::KEY_F24));
+		assert_eq!(key_by_name("f0"), None);
+		assert_eq!(key_by_name("f25"), None);
+		// A lone "f" is the letter rather than a function key.
+		assert_eq!(key_by_name("f"), Some(Key::KEY_F));
+	}
+
+	#[test]
+	fn unknown_key_names() {
+		assert_eq!(key_by_name(""), None);
+		assert_eq!(key_by_name("hyper"), None);
+		assert_eq!(key_by_name("é"), None);
+	}
+
+	#[test]
+	fn keys_for_characters() {
+		assert_eq!(key_for_char('q'), Some((Key::KEY_Q, false)));
+		assert_eq!(key_for_char('Q'), Some((Key::KEY_Q, true)));
+		assert_eq!(key_for_char('0'), Some((Key::KEY_0, false)));
+		assert_eq!(key_for_char(')'), Some((Key::KEY_0, true)));
+		assert_eq!(key_for_char('!'), Some((Key::KEY_1, true)));
+		assert_eq!(key_for_char('('), Some((Key::KEY_9, true)));
+		assert_eq!(key_for_char(' '), Some((Key::KEY_SPACE, false)));
+		assert_eq!(key_for_char('\n'), Some((Key::KEY_ENTER, false)));
+		assert_eq!(key_for_char('\t'), Some((Key::KEY_TAB, false)));
+	}
+
+	#[test]
+	fn symbols_for_characters() {
+		for (plain, shifted, key) in SYMBOLS {
+			assert_eq!(key_for_char(plain), Some((key, false)));
+			assert_eq!(key_for_char(shifted), Some((key, true)));
+		}
+		assert_eq!(key_for_char('~'), Some((Key::KEY_GRAVE, true)));
+		assert_eq!(key_for_char('£'), None);
+		assert_eq!(key_for_char('é'), None);
+	}
+}
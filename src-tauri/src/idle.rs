@@ -0,0 +1,176 @@
+//! Dimming devices or covering their keys with a screensaver after they go without input for a while.
+//! The first input to an idle device wakes it without being sent to the action it was made on.
+
+use crate::events::outbound::devices::{redraw_keys, write_key_image};
+use crate::render::{draw_text, keys::KEY_SIZE, text_width, to_data_url, DEFAULT_FONT};
+use crate::shared::{Context, DEVICES};
+use crate::store::profiles::{IdleConfig, DEVICE_STORES};
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use ab_glyph::PxScale;
+use dashmap::DashMap;
+use image::{imageops::FilterType, DynamicImage, Rgba, RgbaImage};
+use once_cell::sync::Lazy;
+use tokio::sync::Mutex;
+
+/// The time of the most recent input on each device.
+static LAST_INPUT: Lazy<DashMap<String, Instant>> = Lazy::new(DashMap::new);
+/// The idle config of each idle device at the time it became idle.
+static IDLE: Lazy<DashMap<String, IdleConfig>> = Lazy::new(DashMap::new);
+/// Keys and dials whose presses woke their device, so that their releases are also not sent to actions.
+static WAKING_PRESSES: Lazy<Mutex<HashSet<(String, String, u8)>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Whether the keys of a device are covered by a screensaver.
+pub fn showing_screensaver(device: &str) -> bool {
+	IDLE.get(device).is_some_and(|config| matches!(config.mode.as_str(), "image" | "clock"))
+}
+
+/// Limit the brightness of a device according to its idle mode.
+pub fn limit_brightness(device: &str, brightness: u8) -> u8 {
+	match IDLE.get(device).as_deref() {
+		Some(config) if config.mode == "off" => 0,
+		Some(config) if config.mode == "dim" => brightness.min(config.level),
+		_ => brightness,
+	}
+}
+
+/// Record input on a device, waking it if it is idle.
+/// Returns whether the input woke the device, in which case it should not be sent to any action.
+pub async fn input(device: &str) -> bool {
+	LAST_INPUT.insert(device.to_owned(), Instant::now());
+	let Some((_, config)) = IDLE.remove(device) else { return false };
+
+	if let Err(error) = crate::brightness::update_device(device).await {
+		log::warn!("Failed to restore brightness of device {}: {}", device, error);
+	}
+	if matches!(config.mode.as_str(), "image" | "clock") {
+		if let Err(error) = redraw_keys(device).await {
+			log::warn!("Failed to redraw keys of device {}: {}", device, error);
+		}
+	}
+	true
+}
+
+/// Record the press of a key or dial, waking its device if it is idle.
+/// Returns whether the press woke the device, in which case neither it nor its release should be sent to any action.
+pub async fn press(device: &str, controller: &str, position: u8) -> bool {
+	let woke = input(device).await;
+	if woke {
+		WAKING_PRESSES.lock().await.insert((device.to_owned(), controller.to_owned(), position));
+	}
+	woke
+}
+
+/// Record the release of a key or dial, returning whether its press woke its device.
+pub async fn release(device: &str, controller: &str, position: u8) -> bool {
+	LAST_INPUT.insert(device.to_owned(), Instant::now());
+	WAKING_PRESSES.lock().await.remove(&(device.to_owned(), controller.to_owned(), position))
+}
+
+/// Start tracking input on a device when it is connected.
+pub fn register_device(device: &str) {
+	LAST_INPUT.insert(device.to_owned(), Instant::now());
+}
+
+/// Change the idle config of a device, waking it so that the new config takes effect.
+pub async fn set_config(device: &str, config: IdleConfig) -> Result<(), anyhow::Error> {
+	if !matches!(config.mode.as_str(), "dim" | "image" | "clock" | "off") {
+		return Err(anyhow::anyhow!("idle mode must be dim, image, clock or off"));
+	}
+	DEVICE_STORES.write().await.update_config(device, |existing| existing.idle = config)?;
+	input(device).await;
+	Ok(())
+}
+
+pub fn forget_device(device: &str) {
+	LAST_INPUT.remove(device);
+	IDLE.remove(device);
+}
+
+/// Draw a screensaver across all of the keys of a device.
+async fn draw_screensaver(device: &str, config: &IdleConfig) -> Result<(), anyhow::Error> {
+	let Some((rows, columns)) = DEVICES.get(device).map(|device| (device.rows, device.columns)) else {
+		return Ok(());
+	};
+	let (width, height) = (columns as u32 * KEY_SIZE, rows as u32 * KEY_SIZE);
+	let mut canvas = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+
+	if config.mode == "clock" {
+		let time = chrono::Local::now().format("%H:%M").to_string();
+		// The time is sized to fill most of the width of the device without being taller than its keys allow.
+		let reference = text_width(&*DEFAULT_FONT, PxScale::from(100.0), &time);
+		let size = (width as f32 * 0.8 / reference * 100.0).min(height as f32 * 0.6);
+		let scale = PxScale::from(size);
+		let x = (width as f32 - text_width(&*DEFAULT_FONT, scale, &time)) / 2.0;
+		draw_text(&mut canvas, &*DEFAULT_FONT, scale, Rgba([255, 255, 255, 255]), x, (height as f32 - size) / 2.0, &time);
+	} else if !config.image.is_empty() {
+		match crate::render::load_image(&config.image) {
+			Ok(image) => canvas = image.resize_to_fill(width, height, FilterType::Triangle).to_rgba8(),
+			Err(error) => log::warn!("Failed to load screensaver image: {}", error),
+		}
+	}
+
+	let profile = DEVICE_STORES.write().await.get_selected_profile(device)?;
+	// Keys are addressed by a single byte, so any beyond those of the largest layout are left as they are.
+	for position in (0..rows as u16 * columns as u16).map_while(|position| u8::try_from(position).ok()) {
+		// The device may have been woken while the screensaver was being drawn.
+		if !showing_screensaver(device) {
+			break;
+		}
+		let (x, y) = ((position % columns) as u32 * KEY_SIZE, (position / columns) as u32 * KEY_SIZE);
+		let tile = DynamicImage::ImageRgba8(image::imageops::crop_imm(&canvas, x, y, KEY_SIZE, KEY_SIZE).to_image());
+		let context = Context {
+			device: device.to_owned(),
+			profile: profile.clone(),
+			controller: "Keypad".to_owned(),
+			position,
+		};
+		write_key_image(context, Some(to_data_url(&tile)?)).await?;
+	}
+	Ok(())
+}
+
+/// Make a device idle, or update its screensaver if it already is.
+async fn idle(device: &str, config: &IdleConfig, already_idle: bool) -> Result<(), anyhow::Error> {
+	if !already_idle {
+		IDLE.insert(device.to_owned(), config.clone());
+		crate::brightness::update_device(device).await?;
+	}
+	if matches!(config.mode.as_str(), "image" | "clock") && (!already_idle || config.mode == "clock") {
+		draw_screensaver(device, config).await?;
+	}
+	Ok(())
+}
+
+/// Start checking for devices that have gone without input for longer than their idle timeout.
+pub fn init_idle_watcher() {
+	tokio::spawn(async {
+		let mut last_minute = None;
+		loop {
+			tokio::time::sleep(Duration::from_secs(5)).await;
+			// Clocks are only redrawn when the minute changes.
+			let minute = chrono::Local::now().format("%H:%M").to_string();
+			let new_minute = last_minute.as_ref() != Some(&minute);
+			last_minute = Some(minute);
+
+			for device in DEVICES.iter().map(|device| device.key().clone()).collect::<Vec<_>>() {
+				let Ok(config) = DEVICE_STORES.read().await.read_config(&device, |config| config.idle.clone()) else {
+					continue;
+				};
+				let already_idle = IDLE.contains_key(&device);
+				if config.timeout == 0 || (already_idle && !(new_minute && config.mode == "clock")) {
+					continue;
+				}
+				let elapsed = LAST_INPUT.entry(device.clone()).or_insert_with(Instant::now).elapsed();
+				if elapsed < Duration::from_secs(config.timeout as u64 * 60) {
+					continue;
+				}
+				if let Err(error) = idle(&device, &config, already_idle).await {
+					log::warn!("Failed to make device {} idle: {}", device, error);
+				}
+			}
+		}
+	});
+}
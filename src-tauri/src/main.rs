@@ -1,12 +1,23 @@
 // Prevents additional console window on Windows in release.
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod api;
 mod application_watcher;
+mod brightness;
 mod elgato;
 mod events;
+mod gestures;
+#[cfg(target_os = "linux")]
+mod hotkeys;
+mod idle;
+mod multi_actions;
+mod pages;
 mod plugins;
+mod render;
+mod run_command;
 mod shared;
 mod store;
+mod virtual_devices;
 mod zip_extract;
 
 mod built_info {
@@ -39,6 +50,17 @@ async fn main() {
 	let app = match Builder::default()
 		.invoke_handler(tauri::generate_handler![
 			frontend::get_devices,
+			frontend::get_image_write_stats,
+			frontend::set_device_orientation,
+			frontend::get_device_brightness,
+			frontend::set_device_brightness,
+			frontend::nudge_brightness,
+			frontend::get_device_idle,
+			frontend::set_device_idle,
+			frontend::get_virtual_devices,
+			frontend::create_virtual_device,
+			frontend::remove_virtual_device,
+			frontend::press_virtual_key,
 			frontend::restart,
 			frontend::get_categories,
 			frontend::get_localisations,
@@ -49,11 +71,23 @@ async fn main() {
 			frontend::instances::move_instance,
 			frontend::instances::remove_instance,
 			frontend::instances::set_state,
-			frontend::instances::update_image,
+			frontend::instances::set_gesture,
+			frontend::instances::set_gesture_delays,
+			frontend::instances::set_multi_action_step,
+			frontend::instances::set_multi_action_sequence,
+			frontend::instances::set_instance_settings,
+			frontend::instances::set_dial_options,
+			frontend::instances::get_trigger_description,
 			frontend::profiles::get_profiles,
 			frontend::profiles::get_selected_profile,
 			frontend::profiles::set_selected_profile,
 			frontend::profiles::delete_profile,
+			frontend::profiles::get_known_devices,
+			frontend::profiles::remap_profile,
+			frontend::profiles::get_page,
+			frontend::profiles::set_page,
+			frontend::profiles::add_page,
+			frontend::profiles::remove_page,
 			frontend::property_inspector::make_info,
 			frontend::property_inspector::switch_property_inspector,
 			frontend::property_inspector::open_url,
@@ -70,8 +104,12 @@ async fn main() {
 		.setup(|app| {
 			APP_HANDLE.set(app.handle().clone()).unwrap();
 
-			if std::env::args().any(|v| v == "--hide") {
-				let _ = app.get_webview_window("main").unwrap().hide();
+			// The main window is not created automatically so that it can be left out entirely in headless mode.
+			if !*shared::HEADLESS {
+				let window = tauri::WebviewWindowBuilder::from_config(app.handle(), &app.config().app.windows[0])?.build()?;
+				if std::env::args().any(|v| v == "--hide") {
+					let _ = window.hide();
+				}
 			}
 
 			let old = app.path().config_dir().unwrap().join("com.amansprojects.opendeck");
@@ -83,6 +121,15 @@ async fn main() {
 			use std::cmp::Ordering;
 			use tauri_plugin_dialog::{DialogExt, MessageDialogKind};
 			match semver::Version::parse(built_info::PKG_VERSION)?.cmp(&semver::Version::parse(&settings.value.version)?) {
+				Ordering::Less if *shared::HEADLESS => {
+					log::error!(
+						"A newer version of OpenDeck created configuration files on this device. This version is v{}; please upgrade to v{} or newer.",
+						built_info::PKG_VERSION,
+						settings.value.version
+					);
+					app.handle().exit(1);
+					return Ok(());
+				}
 				Ordering::Less => {
 					app.get_webview_window("main").unwrap().close().unwrap();
 					app.dialog()
@@ -100,7 +147,7 @@ async fn main() {
 					let old_version = settings.value.version.clone();
 					settings.value.version = built_info::PKG_VERSION.to_owned();
 					settings.save()?;
-					if old_version == "0.0.0" {
+					if old_version == "0.0.0" && !*shared::HEADLESS {
 						app.dialog()
 							.message(
 								r#"Thanks for installing OpenDeck!
@@ -131,41 +178,74 @@ Enjoy!"#,
 			)?;
 			let _ = app.track_event("app_started", None);
 
-			tokio::spawn(async {
-				loop {
-					elgato::initialise_devices().await;
-					tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-				}
-			});
+			tokio::spawn(elgato::watch_devices());
+			tokio::spawn(virtual_devices::initialise_virtual_devices());
 			plugins::initialise_plugins();
 			application_watcher::init_application_watcher();
+			brightness::init_brightness_scheduler();
+			idle::init_idle_watcher();
+			api::init_api();
+
+			if *shared::HEADLESS {
+				#[cfg(unix)]
+				tokio::spawn(async {
+					use tokio::signal::unix::{signal, SignalKind};
+					let Ok(mut terminate) = signal(SignalKind::terminate()) else { return };
+					tokio::select! {
+						_ = terminate.recv() => {}
+						_ = tokio::signal::ctrl_c() => {}
+					}
+					log::info!("Received termination signal, exiting");
+					APP_HANDLE.get().unwrap().exit(0);
+				});
+				#[cfg(not(unix))]
+				tokio::spawn(async {
+					if tokio::signal::ctrl_c().await.is_ok() {
+						APP_HANDLE.get().unwrap().exit(0);
+					}
+				});
+			} else {
+				let open = MenuItemBuilder::with_id("open", "Open").build(app)?;
+				let hide = MenuItemBuilder::with_id("hide", "Hide").build(app)?;
+				let quit = MenuItemBuilder::with_id("quit", "Quit").build(app)?;
+				let separator = PredefinedMenuItem::separator(app)?;
+				let menu = MenuBuilder::new(app).items(&[&open, &hide, &separator, &quit]).build()?;
+				let _tray = TrayIconBuilder::new()
+					.menu(&menu)
+					.icon(app.default_window_icon().unwrap().clone())
+					.on_menu_event(move |app, event| {
+						let window = app.get_webview_window("main").unwrap();
+						let _ = match event.id().as_ref() {
+							"open" => window.show(),
+							"hide" => window.hide(),
+							"quit" => {
+								app.exit(0);
+								Ok(())
+							}
+							_ => Ok(()),
+						};
+					})
+					.build(app)?;
+			}
 
-			let open = MenuItemBuilder::with_id("open", "Open").build(app)?;
-			let hide = MenuItemBuilder::with_id("hide", "Hide").build(app)?;
-			let quit = MenuItemBuilder::with_id("quit", "Quit").build(app)?;
-			let separator = PredefinedMenuItem::separator(app)?;
-			let menu = MenuBuilder::new(app).items(&[&open, &hide, &separator, &quit]).build()?;
-			let _tray = TrayIconBuilder::new()
-				.menu(&menu)
-				.icon(app.default_window_icon().unwrap().clone())
-				.on_menu_event(move |app, event| {
-					let window = app.get_webview_window("main").unwrap();
-					let _ = match event.id().as_ref() {
-						"open" => window.show(),
-						"hide" => window.hide(),
-						"quit" => {
-							app.exit(0);
-							Ok(())
-						}
-						_ => Ok(()),
-					};
-				})
-				.build(app)?;
-
-			#[cfg(any(target_os = "linux", all(debug_assertions, windows)))]
 			{
 				use tauri_plugin_deep_link::DeepLinkExt;
+				#[cfg(any(target_os = "linux", all(debug_assertions, windows)))]
 				let _ = app.deep_link().register_all();
+
+				fn handle_deep_links(urls: Vec<tauri::Url>) {
+					for url in urls {
+						tokio::spawn(async move {
+							if let Err(error) = events::outbound::deep_link::did_receive_deep_link(url.as_str()).await {
+								log::warn!("Failed to handle deep link {}: {}", url, error);
+							}
+						});
+					}
+				}
+				app.deep_link().on_open_url(|event| handle_deep_links(event.urls()));
+				if let Ok(Some(urls)) = app.deep_link().get_current() {
+					handle_deep_links(urls);
+				}
 			}
 
 			async fn update() -> Result<(), anyhow::Error> {
@@ -179,6 +259,10 @@ Enjoy!"#,
 					.await?;
 				let tag_name = res.get("tag_name").unwrap().as_str().unwrap();
 				if semver::Version::parse(built_info::PKG_VERSION)?.cmp(&semver::Version::parse(&tag_name[1..])?) == Ordering::Less {
+					if *shared::HEADLESS {
+						log::info!("A new version of OpenDeck, {}, is available", tag_name);
+						return Ok(());
+					}
 					let app = APP_HANDLE.get().unwrap();
 					app.dialog()
 						.message(format!(
@@ -211,7 +295,11 @@ Enjoy!"#,
 				.build(),
 		)
 		.plugin(tauri_plugin_cors_fetch::init())
-		.plugin(tauri_plugin_single_instance::init(|app, _, _| app.get_webview_window("main").unwrap().show().unwrap()))
+		.plugin(tauri_plugin_single_instance::init(|app, _, _| {
+			if let Some(window) = app.get_webview_window("main") {
+				let _ = window.show();
+			}
+		}))
 		.plugin(tauri_plugin_autostart::init(tauri_plugin_autostart::MacosLauncher::LaunchAgent, Some(vec!["--hide"])))
 		.plugin(tauri_plugin_dialog::init())
 		.plugin(tauri_plugin_deep_link::init())
@@ -0,0 +1,294 @@
+//! Multi actions, which run their children one after another as a sequence of steps when their key is pressed.
+//! Multi action switches have two sequences, and alternate between running the first and the second, showing a different state for each.
+//! Sequences run in the background without holding the profile locks while waiting, so that other keys and devices are not blocked.
+
+use crate::events::frontend::instances::update_state;
+use crate::events::outbound::keypad::send_key_event;
+use crate::shared::{ActionInstance, Context};
+use crate::store::profiles::{acquire_locks_mut, get_slot_mut, save_profile};
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use tokio::sync::Notify;
+
+pub const STEP_MODES: [&str; 3] = ["full", "down", "up"];
+pub const MULTI_ACTIONS: [&str; 2] = ["opendeck.multiaction", "opendeck.multiactionswitch"];
+
+/// How long the key of a child is held down for by steps that press and release it.
+const PRESS_DURATION: Duration = Duration::from_millis(100);
+
+enum StepKind {
+	/// Send keyDown and then keyUp to the child.
+	Full,
+	/// Only send keyDown to the child, so that it can be held down until a later step releases it.
+	Down,
+	/// Only send keyUp to the child.
+	Up,
+	/// Do nothing but wait, for children that are instances of the built-in wait action.
+	Wait,
+}
+
+struct Step {
+	index: u16,
+	delay: Duration,
+	kind: StepKind,
+}
+
+/// Get the sequence of a multi action switch that a child belongs to, from its settings, where `sequences` maps the index of each child to its sequence.
+fn sequence(instance: &ActionInstance, index: u16) -> u16 {
+	instance.settings["sequences"][index.to_string()].as_u64().unwrap_or(0) as u16
+}
+
+/// Read the steps of a multi action from its settings, where `steps` maps the index of each child to its mode and the delay before it in milliseconds.
+/// Without a delay set, wait steps wait for a second, and other steps follow 100 milliseconds after the step before them.
+/// Multi action switches only run the children in the sequence of their current state.
+fn steps(instance: &ActionInstance) -> Vec<Step> {
+	let switch = instance.action.uuid == "opendeck.multiactionswitch";
+	instance
+		.children
+		.iter()
+		.flatten()
+		.filter(|child| !switch || sequence(instance, child.context.index) == instance.current_state)
+		.enumerate()
+		.map(|(position, child)| {
+			let settings = &instance.settings["steps"][child.context.index.to_string()];
+			let (kind, default_delay) = if child.action.uuid == "opendeck.wait" {
+				(StepKind::Wait, 1000)
+			} else {
+				let kind = match settings["mode"].as_str() {
+					Some("down") => StepKind::Down,
+					Some("up") => StepKind::Up,
+					_ => StepKind::Full,
+				};
+				(kind, if position == 0 { 0 } else { 100 })
+			};
+			Step {
+				index: child.context.index,
+				delay: Duration::from_millis(settings["delay"].as_u64().unwrap_or(default_delay)),
+				kind,
+			}
+		})
+		.collect()
+}
+
+/// The sequences that are running on each key, which are cancelled by notifying them.
+static RUNNING: Lazy<DashMap<Context, Arc<Notify>>> = Lazy::new(DashMap::new);
+
+/// Wait for a delay, returning false if the sequence was cancelled before or during it.
+async fn wait(cancel: &Notify, delay: Duration) -> bool {
+	tokio::select! {
+		biased;
+		_ = cancel.notified() => false,
+		_ = tokio::time::sleep(delay) => true,
+	}
+}
+
+/// Send a key event to a child of the multi action on a key, returning false if the multi action is no longer there to send it to.
+async fn send(context: &Context, index: u16, down: bool) -> Result<bool, anyhow::Error> {
+	let mut locks = acquire_locks_mut().await;
+	// The profile may have been switched away from while waiting between steps.
+	if locks.device_stores.get_selected_profile(&context.device)? != context.profile {
+		return Ok(false);
+	}
+	let Some(instance) = get_slot_mut(context, &mut locks).await? else { return Ok(false) };
+	if !MULTI_ACTIONS.contains(&instance.action.uuid.as_str()) {
+		return Ok(false);
+	}
+	let Some(child) = instance.children.as_mut().and_then(|children| children.iter_mut().find(|child| child.context.index == index)) else {
+		return Ok(true);
+	};
+	// Hotkeys are pressed by OpenDeck itself rather than by a plugin.
+	if child.action.uuid == "opendeck.hotkey" {
+		#[cfg(target_os = "linux")]
+		{
+			let child = child.clone();
+			drop(locks);
+			if down {
+				crate::hotkeys::press(&child).await?;
+			} else {
+				crate::hotkeys::release(&child.context).await?;
+			}
+		}
+		return Ok(true);
+	}
+	send_key_event(child, down).await?;
+	let child = child.context.clone();
+	save_profile(&context.device, &mut locks).await?;
+	if let Some(app) = crate::APP_HANDLE.get() {
+		let _ = update_state(app, child, locks).await;
+	}
+	Ok(true)
+}
+
+/// Run the steps of the multi action on a key until they are finished or cancelled.
+/// Children held down by earlier steps are released if the sequence is cancelled before the steps that would release them.
+async fn run(context: Context, steps: Vec<Step>, cancel: Arc<Notify>) {
+	let mut held: Vec<u16> = vec![];
+	for step in steps {
+		if !wait(&cancel, step.delay).await {
+			for index in held.into_iter().rev() {
+				if let Err(error) = send(&context, index, false).await {
+					log::warn!("Failed to release child of cancelled multi action on key {}.{}: {}", context.device, context.position, error);
+				}
+			}
+			break;
+		}
+		let result = match step.kind {
+			StepKind::Wait => Ok(true),
+			StepKind::Down => {
+				let result = send(&context, step.index, true).await;
+				if matches!(result, Ok(true)) && !held.contains(&step.index) {
+					held.push(step.index);
+				}
+				result
+			}
+			StepKind::Up => {
+				held.retain(|index| *index != step.index);
+				send(&context, step.index, false).await
+			}
+			// Cancelling does not interrupt a press, so that the child is not left held down.
+			StepKind::Full => match send(&context, step.index, true).await {
+				Ok(true) => {
+					tokio::time::sleep(PRESS_DURATION).await;
+					send(&context, step.index, false).await
+				}
+				other => other,
+			},
+		};
+		match result {
+			Ok(true) => {}
+			Ok(false) => break,
+			Err(error) => {
+				log::warn!("Failed to run multi action on key {}.{}: {}", context.device, context.position, error);
+				break;
+			}
+		}
+	}
+	RUNNING.remove_if(&context, |_, running| Arc::ptr_eq(running, &cancel));
+}
+
+/// Handle the key of a multi action being pressed, starting its sequence of steps, or cancelling the sequence if it is already running.
+/// Multi action switches move on to their other state as they start, so that the next press runs their other sequence.
+pub fn press(instance: &mut ActionInstance) {
+	let context: Context = (&instance.context).into();
+	if let Some((_, running)) = RUNNING.remove(&context) {
+		running.notify_one();
+		return;
+	}
+	let cancel = Arc::new(Notify::new());
+	RUNNING.insert(context.clone(), cancel.clone());
+	tokio::spawn(run(context, steps(instance), cancel));
+	if instance.action.uuid == "opendeck.multiactionswitch" {
+		instance.current_state = (instance.current_state + 1) % (instance.states.len().max(1) as u16);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::store::elgato_profile::{self, tests::install_action};
+	use crate::virtual_devices::key;
+	use crate::virtual_devices::tests::{action, builtin, connect, events, new_events, place};
+
+	use serde_json::json;
+
+	/// Press and release the key of a multi action.
+	async fn tap(device: &str) {
+		key(device, 0, true).await.unwrap();
+		key(device, 0, false).await.unwrap();
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn step_modes() {
+		let device = connect("multi-steps", 1);
+		place(
+			&device.id,
+			0,
+			builtin("opendeck.multiaction").await,
+			json!({ "steps": { "2": { "mode": "down" }, "3": { "mode": "up", "delay": 300 } } }),
+			Some(vec![action("com.example.test.full"), action("com.example.test.down"), action("com.example.test.up")]),
+		)
+		.await;
+		let mut seen = 0;
+
+		// The first step runs straight away, pressing and releasing its child, and the multi action itself is not sent the key events.
+		tap(&device.id).await;
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		assert_eq!(new_events(&device.id, &mut seen).await, events(&[(1, "keyDown")]));
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		assert_eq!(new_events(&device.id, &mut seen).await, events(&[(1, "keyUp")]));
+		// Steps without a delay follow 100 milliseconds after the step before them, which ends once its child is released.
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		assert_eq!(new_events(&device.id, &mut seen).await, events(&[(2, "keyDown")]));
+		tokio::time::sleep(Duration::from_millis(250)).await;
+		assert_eq!(new_events(&device.id, &mut seen).await, events(&[]));
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		assert_eq!(new_events(&device.id, &mut seen).await, events(&[(3, "keyUp")]));
+		assert!(RUNNING.iter().all(|running| running.key().device != device.id));
+
+		crate::shared::DEVICES.remove(&device.id);
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn cancel_releases_held_children() {
+		let device = connect("multi-cancel", 1);
+		place(
+			&device.id,
+			0,
+			builtin("opendeck.multiaction").await,
+			json!({ "steps": { "1": { "mode": "down" }, "3": { "mode": "up" } } }),
+			Some(vec![action("com.example.test.down"), builtin("opendeck.wait").await, action("com.example.test.up")]),
+		)
+		.await;
+		let mut seen = 0;
+
+		tap(&device.id).await;
+		tokio::time::sleep(Duration::from_millis(500)).await;
+		assert_eq!(new_events(&device.id, &mut seen).await, events(&[(1, "keyDown")]));
+
+		// Pressing the key while the sequence is waiting cancels it, releasing the child it held down instead of running the rest of the steps.
+		tap(&device.id).await;
+		tokio::time::sleep(Duration::from_secs(2)).await;
+		assert_eq!(new_events(&device.id, &mut seen).await, events(&[(1, "keyUp")]));
+
+		// The next press starts the sequence again from the beginning.
+		tap(&device.id).await;
+		tokio::time::sleep(Duration::from_millis(10)).await;
+		assert_eq!(new_events(&device.id, &mut seen).await, events(&[(1, "keyDown")]));
+		tap(&device.id).await;
+		tokio::time::sleep(Duration::from_millis(10)).await;
+		assert_eq!(new_events(&device.id, &mut seen).await, events(&[(1, "keyUp")]));
+
+		crate::shared::DEVICES.remove(&device.id);
+	}
+
+	#[tokio::test]
+	async fn imported_switch_alternates_between_sequences() {
+		install_action().await;
+		let bytes = include_bytes!("../fixtures/elgato/multi-action-switch.streamDeckProfile");
+		let profile = elgato_profile::import(bytes, &elgato_profile::tests::device(), "Switch").await.unwrap();
+		let mut instance = profile.pages[0].keys[0].clone().unwrap();
+		assert_eq!(instance.action.uuid, "opendeck.multiactionswitch");
+
+		// The action that is not installed is left out of the first sequence, and the children are numbered in order from 1.
+		let children = instance.children.iter().flatten().map(|child| (child.context.index, child.states[0].text.as_str())).collect::<Vec<_>>();
+		assert_eq!(children, vec![(1, "First A"), (2, "First B"), (3, "Second")]);
+		assert_eq!(instance.settings["sequences"], serde_json::json!({ "1": 0, "2": 0, "3": 1 }));
+
+		let indices = |instance: &ActionInstance| steps(instance).iter().map(|step| step.index).collect::<Vec<_>>();
+		assert_eq!(indices(&instance), vec![1, 2]);
+		press(&mut instance);
+		assert_eq!(instance.current_state, 1);
+		assert_eq!(indices(&instance), vec![3]);
+
+		// Pressing again while the first sequence is still running cancels it rather than running the second.
+		press(&mut instance);
+		assert_eq!(instance.current_state, 1);
+		press(&mut instance);
+		assert_eq!(instance.current_state, 0);
+		assert_eq!(indices(&instance), vec![1, 2]);
+	}
+}
@@ -0,0 +1,405 @@
+//! Pages of a profile, of which one is shown on its device at a time.
+//! Pages are switched between with the page actions, encoders and touchscreen swipes, or opened by folders.
+//! The positions of instances follow on from those on the pages before them, so the instance at a position is shown in slot `position % size`
+//! of page `position / size`, where `size` is the number of slots of its controller on the device.
+//! As positions are a single byte, a device fits at most `256 / size` pages, so a Stream Deck XL fits 8 pages including those of folders.
+//! Removing a page moves the instances on the pages after it back, which changes the contexts that plugins know them by.
+
+use crate::events::outbound::will_appear::{slot_will_appear, slot_will_disappear};
+use crate::shared::{ActionContext, ActionInstance, Context, DeviceInfo, Page, Profile, CATEGORIES, DEVICES};
+use crate::store::profiles::{acquire_locks_mut, LocksMut};
+
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use tauri::Emitter;
+
+/// The actions that switch page when their key is released.
+pub const PAGE_ACTIONS: [&str; 5] = ["opendeck.folder", "opendeck.back", "opendeck.nextpage", "opendeck.previouspage", "opendeck.gotopage"];
+/// The actions whose settings refer to a page of their profile.
+const TARGETED_ACTIONS: [&str; 3] = ["opendeck.folder", "opendeck.back", "opendeck.gotopage"];
+
+/// The page of the selected profile shown on each device.
+static CURRENT_PAGES: Lazy<DashMap<String, u8>> = Lazy::new(DashMap::new);
+
+fn slot_count(device: &DeviceInfo, controller: &str) -> usize {
+	match controller {
+		"Encoder" => device.encoders as usize,
+		_ => device.key_count(),
+	}
+}
+
+/// Get the number of slots on a page for a controller of a device.
+pub fn page_size(device: &str, controller: &str) -> u8 {
+	DEVICES.get(device).map_or(0, |device| u8::try_from(slot_count(&device, controller)).unwrap_or(u8::MAX))
+}
+
+pub fn current_page(device: &str) -> u8 {
+	CURRENT_PAGES.get(device).map_or(0, |page| *page)
+}
+
+/// Get the position in the selected profile of a slot of a device on its current page.
+pub fn profile_position(device: &str, controller: &str, slot: u8) -> u8 {
+	current_page(device).saturating_mul(page_size(device, controller)).saturating_add(slot)
+}
+
+/// Get the slot of its device that a position in a profile is shown in, or `None` if it is on a page that is not shown.
+pub fn device_slot(context: &Context) -> Option<u8> {
+	let size = page_size(&context.device, &context.controller);
+	if size == 0 {
+		return Some(context.position);
+	}
+	(context.position / size == current_page(&context.device)).then_some(context.position % size)
+}
+
+/// Split a position in a profile into the index of its page and its slot on that page.
+pub fn split_position(device: &DeviceInfo, controller: &str, position: u8) -> (usize, usize) {
+	match slot_count(device, controller) {
+		0 => (0, position as usize),
+		size => (position as usize / size, position as usize % size),
+	}
+}
+
+/// Make every page of a profile fill the layout of a device.
+/// A single page with more slots than the device has, as saved before pages were stored separately, is split into several pages.
+pub fn fit_pages(profile: &mut Profile, device: &DeviceInfo) {
+	let (key_size, slider_size) = (slot_count(device, "Keypad"), slot_count(device, "Encoder"));
+	if profile.pages.is_empty() {
+		profile.pages.push(Page::default());
+	}
+	let first = &profile.pages[0];
+	if profile.pages.len() == 1 && key_size > 0 && (first.keys.len() > key_size || (slider_size > 0 && first.sliders.len() > slider_size)) {
+		let Page { keys, sliders } = std::mem::take(&mut profile.pages[0]);
+		let count = keys.len().div_ceil(key_size).max(if slider_size > 0 { sliders.len().div_ceil(slider_size) } else { 1 });
+		let (mut keys, mut sliders) = (keys.into_iter(), sliders.into_iter());
+		profile.pages = (0..count)
+			.map(|_| Page {
+				keys: keys.by_ref().take(key_size).collect(),
+				sliders: sliders.by_ref().take(slider_size).collect(),
+			})
+			.collect();
+	}
+	for page in &mut profile.pages {
+		page.keys.resize(key_size, None);
+		page.sliders.resize(slider_size, None);
+	}
+}
+
+/// Get the instances in the slots of a page of a profile.
+pub fn page_instances(profile: &Profile, page: u8) -> impl Iterator<Item = &ActionInstance> {
+	profile.pages.get(page as usize).into_iter().flat_map(|page| page.keys.iter().chain(&page.sliders)).flatten()
+}
+
+/// Get the pages of a profile opened by folders on it.
+fn folder_pages(profile: &Profile) -> HashSet<u8> {
+	profile
+		.pages
+		.iter()
+		.flat_map(|page| page.keys.iter().flatten())
+		.filter(|instance| instance.action.uuid == "opendeck.folder")
+		.filter_map(|instance| instance.settings["page"].as_u64())
+		.map(|page| page as u8)
+		.collect()
+}
+
+/// Get the pages of a profile that are moved between by the page actions, encoders and touchscreen swipes, which are those not opened by folders.
+pub fn navigable_pages(profile: &Profile) -> Vec<u8> {
+	let folders = folder_pages(profile);
+	(0..profile.pages.len() as u8).filter(|page| !folders.contains(page)).collect()
+}
+
+/// Get the navigable page before or after a page, wrapping around at either end.
+pub fn adjacent_page(profile: &Profile, current: u8, forward: bool) -> u8 {
+	let pages = navigable_pages(profile);
+	let next = if forward {
+		pages.iter().find(|page| **page > current).or(pages.first())
+	} else {
+		pages.iter().rev().find(|page| **page < current).or(pages.last())
+	};
+	next.copied().unwrap_or(0)
+}
+
+#[derive(Clone, serde::Serialize)]
+struct PageChangedEvent {
+	device: String,
+	page: u8,
+}
+
+fn emit_page_changed(device: &str, page: u8) {
+	let event = PageChangedEvent { device: device.to_owned(), page };
+	if let Err(error) = crate::APP_HANDLE.get().unwrap().emit_to("main", "page_changed", event) {
+		log::warn!("Failed to notify frontend of page change: {}", error);
+	}
+}
+
+/// Show the first page of the selected profile of a device, such as after switching profiles, without sending any events to instances.
+pub fn reset_page(device: &str) {
+	if CURRENT_PAGES.remove(device).is_some_and(|(_, page)| page != 0) {
+		emit_page_changed(device, 0);
+	}
+}
+
+/// Show a page of the selected profile of a device, sending willDisappear to the instances on the current page and willAppear to those on the new page.
+pub async fn show_page(device: &str, page: u8) -> Result<(), anyhow::Error> {
+	let Some(info) = DEVICES.get(device).map(|device| device.value().clone()) else {
+		return Err(anyhow!("device {device} not found"));
+	};
+	let mut locks = acquire_locks_mut().await;
+	let old_page = current_page(device);
+	if old_page == page {
+		return Ok(());
+	}
+
+	let selected_profile = locks.device_stores.get_selected_profile(device)?;
+	let profile = &locks.profile_stores.get_profile_store_mut(&info, &selected_profile).await?.value;
+	if page as usize >= profile.pages.len() {
+		return Err(anyhow!("page {page} not found in profile {selected_profile}"));
+	}
+	for instance in page_instances(profile, old_page) {
+		slot_will_disappear(instance).await;
+	}
+	let _ = crate::events::outbound::devices::clear_screen(device.to_owned()).await;
+
+	CURRENT_PAGES.insert(device.to_owned(), page);
+	// The instances on the new page are drawn without holding the locks, so that other keys and devices are not blocked while they are rendered.
+	let shown = page_instances(profile, page).cloned().collect::<Vec<_>>();
+	drop(locks);
+	for instance in &shown {
+		slot_will_appear(instance).await;
+	}
+
+	emit_page_changed(device, page);
+	Ok(())
+}
+
+/// Move to the next or previous navigable page of the selected profile of a device.
+pub async fn step_page(device: &str, forward: bool) -> Result<(), anyhow::Error> {
+	let Some(info) = DEVICES.get(device).map(|device| device.value().clone()) else {
+		return Ok(());
+	};
+	let mut locks = acquire_locks_mut().await;
+	let selected_profile = locks.device_stores.get_selected_profile(device)?;
+	let profile = &locks.profile_stores.get_profile_store_mut(&info, &selected_profile).await?.value;
+	let page = adjacent_page(profile, current_page(device), forward);
+	drop(locks);
+	show_page(device, page).await
+}
+
+/// Switch to the page that an instance of a page action leads to.
+pub async fn navigate(instance: &ActionInstance) -> Result<(), anyhow::Error> {
+	let device = &instance.context.device;
+	match instance.action.uuid.as_str() {
+		"opendeck.nextpage" => step_page(device, true).await,
+		"opendeck.previouspage" => step_page(device, false).await,
+		_ => show_page(device, instance.settings["page"].as_u64().unwrap_or(0) as u8).await,
+	}
+}
+
+/// Add an empty page to the end of a profile, returning its index.
+fn push_page(profile: &mut Profile, device: &DeviceInfo) -> Result<usize, anyhow::Error> {
+	let (key_size, slider_size) = (slot_count(device, "Keypad"), slot_count(device, "Encoder"));
+	let page = profile.pages.len();
+	// Positions are stored as a single byte, which limits how many pages fit in a profile.
+	if (page + 1) * key_size > 256 || (page + 1) * slider_size > 256 {
+		let limit = 256 / key_size.max(slider_size).max(1);
+		return Err(anyhow!(
+			"profile {} already has the most pages that fit on this device ({limit}, counting the pages of folders), as each key is numbered with a single byte across all of its pages",
+			profile.id
+		));
+	}
+	profile.pages.push(Page {
+		keys: vec![None; key_size],
+		sliders: vec![None; slider_size],
+	});
+	Ok(page)
+}
+
+/// Add an empty page to the end of the selected profile of a device, returning its index.
+pub async fn add_page(device: &str) -> Result<u8, anyhow::Error> {
+	let info = DEVICES.get(device).map(|device| device.value().clone()).ok_or_else(|| anyhow!("device {device} not found"))?;
+	let mut locks = acquire_locks_mut().await;
+	let selected_profile = locks.device_stores.get_selected_profile(device)?;
+	let store = locks.profile_stores.get_profile_store_mut(&info, &selected_profile).await?;
+	let page = push_page(&mut store.value, &info)?;
+	store.save()?;
+	Ok(page as u8)
+}
+
+/// Give a new folder an empty page of its profile to open, with a back key in the first slot that returns to the page of the folder.
+pub async fn add_folder_page(context: &Context, locks: &mut LocksMut<'_>) -> Result<(), anyhow::Error> {
+	let device = DEVICES.get(&context.device).map(|device| device.value().clone()).ok_or_else(|| anyhow!("device not found"))?;
+	let back = CATEGORIES
+		.read()
+		.await
+		.values()
+		.flatten()
+		.find(|action| action.uuid == "opendeck.back")
+		.cloned()
+		.ok_or_else(|| anyhow!("back action not found"))?;
+
+	let profile = &mut locks.profile_stores.get_profile_store_mut(&device, &context.profile).await?.value;
+	let page = push_page(profile, &device)?;
+	let (parent_page, slot) = split_position(&device, &context.controller, context.position);
+	if let Some(folder) = &mut profile.pages[parent_page].keys[slot] {
+		folder.settings["page"] = page.into();
+	}
+	profile.pages[page].keys[0] = Some(ActionInstance {
+		action: back.clone(),
+		context: ActionContext {
+			position: (page * slot_count(&device, "Keypad")) as u8,
+			..ActionContext::from_context(context.clone(), 0)
+		},
+		states: back.states.clone(),
+		current_state: 0,
+		settings: serde_json::json!({ "page": parent_page }),
+		children: None,
+		dial: Default::default(),
+	});
+
+	Ok(())
+}
+
+/// Get a page of a profile along with the pages of any folders on it, and of any folders on those pages in turn.
+fn page_with_folders(profile: &Profile, page: u8) -> HashSet<u8> {
+	let mut pages = HashSet::new();
+	let mut pending = vec![page];
+	while let Some(page) = pending.pop() {
+		if !pages.insert(page) {
+			continue;
+		}
+		let folders = page_instances(profile, page).filter(|instance| instance.action.uuid == "opendeck.folder");
+		pending.extend(folders.filter_map(|folder| folder.settings["page"].as_u64()).map(|page| page as u8));
+	}
+	pages
+}
+
+/// Get the index that a page moves to once pages before it have been removed.
+fn shifted_page(page: u8, removed: &HashSet<u8>) -> u8 {
+	page - removed.iter().filter(|removed| **removed < page).count() as u8
+}
+
+/// Remove pages from a profile, moving the pages after them back to fill the gaps.
+/// The instances on moved pages are given the contexts of their new positions, and the pages that page actions refer to are updated to match.
+/// None of the pages may be shown on the device, as no events are sent to the instances.
+async fn remove_pages(profile: &mut Profile, device: &DeviceInfo, removed: &HashSet<u8>) {
+	let pages = std::mem::take(&mut profile.pages);
+	for (old, mut page) in pages.into_iter().enumerate() {
+		let old = old as u8;
+		if removed.contains(&old) {
+			for instance in page.keys.into_iter().chain(page.sliders).flatten() {
+				crate::render::animation::stop(&(&instance.context).into()).await;
+				let dirs: Vec<_> = std::iter::once(&instance)
+					.chain(instance.children.iter().flatten())
+					.map(|instance| crate::events::frontend::instances::instance_images_dir(&instance.context))
+					.collect();
+				for dir in dirs {
+					let _ = tokio::fs::remove_dir_all(dir).await;
+				}
+			}
+			continue;
+		}
+
+		let new = shifted_page(old, removed);
+		for slot in page.keys.iter_mut().chain(page.sliders.iter_mut()) {
+			let Some(mut instance) = slot.take() else { continue };
+			if new != old {
+				crate::render::animation::stop(&(&instance.context).into()).await;
+				let old_dirs: Vec<_> = std::iter::once(&instance)
+					.chain(instance.children.iter().flatten())
+					.map(|instance| crate::events::frontend::instances::instance_images_dir(&instance.context))
+					.collect();
+				let size = slot_count(device, &instance.context.controller);
+				let context = ActionContext {
+					position: instance.context.position - ((old - new) as usize * size) as u8,
+					..instance.context.clone()
+				};
+				instance = crate::store::remap::relocate(instance, context);
+				for dir in old_dirs {
+					let _ = tokio::fs::remove_dir_all(dir).await;
+				}
+			}
+			if TARGETED_ACTIONS.contains(&instance.action.uuid.as_str()) {
+				if let Some(target) = instance.settings["page"].as_u64() {
+					let target = target as u8;
+					instance.settings["page"] = if removed.contains(&target) { 0 } else { shifted_page(target, removed) }.into();
+				}
+			}
+			*slot = Some(instance);
+		}
+		profile.pages.push(page);
+	}
+}
+
+/// Remove a page from the selected profile of a device along with the pages of any folders on it, and show the page before it if it was shown.
+pub async fn remove_page(device: &str, page: u8) -> Result<(), anyhow::Error> {
+	let info = DEVICES.get(device).map(|device| device.value().clone()).ok_or_else(|| anyhow!("device {device} not found"))?;
+	let mut locks = acquire_locks_mut().await;
+	let selected_profile = locks.device_stores.get_selected_profile(device)?;
+	let store = locks.profile_stores.get_profile_store_mut(&info, &selected_profile).await?;
+	if page == 0 {
+		return Err(anyhow!("the first page of a profile cannot be removed"));
+	}
+	if page as usize >= store.value.pages.len() {
+		return Err(anyhow!("page {page} not found in profile {selected_profile}"));
+	}
+	if folder_pages(&store.value).contains(&page) {
+		return Err(anyhow!("the page of a folder is removed by removing the folder"));
+	}
+
+	// The pages after those removed move back, so the current page is hidden and then shown again in its new position.
+	let current = current_page(device);
+	for instance in page_instances(&store.value, current) {
+		slot_will_disappear(instance).await;
+	}
+	let _ = crate::events::outbound::devices::clear_screen(device.to_owned()).await;
+
+	let removed = page_with_folders(&store.value, page);
+	let next = if removed.contains(&current) {
+		navigable_pages(&store.value).into_iter().filter(|page| !removed.contains(page) && *page < current).max().unwrap_or(0)
+	} else {
+		current
+	};
+	let next = shifted_page(next, &removed);
+	remove_pages(&mut store.value, &info, &removed).await;
+	store.save()?;
+
+	CURRENT_PAGES.insert(device.to_owned(), next);
+	let shown = page_instances(&store.value, next).cloned().collect::<Vec<_>>();
+	drop(locks);
+	for instance in &shown {
+		slot_will_appear(instance).await;
+	}
+
+	emit_page_changed(device, next);
+	Ok(())
+}
+
+/// Remove the page opened by a folder and the pages of any folders within it, which must not be shown on the device.
+/// If the page shown moves back to fill their place, the instances on it are returned so that the caller can show them once it releases the locks.
+pub async fn remove_folder_page(folder: &ActionInstance, locks: &mut LocksMut<'_>) -> Result<Vec<ActionInstance>, anyhow::Error> {
+	let Some(page) = folder.settings["page"].as_u64() else { return Ok(vec![]) };
+	let device = DEVICES.get(&folder.context.device).map(|device| device.value().clone()).ok_or_else(|| anyhow!("device not found"))?;
+	let shown = locks.device_stores.get_selected_profile(&folder.context.device)? == folder.context.profile;
+	let profile = &mut locks.profile_stores.get_profile_store_mut(&device, &folder.context.profile).await?.value;
+	let removed = page_with_folders(profile, page as u8);
+
+	// The page being shown moves back too if it comes after the removed pages.
+	let current = current_page(&folder.context.device);
+	let moves = shown && removed.iter().any(|removed| *removed < current);
+	if moves {
+		for instance in page_instances(profile, current) {
+			slot_will_disappear(instance).await;
+		}
+		let _ = crate::events::outbound::devices::clear_screen(folder.context.device.clone()).await;
+	}
+	remove_pages(profile, &device, &removed).await;
+	if !moves {
+		return Ok(vec![]);
+	}
+	let current = shifted_page(current, &removed);
+	CURRENT_PAGES.insert(folder.context.device.clone(), current);
+	emit_page_changed(&folder.context.device, current);
+	Ok(page_instances(profile, current).cloned().collect())
+}
@@ -9,6 +9,25 @@ pub struct OS {
 	pub platform: String,
 }
 
+#[allow(dead_code)]
+#[serde_inline_default]
+#[derive(Deserialize)]
+pub struct ProfileManifest {
+	#[serde(alias = "Name")]
+	pub name: String,
+
+	#[serde(alias = "DeviceType")]
+	pub device_type: u8,
+
+	#[serde_inline_default(false)]
+	#[serde(alias = "ReadOnly", alias = "Readonly")]
+	pub readonly: bool,
+
+	#[serde_inline_default(false)]
+	#[serde(alias = "DontAutoSwitchWhenInstalled")]
+	pub dont_auto_switch_when_installed: bool,
+}
+
 #[allow(dead_code)]
 #[serde_inline_default]
 #[derive(Deserialize)]
@@ -52,6 +71,10 @@ pub struct PluginManifest {
 
 	#[serde(alias = "DeviceNamespace")]
 	pub device_namespace: Option<String>,
+
+	#[serde_inline_default(vec![])]
+	#[serde(alias = "Profiles")]
+	pub profiles: Vec<ProfileManifest>,
 }
 
 pub fn read_manifest(base_path: &std::path::Path) -> Result<PluginManifest, anyhow::Error> {
@@ -48,6 +48,12 @@ pub async fn initialise_plugin(path: &path::Path) -> anyhow::Result<()> {
 			action.property_inspector = path.join(property_inspector).to_string_lossy().to_string();
 		}
 
+		if let Some(encoder) = &mut action.encoder {
+			if !encoder.layout.starts_with('$') {
+				encoder.layout = path.join(&encoder.layout).to_string_lossy().to_string();
+			}
+		}
+
 		for state in &mut action.states {
 			if state.image == "actionDefaultImage" {
 				state.image.clone_from(&action.icon);
@@ -56,19 +62,9 @@ pub async fn initialise_plugin(path: &path::Path) -> anyhow::Result<()> {
 				state.image = convert_icon(state_icon.to_str().unwrap().to_owned());
 			}
 
-			match state.family.clone().to_lowercase().trim() {
-				"arial" => "Liberation Sans",
-				"arial black" => "Archivo Black",
-				"comic sans ms" => "Comic Neue",
-				"courier" | "Courier New" => "Courier Prime",
-				"georgia" => "Tinos",
-				"impact" => "Anton",
-				"microsoft sans serif" | "Times New Roman" => "Liberation Serif",
-				"tahoma" | "Verdana" => "Open Sans",
-				"trebuchet ms" => "Fira Sans",
-				_ => continue,
+			if let Some(family) = crate::render::fonts::canonical_family(&state.family) {
+				family.clone_into(&mut state.family);
 			}
-			.clone_into(&mut state.family);
 		}
 	}
 
@@ -93,7 +89,11 @@ pub async fn initialise_plugin(path: &path::Path) -> anyhow::Result<()> {
 	}
 
 	if let Some(namespace) = manifest.device_namespace {
-		DEVICE_NAMESPACES.write().await.insert(namespace, plugin_uuid.to_owned());
+		if namespace == "sd" || namespace == crate::virtual_devices::NAMESPACE {
+			log::warn!("Plugin {} cannot use the reserved device namespace {}", plugin_uuid, namespace);
+		} else {
+			DEVICE_NAMESPACES.write().await.insert(namespace, plugin_uuid.to_owned());
+		}
 	}
 
 	#[cfg(target_os = "windows")]
@@ -145,6 +145,10 @@ pub async fn initialise_plugin(path: &path::Path) -> anyhow::Result<()> {
 	let args = ["-port", "57116", "-pluginUUID", plugin_uuid, "-registerEvent", "registerPlugin", "-info"];
 
 	if code_path.to_lowercase().ends_with(".html") || code_path.to_lowercase().ends_with(".htm") || code_path.to_lowercase().ends_with(".xhtml") {
+		if *crate::shared::HEADLESS {
+			return Err(anyhow!("plugins that run in a webview are not supported in headless mode"));
+		}
+
 		let url = "http://localhost:57118/".to_owned() + path.join(code_path).to_str().unwrap();
 		let window = tauri::WebviewWindowBuilder::new(APP_HANDLE.get().unwrap(), plugin_uuid.replace('.', "_"), tauri::WebviewUrl::External(url.parse()?))
 			.title(plugin_uuid)
@@ -311,6 +315,83 @@ pub async fn deactivate_plugin(app: &AppHandle, uuid: &str) -> Result<(), anyhow
 	}
 }
 
+/// Get the ID of a profile bundled with a plugin.
+/// Profiles bundled with plugins are kept in a folder named after the plugin, with periods replaced as they delimit action contexts.
+pub fn bundled_profile_id(plugin: &str, name: &str) -> String {
+	let plugin = plugin.strip_suffix(".sdPlugin").unwrap_or(plugin);
+	let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+	format!("{}/{}", plugin.replace('.', "_"), name.replace('.', "_"))
+}
+
+/// Import the profiles bundled with a plugin for each connected device of the type they were made for.
+/// Profiles that have already been imported are left untouched so that changes made to them are kept.
+pub async fn install_profiles(path: &path::Path) -> anyhow::Result<()> {
+	install_profiles_for(path, None).await
+}
+
+/// Import the profiles bundled with every installed plugin for a device that has just been connected.
+/// Devices are not switched to profiles installed this way, as the user did not ask for the plugin to be installed just now.
+pub async fn install_device_profiles(device: &str) {
+	let Ok(entries) = fs::read_dir(config_dir().join("plugins")) else { return };
+	for entry in entries.flatten() {
+		let path = entry.path();
+		// Skip the backups of builtin plugins made when they are upgraded.
+		if !path.extension().is_some_and(|extension| extension == "sdPlugin") {
+			continue;
+		}
+		if let Err(error) = install_profiles_for(&path, Some(device)).await {
+			warn!("Failed to install profiles bundled with plugin {} for device {}: {:#}", path.display(), device, error);
+		}
+	}
+}
+
+/// Import the profiles bundled with a plugin, either for all connected devices or only for one of them without switching to them.
+async fn install_profiles_for(path: &path::Path, only_device: Option<&str>) -> anyhow::Result<()> {
+	let plugin_uuid = path.file_name().unwrap().to_str().unwrap();
+	let manifest = manifest::read_manifest(path)?;
+
+	for bundled in manifest.profiles {
+		let devices = crate::shared::DEVICES
+			.iter()
+			.filter(|device| device.r#type == bundled.device_type && only_device.map_or(true, |id| device.id == id))
+			.map(|device| device.value().clone())
+			.collect::<Vec<_>>();
+		if devices.is_empty() {
+			continue;
+		}
+
+		let bytes = match fs::read(path.join(format!("{}.streamDeckProfile", bundled.name))) {
+			Ok(bytes) => bytes,
+			Err(error) => {
+				warn!("Failed to read profile {} bundled with plugin {}: {}", bundled.name, plugin_uuid, error);
+				continue;
+			}
+		};
+
+		let id = bundled_profile_id(plugin_uuid, &bundled.name);
+		for device in devices {
+			if crate::store::profiles::get_device_profiles(&device.id)?.contains(&id) {
+				continue;
+			}
+
+			let profile = match crate::store::elgato_profile::import(&bytes, &device, &id).await {
+				Ok(profile) => profile,
+				Err(error) => {
+					warn!("Failed to import profile {} bundled with plugin {}: {}", bundled.name, plugin_uuid, error);
+					break;
+				}
+			};
+			crate::store::profiles::PROFILE_STORES.write().await.insert_profile(&device.id, profile)?;
+
+			if only_device.is_none() && !bundled.dont_auto_switch_when_installed {
+				crate::events::frontend::profiles::request_switch_profile(device.id.clone(), id.clone()).await?;
+			}
+		}
+	}
+
+	Ok(())
+}
+
 /// Initialise plugins from the plugins directory.
 pub fn initialise_plugins() {
 	tokio::spawn(init_websocket_server());
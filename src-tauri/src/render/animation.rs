@@ -0,0 +1,136 @@
+//! Playback of animated key images in the backend, with a task per key that sends each frame to the device after the delay of the previous one.
+
+use crate::shared::Context;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use image::{DynamicImage, RgbaImage};
+use once_cell::sync::Lazy;
+use tokio::sync::{Mutex, Notify};
+use tokio::task::JoinHandle;
+
+/// A decoded frame of an image and how long it is shown for.
+pub struct Frame {
+	pub image: DynamicImage,
+	pub delay: Duration,
+}
+
+struct Playback {
+	task: JoinHandle<()>,
+	stop: Arc<Notify>,
+}
+
+struct Animation {
+	/// The image the frames were decoded from, used to continue the animation when the same image is shown again.
+	source: String,
+	frames: Arc<Vec<Frame>>,
+	/// The index of the next frame to be shown.
+	position: Arc<AtomicUsize>,
+	/// The task sending frames to the device, which is absent while the animation is paused.
+	playback: Option<Playback>,
+}
+
+static ANIMATIONS: Lazy<Mutex<HashMap<Context, Animation>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+impl Animation {
+	/// Stop the task sending frames, waiting for it to finish so that it cannot send a frame after an image set afterwards.
+	async fn pause(&mut self) {
+		if let Some(playback) = self.playback.take() {
+			playback.stop.notify_one();
+			let _ = playback.task.await;
+		}
+	}
+}
+
+/// Get the frames previously decoded from an image for a key, if the key is animated with that image.
+pub async fn cached_frames(context: &Context, source: &str) -> Option<Arc<Vec<Frame>>> {
+	ANIMATIONS
+		.lock()
+		.await
+		.get(context)
+		.filter(|animation| animation.source == source)
+		.map(|animation| animation.frames.clone())
+}
+
+/// Play an animation on a key, continuing from the frame it was on if the key was already animated with the same image.
+/// Each frame is passed through `render`, such as to draw the title of the key over it, before being sent to the device.
+pub async fn play(context: Context, source: String, frames: Arc<Vec<Frame>>, render: impl Fn(&DynamicImage) -> RgbaImage + Send + Sync + 'static) {
+	let render = Arc::new(render);
+	let mut animations = ANIMATIONS.lock().await;
+	let position = match animations.get_mut(&context) {
+		Some(animation) => {
+			animation.pause().await;
+			if animation.source == source {
+				animation.position.clone()
+			} else {
+				Arc::new(AtomicUsize::new(0))
+			}
+		}
+		None => Arc::new(AtomicUsize::new(0)),
+	};
+
+	let stop = Arc::new(Notify::new());
+	let task = tokio::spawn({
+		let (context, frames, position, stop) = (context.clone(), frames.clone(), position.clone(), stop.clone());
+		async move {
+			loop {
+				if !crate::shared::DEVICES.contains_key(&context.device) {
+					break;
+				}
+
+				let index = position.load(Ordering::Relaxed) % frames.len();
+				let (rendered_frames, render) = (frames.clone(), render.clone());
+				let image = match tokio::task::spawn_blocking(move || super::to_data_url(&DynamicImage::ImageRgba8(render(&rendered_frames[index].image)))).await {
+					Ok(Ok(image)) => image,
+					Ok(Err(error)) => {
+						log::warn!("Failed to encode frame of animated image: {}", error);
+						break;
+					}
+					Err(error) => {
+						log::warn!("Failed to render frame of animated image: {}", error);
+						break;
+					}
+				};
+				let frame = &frames[index];
+				if let Err(error) = crate::events::outbound::devices::update_image(context.clone(), Some(image)).await {
+					log::warn!("Failed to update device image: {}", error);
+					break;
+				}
+				position.store((index + 1) % frames.len(), Ordering::Relaxed);
+
+				tokio::select! {
+					_ = tokio::time::sleep(frame.delay) => {}
+					_ = stop.notified() => break,
+				}
+			}
+		}
+	});
+
+	animations.insert(
+		context,
+		Animation {
+			source,
+			frames,
+			position,
+			playback: Some(Playback { task, stop }),
+		},
+	);
+}
+
+/// Pause the animation on a key, such as when its profile is switched away from, keeping the frame it was on.
+pub async fn pause(context: &Context) {
+	if let Some(animation) = ANIMATIONS.lock().await.get_mut(context) {
+		animation.pause().await;
+	}
+}
+
+/// Stop the animation on a key and discard its frames, such as when a still image is set or the instance is removed.
+pub async fn stop(context: &Context) {
+	let animation = ANIMATIONS.lock().await.remove(context);
+	if let Some(mut animation) = animation {
+		animation.pause().await;
+	}
+}
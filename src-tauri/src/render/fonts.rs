@@ -0,0 +1,124 @@
+//! A registry of the fonts bundled with OpenDeck, used to render titles in the backend.
+
+use ab_glyph::FontRef;
+
+pub const DEFAULT_FAMILY: &str = "Liberation Sans";
+
+struct Family {
+	name: &'static str,
+	/// Names of commonly used proprietary fonts that this family is metrically compatible with or similar to.
+	aliases: &'static [&'static str],
+	regular: &'static [u8],
+	bold: Option<&'static [u8]>,
+	italic: Option<&'static [u8]>,
+	bold_italic: Option<&'static [u8]>,
+}
+
+macro_rules! font {
+	($file:literal) => {
+		include_bytes!(concat!("../../../static/fonts/", $file))
+	};
+}
+
+static FAMILIES: &[Family] = &[
+	Family {
+		name: "Liberation Sans",
+		aliases: &["arial", "helvetica"],
+		regular: font!("LiberationSans-Regular.ttf"),
+		bold: Some(font!("LiberationSans-Bold.ttf")),
+		italic: Some(font!("LiberationSans-Italic.ttf")),
+		bold_italic: Some(font!("LiberationSans-BoldItalic.ttf")),
+	},
+	Family {
+		name: "Liberation Serif",
+		aliases: &["microsoft sans serif", "times new roman"],
+		regular: font!("LiberationSerif-Regular.ttf"),
+		bold: Some(font!("LiberationSerif-Bold.ttf")),
+		italic: Some(font!("LiberationSerif-Italic.ttf")),
+		bold_italic: Some(font!("LiberationSerif-BoldItalic.ttf")),
+	},
+	Family {
+		name: "Archivo Black",
+		aliases: &["arial black"],
+		regular: font!("ArchivoBlack-Regular.ttf"),
+		bold: None,
+		italic: None,
+		bold_italic: None,
+	},
+	Family {
+		name: "Comic Neue",
+		aliases: &["comic sans ms"],
+		regular: font!("ComicNeue-Regular.ttf"),
+		bold: Some(font!("ComicNeue-Bold.ttf")),
+		italic: Some(font!("ComicNeue-Italic.ttf")),
+		bold_italic: Some(font!("ComicNeue-BoldItalic.ttf")),
+	},
+	Family {
+		name: "Courier Prime",
+		aliases: &["courier", "courier new"],
+		regular: font!("CourierPrime-Regular.ttf"),
+		bold: Some(font!("CourierPrime-Bold.ttf")),
+		italic: Some(font!("CourierPrime-Italic.ttf")),
+		bold_italic: Some(font!("CourierPrime-BoldItalic.ttf")),
+	},
+	Family {
+		name: "Tinos",
+		aliases: &["georgia"],
+		regular: font!("Tinos-Regular.ttf"),
+		bold: Some(font!("Tinos-Bold.ttf")),
+		italic: Some(font!("Tinos-Italic.ttf")),
+		bold_italic: Some(font!("Tinos-BoldItalic.ttf")),
+	},
+	Family {
+		name: "Anton",
+		aliases: &["impact"],
+		regular: font!("Anton-Regular.ttf"),
+		bold: None,
+		italic: None,
+		bold_italic: None,
+	},
+	Family {
+		name: "Open Sans",
+		aliases: &["tahoma", "verdana"],
+		regular: font!("OpenSans-VariableFont_wdth,wght.ttf"),
+		bold: None,
+		italic: Some(font!("OpenSans-Italic-VariableFont_wdth,wght.ttf")),
+		bold_italic: None,
+	},
+	Family {
+		name: "Fira Sans",
+		aliases: &["trebuchet ms"],
+		regular: font!("FiraSans-Regular.ttf"),
+		bold: Some(font!("FiraSans-Bold.ttf")),
+		italic: Some(font!("FiraSans-Italic.ttf")),
+		bold_italic: Some(font!("FiraSans-BoldItalic.ttf")),
+	},
+];
+
+/// Get the contents of every face of every bundled family.
+pub fn all_faces() -> impl Iterator<Item = &'static [u8]> {
+	FAMILIES.iter().flat_map(|family| [Some(family.regular), family.bold, family.italic, family.bold_italic]).flatten()
+}
+
+fn find_family(name: &str) -> Option<&'static Family> {
+	let name = name.trim().to_lowercase();
+	FAMILIES.iter().find(|family| family.name.to_lowercase() == name || family.aliases.contains(&name.as_str()))
+}
+
+/// Get the name of the bundled family used in place of a font family, if there is one.
+pub fn canonical_family(name: &str) -> Option<&'static str> {
+	find_family(name).map(|family| family.name)
+}
+
+/// Get the face of a font family for a style such as "Bold Italic", falling back to the regular face and then to the default family.
+pub fn get_font(family: &str, style: &str) -> FontRef<'static> {
+	let family = find_family(family).unwrap_or(&FAMILIES[0]);
+	let (bold, italic) = (style.contains("Bold"), style.contains("Italic"));
+	let face = match (bold, italic) {
+		(true, true) => family.bold_italic.or(family.bold).or(family.italic),
+		(true, false) => family.bold,
+		(false, true) => family.italic,
+		(false, false) => None,
+	};
+	FontRef::try_from_slice(face.unwrap_or(family.regular)).unwrap()
+}
@@ -0,0 +1,292 @@
+//! Rendering of key images from the states of action instances, matching the renderer previously used by the frontend.
+
+use super::animation::{self, Frame};
+use super::{draw_text, fill_rect, fonts, load_frames, overlay, parse_colour, text_width};
+use crate::shared::{ActionInstance, ActionState, Context};
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use ab_glyph::PxScale;
+use image::{imageops::FilterType, DynamicImage, Rgba, RgbaImage};
+
+/// The size at which key images are rendered, which matches the size of the keys of the Stream Deck XL.
+pub const KEY_SIZE: u32 = 144;
+
+/// Load an image bundled with OpenDeck from its path in the frontend's static directory.
+fn load_builtin_image(name: &str) -> Option<DynamicImage> {
+	let bytes: &[u8] = match name {
+		"alert.png" => include_bytes!("../../../static/alert.png"),
+		"back.png" => include_bytes!("../../../static/back.png"),
+		"brightness.png" => include_bytes!("../../../static/brightness.png"),
+		"cube.png" => include_bytes!("../../../static/cube.png"),
+		"folder.png" => include_bytes!("../../../static/folder.png"),
+		"gesture-action.png" => include_bytes!("../../../static/gesture-action.png"),
+		"go-to-page.png" => include_bytes!("../../../static/go-to-page.png"),
+		"hotkey.png" => include_bytes!("../../../static/hotkey.png"),
+		"multi-action.png" => include_bytes!("../../../static/multi-action.png"),
+		"multi-action-switch.png" => include_bytes!("../../../static/multi-action-switch.png"),
+		"next-page.png" => include_bytes!("../../../static/next-page.png"),
+		"ok.png" => include_bytes!("../../../static/ok.png"),
+		"page-dial.png" => include_bytes!("../../../static/page-dial.png"),
+		"previous-page.png" => include_bytes!("../../../static/previous-page.png"),
+		"run-command.png" => include_bytes!("../../../static/run-command.png"),
+		"switch-profile.png" => include_bytes!("../../../static/switch-profile.png"),
+		"toggle-action.png" => include_bytes!("../../../static/toggle-action.png"),
+		"wait.png" => include_bytes!("../../../static/wait.png"),
+		_ => return None,
+	};
+	image::load_from_memory(bytes).ok()
+}
+
+/// Decode the frames of an image for a key and scale them to the size of keys.
+fn decode_frames(source: &str) -> Option<Vec<Frame>> {
+	let frames = if let Some(name) = source.strip_prefix("opendeck/") {
+		load_builtin_image(name).map(|image| vec![Frame { image, delay: Duration::ZERO }])
+	} else {
+		match load_frames(source) {
+			Ok(frames) => Some(frames),
+			Err(error) => {
+				log::debug!("Failed to load image for key: {}", error);
+				None
+			}
+		}
+	};
+	frames.map(|frames| {
+		frames
+			.into_iter()
+			.map(|frame| Frame {
+				image: frame.image.resize_exact(KEY_SIZE, KEY_SIZE, FilterType::Triangle),
+				delay: frame.delay,
+			})
+			.collect()
+	})
+}
+
+/// Load the frames of the image of a state on a key, falling back to another image, such as the image of the state in the action's definition, if it cannot be loaded.
+/// Frames already decoded for an animation on the key are reused instead of being decoded again.
+async fn load_state_frames(context: &Context, image: &str, fallback: Option<&str>) -> Option<(String, Arc<Vec<Frame>>)> {
+	for source in [Some(image), fallback].into_iter().flatten().map(str::trim).filter(|source| !source.is_empty()) {
+		if let Some(frames) = animation::cached_frames(context, source).await {
+			return Some((source.to_owned(), frames));
+		}
+
+		let owned = source.to_owned();
+		if let Ok(Some(frames)) = tokio::task::spawn_blocking(move || decode_frames(&owned)).await {
+			return Some((source.to_owned(), Arc::new(frames)));
+		}
+	}
+	None
+}
+
+/// Draw the title of a state onto a key image.
+fn draw_title(canvas: &mut RgbaImage, state: &ActionState) {
+	if !state.show || state.text.is_empty() {
+		return;
+	}
+
+	let scale = canvas.width() as f32 / KEY_SIZE as f32;
+	let font = fonts::get_font(&state.family, &state.style);
+	let size = state.size.0 as f32 * 2.0 * scale;
+	let px_scale = PxScale::from(size);
+	let colour = parse_colour(&state.colour).unwrap_or(Rgba([255, 255, 255, 255]));
+	let outline = Rgba([0, 0, 0, 255]);
+	let line_width = 3.0 * scale;
+
+	let lines = state.text.split('\n').collect::<Vec<_>>();
+	let height = canvas.height() as f32;
+	let top = match state.alignment.as_str() {
+		"top" => -(size * 0.2),
+		"bottom" => height - size * lines.len() as f32 - line_width,
+		_ => height / 2.0 - size * lines.len() as f32 * 0.5,
+	};
+
+	for (index, line) in lines.iter().enumerate() {
+		let width = text_width(&font, px_scale, line);
+		let x = canvas.width() as f32 / 2.0 - width / 2.0;
+		let y = top + size * index as f32;
+
+		// Approximate the stroke drawn around text by drawing it offset in each direction.
+		let radius = line_width / 2.0;
+		for step in 0..8 {
+			let angle = step as f32 * std::f32::consts::FRAC_PI_4;
+			draw_text(canvas, &font, px_scale, outline, x + radius * angle.cos(), y + radius * angle.sin(), line);
+		}
+		draw_text(canvas, &font, px_scale, colour, x, y, line);
+
+		if state.underline {
+			let (x, y) = (x.round() as i32, (y + size).round() as i32);
+			let pixels = |value: f32| (value * scale).round() as i32;
+			fill_rect(canvas, x - pixels(3.0), y, width.round() as i32 + pixels(6.0), pixels(9.0), outline);
+			fill_rect(canvas, x, y + pixels(4.0), width.round() as i32, pixels(3.0), colour);
+		}
+	}
+}
+
+/// Render the image of a key from a state and its loaded image.
+/// Keys whose image could not be loaded show an alert, and pressed keys are drawn smaller.
+pub fn render_state(image: Option<&DynamicImage>, state: &ActionState, size: u32, pressed: bool) -> RgbaImage {
+	let mut canvas = RgbaImage::from_pixel(size, size, Rgba([0, 0, 0, 255]));
+
+	if let Some(image) = image {
+		overlay(&mut canvas, &image.resize_exact(size, size, FilterType::Triangle).to_rgba8(), 0, 0, 1.0);
+	}
+	draw_title(&mut canvas, state);
+	if image.is_none() {
+		if let Some(alert) = load_builtin_image("alert.png") {
+			overlay(&mut canvas, &alert.resize_exact(size, size, FilterType::Triangle).to_rgba8(), 0, 0, 1.0);
+		}
+	}
+
+	if pressed {
+		let margin = (size as f32 * 0.1).round() as u32;
+		let smaller = image::imageops::resize(&canvas, size - margin * 2, size - margin * 2, FilterType::Triangle);
+		canvas = RgbaImage::from_pixel(size, size, Rgba([0, 0, 0, 255]));
+		overlay(&mut canvas, &smaller, margin as i32, margin as i32, 1.0);
+	}
+
+	canvas
+}
+
+/// Render the current state of an instance on a key and send it to its device, playing it as an animation if its image has multiple frames.
+/// Images are decoded and drawn on the blocking thread pool, so callers should not hold the profile locks while awaiting this.
+/// Instances that are children of multi actions or toggle actions, and instances not on keys, are not rendered.
+pub async fn update_key(instance: &ActionInstance, pressed: bool) -> Result<(), anyhow::Error> {
+	if instance.context.index != 0 || instance.context.controller != "Keypad" {
+		return Ok(());
+	}
+	let Some(state) = instance.states.get(instance.current_state as usize) else { return Ok(()) };
+
+	let context: Context = (&instance.context).into();
+	let fallback = instance
+		.action
+		.states
+		.get(instance.current_state as usize)
+		.map(|state| state.image.as_str())
+		.unwrap_or(&instance.action.icon);
+	match load_state_frames(&context, &state.image, Some(fallback)).await {
+		Some((source, frames)) if frames.len() > 1 => {
+			let state = state.clone();
+			animation::play(context, source, frames, move |image| render_state(Some(image), &state, KEY_SIZE, pressed)).await;
+			Ok(())
+		}
+		frames => {
+			animation::stop(&context).await;
+			let state = state.clone();
+			let image = tokio::task::spawn_blocking(move || {
+				let image = frames.as_ref().map(|(_, frames)| &frames[0].image);
+				super::to_data_url(&DynamicImage::ImageRgba8(render_state(image, &state, KEY_SIZE, pressed)))
+			})
+			.await??;
+			crate::events::outbound::devices::update_image(context, Some(image)).await
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::path::PathBuf;
+
+	/// Compare a rendered key with its golden image, or record the golden image instead if `UPDATE_GOLDEN` is set.
+	/// Channels may differ slightly, so that the comparison is not broken by differences in floating point rounding between platforms.
+	fn assert_golden(name: &str, image: &RgbaImage) {
+		let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("golden").join("keys").join(format!("{name}.png"));
+		if std::env::var_os("UPDATE_GOLDEN").is_some() {
+			std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+			image.save(&path).unwrap();
+			return;
+		}
+		assert!(
+			path.exists(),
+			"golden image {} is missing, and can be recorded by running the tests with UPDATE_GOLDEN set",
+			path.display()
+		);
+
+		let golden = image::open(&path).unwrap().to_rgba8();
+		assert_eq!(golden.dimensions(), image.dimensions(), "size of {name} differs from its golden image");
+		let differing = golden
+			.pixels()
+			.zip(image.pixels())
+			.filter(|(expected, actual)| expected.0.iter().zip(actual.0.iter()).any(|(expected, actual)| expected.abs_diff(*actual) > 2))
+			.count();
+		assert_eq!(differing, 0, "{differing} pixels of {name} differ from its golden image at {}", path.display());
+	}
+
+	fn titled(text: &str, alignment: &str) -> ActionState {
+		ActionState {
+			text: text.to_owned(),
+			alignment: alignment.to_owned(),
+			..Default::default()
+		}
+	}
+
+	fn background() -> DynamicImage {
+		DynamicImage::ImageRgba8(RgbaImage::from_pixel(KEY_SIZE, KEY_SIZE, Rgba([40, 90, 160, 255])))
+	}
+
+	#[test]
+	fn title_layout() {
+		let image = background();
+		for alignment in ["top", "middle", "bottom"] {
+			assert_golden(&format!("title-{alignment}"), &render_state(Some(&image), &titled("Title\nTwo", alignment), KEY_SIZE, false));
+		}
+
+		let underlined = ActionState {
+			underline: true,
+			colour: "#FFCC00".to_owned(),
+			..titled("Underlined", "bottom")
+		};
+		assert_golden("title-underline", &render_state(Some(&image), &underlined, KEY_SIZE, false));
+
+		let hidden = ActionState {
+			show: false,
+			..titled("Hidden", "middle")
+		};
+		assert_eq!(render_state(Some(&image), &hidden, KEY_SIZE, false), render_state(Some(&image), &titled("", "middle"), KEY_SIZE, false));
+	}
+
+	#[test]
+	fn font_fallback() {
+		let image = background();
+		let render = |family: &str, style: &str| {
+			let state = ActionState {
+				family: family.to_owned(),
+				style: style.to_owned(),
+				..titled("Font", "middle")
+			};
+			render_state(Some(&image), &state, KEY_SIZE, false)
+		};
+
+		let default = render(fonts::DEFAULT_FAMILY, "Regular");
+		assert_golden("font-default", &default);
+		// Unknown families fall back to the default family, and aliases of proprietary fonts use the family that replaces them.
+		assert_eq!(render("Not A Real Font", "Regular"), default);
+		assert_eq!(render("Arial", "Regular"), default);
+		// Families without a bold face fall back to their regular face.
+		assert_eq!(render("Anton", "Bold"), render("Anton", "Regular"));
+		assert_golden("font-bold-italic", &render("Comic Sans MS", "Bold Italic"));
+	}
+
+	#[test]
+	fn state_images() {
+		let folder = load_builtin_image("folder.png").unwrap();
+		assert_golden("state-image", &render_state(Some(&folder), &ActionState::default(), KEY_SIZE, false));
+		assert_golden("state-image-pressed", &render_state(Some(&folder), &ActionState::default(), KEY_SIZE, true));
+		assert_golden("state-missing", &render_state(None, &ActionState::default(), KEY_SIZE, false));
+
+		// Pressed keys are drawn smaller, leaving a black border around them.
+		let pressed = render_state(Some(&background()), &ActionState::default(), KEY_SIZE, true);
+		assert_eq!(*pressed.get_pixel(0, 0), Rgba([0, 0, 0, 255]));
+		assert_eq!(*pressed.get_pixel(KEY_SIZE / 2, KEY_SIZE / 2), Rgba([40, 90, 160, 255]));
+	}
+
+	#[test]
+	fn decode_builtin_frames() {
+		let frames = decode_frames("opendeck/multi-action.png").unwrap();
+		assert_eq!(frames.len(), 1);
+		assert_eq!(frames[0].image.width(), KEY_SIZE);
+		assert!(decode_frames("opendeck/does-not-exist.png").is_none());
+	}
+}
@@ -0,0 +1,263 @@
+//! Rendering of encoder touchscreen segments from the layouts and feedback set by plugins.
+
+use super::{draw_text, fill_rect, load_image, overlay, parse_colour, text_width, DEFAULT_FONT};
+use crate::shared::{ActionContext, ActionInstance, ActionState, TriggerDescription};
+
+use std::collections::HashMap;
+
+use ab_glyph::PxScale;
+use image::{imageops::FilterType, Rgba, RgbaImage};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tokio::sync::RwLock;
+
+pub const SEGMENT_WIDTH: u32 = 200;
+pub const SEGMENT_HEIGHT: u32 = 100;
+
+type LayoutItem = Map<String, Value>;
+
+/// The feedback state of an encoder instance, as set by its plugin.
+#[derive(Clone, Default)]
+pub struct EncoderFeedback {
+	pub layout: Option<String>,
+	pub values: Map<String, Value>,
+	pub trigger_description: Option<TriggerDescription>,
+}
+
+/// Feedback state of all encoder instances that have appeared.
+pub static FEEDBACK: Lazy<RwLock<HashMap<ActionContext, EncoderFeedback>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+fn builtin_layout(id: &str) -> Option<Value> {
+	let title = json!({ "key": "title", "type": "text", "rect": [16, 10, 136, 24], "font": { "size": 16 }, "alignment": "left", "zOrder": 1 });
+	let icon = json!({ "key": "icon", "type": "pixmap", "rect": [16, 40, 48, 48] });
+	let value = json!({ "key": "value", "type": "text", "rect": [76, 40, 108, 32], "font": { "size": 24 }, "alignment": "right" });
+	Some(match id {
+		"$X1" => json!([
+			{ "key": "title", "type": "text", "rect": [16, 10, 168, 24], "font": { "size": 16 }, "alignment": "center" },
+			{ "key": "icon", "type": "pixmap", "rect": [76, 40, 48, 48] }
+		]),
+		"$A0" => json!([title, { "key": "full-canvas", "type": "pixmap", "rect": [0, 0, 200, 100], "zOrder": 0 }]),
+		"$A1" => json!([title, icon, value]),
+		"$B1" => json!([title, icon, value, { "key": "indicator", "type": "bar", "rect": [76, 74, 108, 20], "value": 0 }]),
+		"$B2" => json!([title, icon, value, { "key": "indicator", "type": "gbar", "rect": [76, 74, 108, 20], "value": 0 }]),
+		"$C1" => json!([
+			title,
+			{ "key": "icon1", "type": "pixmap", "rect": [16, 40, 24, 24] },
+			{ "key": "indicator1", "type": "bar", "rect": [48, 42, 136, 20], "value": 0 },
+			{ "key": "icon2", "type": "pixmap", "rect": [16, 70, 24, 24] },
+			{ "key": "indicator2", "type": "bar", "rect": [48, 72, 136, 20], "value": 0 }
+		]),
+		_ => return None,
+	})
+}
+
+/// Load the items of a built-in layout, or of a custom layout from a JSON file at the given path.
+pub fn load_layout(layout: &str) -> Result<Vec<LayoutItem>, anyhow::Error> {
+	#[derive(Deserialize)]
+	struct CustomLayout {
+		items: Vec<LayoutItem>,
+	}
+
+	if let Some(items) = builtin_layout(layout) {
+		Ok(serde_json::from_value(items)?)
+	} else {
+		let custom: CustomLayout = serde_json::from_slice(&std::fs::read(layout)?)?;
+		Ok(custom.items)
+	}
+}
+
+/// Apply feedback values to the items of a layout.
+/// Objects are merged into the item with the matching key, and other values replace the item's value.
+fn apply_feedback(items: &mut [LayoutItem], values: &Map<String, Value>) {
+	for (key, value) in values {
+		let Some(item) = items.iter_mut().find(|item| item.get("key").and_then(Value::as_str) == Some(key)) else {
+			continue;
+		};
+		match value {
+			Value::Object(properties) => item.extend(properties.clone()),
+			value => {
+				item.insert("value".to_owned(), value.clone());
+			}
+		}
+	}
+}
+
+fn rect(item: &LayoutItem) -> Option<[i32; 4]> {
+	let values = item.get("rect")?.as_array()?;
+	if values.len() != 4 {
+		return None;
+	}
+	Some([values[0].as_i64()? as i32, values[1].as_i64()? as i32, values[2].as_i64()? as i32, values[3].as_i64()? as i32])
+}
+
+fn colour(item: &LayoutItem, key: &str, default: Rgba<u8>, opacity: f32) -> Rgba<u8> {
+	let mut colour = item.get(key).and_then(Value::as_str).and_then(parse_colour).unwrap_or(default);
+	colour[3] = (colour[3] as f32 * opacity) as u8;
+	colour
+}
+
+/// Parse a fill that is either a single colour or a gradient of the form `0:#FF0000,1:#00FF00`, and sample it.
+fn sample_fill(fill: &str, position: f32) -> Option<Rgba<u8>> {
+	if !fill.contains(':') {
+		return parse_colour(fill);
+	}
+	let mut stops = fill
+		.split(',')
+		.filter_map(|stop| stop.split_once(':'))
+		.filter_map(|(offset, colour)| Some((offset.trim().parse::<f32>().ok()?, parse_colour(colour)?)))
+		.collect::<Vec<_>>();
+	stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+	let (first, last) = (stops.first()?, stops.last()?);
+	if position <= first.0 {
+		return Some(first.1);
+	}
+	if position >= last.0 {
+		return Some(last.1);
+	}
+	let window = stops.windows(2).find(|window| position >= window[0].0 && position <= window[1].0)?;
+	let t = (position - window[0].0) / (window[1].0 - window[0].0).max(f32::EPSILON);
+	let mut colour = window[0].1;
+	for (channel, end) in colour.0.iter_mut().zip(window[1].1 .0) {
+		*channel = (*channel as f32 + (end as f32 - *channel as f32) * t).round() as u8;
+	}
+	Some(colour)
+}
+
+fn value_string(value: Option<&Value>) -> String {
+	match value {
+		Some(Value::String(value)) => value.clone(),
+		Some(Value::Null) | None => String::new(),
+		Some(value) => value.to_string(),
+	}
+}
+
+fn render_text(canvas: &mut RgbaImage, item: &LayoutItem, [x, y, width, height]: [i32; 4], opacity: f32) {
+	let text = value_string(item.get("value"));
+	if text.is_empty() {
+		return;
+	}
+	let size = item.get("font").and_then(|font| font.get("size")).and_then(Value::as_f64).unwrap_or(16.0) as f32;
+	let scale = PxScale::from(size);
+	let colour = colour(item, "color", Rgba([255, 255, 255, 255]), opacity);
+
+	let text_width = text_width(&*DEFAULT_FONT, scale, &text);
+	let left = match item.get("alignment").and_then(Value::as_str) {
+		Some("left") => x as f32,
+		Some("right") => (x + width) as f32 - text_width,
+		_ => x as f32 + (width as f32 - text_width) / 2.0,
+	};
+	let top = y as f32 + (height as f32 - size) / 2.0;
+	draw_text(canvas, &*DEFAULT_FONT, scale, colour, left, top, &text);
+}
+
+fn render_pixmap(canvas: &mut RgbaImage, item: &LayoutItem, [x, y, width, height]: [i32; 4], opacity: f32) {
+	let Some(source) = item.get("value").and_then(Value::as_str).filter(|v| !v.is_empty()) else {
+		return;
+	};
+	match load_image(source) {
+		Ok(image) => {
+			let image = image.resize_exact(width.max(1) as u32, height.max(1) as u32, FilterType::Triangle).to_rgba8();
+			overlay(canvas, &image, x, y, opacity);
+		}
+		Err(error) => log::warn!("Failed to load image for layout item: {}", error),
+	}
+}
+
+fn render_bar(canvas: &mut RgbaImage, item: &LayoutItem, [x, y, width, height]: [i32; 4], opacity: f32, groove: bool) {
+	let (min, max) = match item.get("range") {
+		Some(range) => (range.get("min").and_then(Value::as_f64).unwrap_or(0.0), range.get("max").and_then(Value::as_f64).unwrap_or(100.0)),
+		None => (0.0, 100.0),
+	};
+	let value = item.get("value").and_then(Value::as_f64).unwrap_or(0.0);
+	let fraction = ((value - min) / (max - min).max(f64::EPSILON)).clamp(0.0, 1.0) as f32;
+	let fill = item.get("bar_fill_c").and_then(Value::as_str).unwrap_or("#FFFFFF");
+	let background = colour(item, "bar_bg_c", Rgba([80, 80, 80, 255]), opacity);
+
+	if groove {
+		// A thin gradient bar with a marker indicating the value.
+		let bar_height = item.get("bar_h").and_then(Value::as_i64).unwrap_or(10) as i32;
+		let top = y + (height - bar_height) / 2;
+		for px in 0..width {
+			let mut colour = sample_fill(fill, px as f32 / width.max(1) as f32).unwrap_or(Rgba([255, 255, 255, 255]));
+			colour[3] = (colour[3] as f32 * opacity) as u8;
+			fill_rect(canvas, x + px, top, 1, bar_height, colour);
+		}
+		let marker = x + (fraction * (width - 4).max(0) as f32) as i32;
+		let border = colour(item, "bar_border_c", Rgba([255, 255, 255, 255]), opacity);
+		fill_rect(canvas, marker, y, 4, height, border);
+	} else {
+		let border_width = item.get("border_w").and_then(Value::as_i64).unwrap_or(2) as i32;
+		let border = colour(item, "bar_border_c", Rgba([255, 255, 255, 255]), opacity);
+		fill_rect(canvas, x, y, width, height, border);
+		let (inner_x, inner_y, inner_width, inner_height) = (x + border_width, y + border_width, width - border_width * 2, height - border_width * 2);
+		fill_rect(canvas, inner_x, inner_y, inner_width, inner_height, background);
+		let filled = (inner_width as f32 * fraction) as i32;
+		for px in 0..filled {
+			let mut colour = sample_fill(fill, px as f32 / inner_width.max(1) as f32).unwrap_or(Rgba([255, 255, 255, 255]));
+			colour[3] = (colour[3] as f32 * opacity) as u8;
+			fill_rect(canvas, inner_x + px, inner_y, 1, inner_height, colour);
+		}
+	}
+}
+
+/// Render the items of a layout onto a touchscreen segment.
+pub fn render_layout(items: &[LayoutItem]) -> RgbaImage {
+	let mut canvas = RgbaImage::from_pixel(SEGMENT_WIDTH, SEGMENT_HEIGHT, Rgba([0, 0, 0, 255]));
+
+	let mut items = items.iter().filter(|item| item.get("enabled").and_then(Value::as_bool).unwrap_or(true)).collect::<Vec<_>>();
+	items.sort_by_key(|item| item.get("zOrder").and_then(Value::as_i64).unwrap_or(0));
+
+	for item in items {
+		let Some(rect) = rect(item) else { continue };
+		let opacity = item.get("opacity").and_then(Value::as_f64).unwrap_or(1.0).clamp(0.0, 1.0) as f32;
+		if let Some(background) = item.get("background").and_then(Value::as_str).and_then(parse_colour) {
+			fill_rect(&mut canvas, rect[0], rect[1], rect[2], rect[3], background);
+		}
+		match item.get("type").and_then(Value::as_str) {
+			Some("text") => render_text(&mut canvas, item, rect, opacity),
+			Some("pixmap") => render_pixmap(&mut canvas, item, rect, opacity),
+			Some("bar") => render_bar(&mut canvas, item, rect, opacity, false),
+			Some("gbar") => render_bar(&mut canvas, item, rect, opacity, true),
+			_ => (),
+		}
+	}
+
+	canvas
+}
+
+/// Render the touchscreen segment of an encoder instance from its layout and feedback.
+pub fn render_instance(instance: &ActionInstance, feedback: Option<&EncoderFeedback>) -> Result<RgbaImage, anyhow::Error> {
+	let layout = feedback
+		.and_then(|feedback| feedback.layout.clone())
+		.or_else(|| instance.action.encoder.as_ref().map(|encoder| encoder.layout.clone()))
+		.unwrap_or_else(|| "$X1".to_owned());
+	let mut items = load_layout(&layout)?;
+
+	// The title and icon default to those of the instance's current state if the layout does not specify them.
+	let default = ActionState::default();
+	let state = instance.states.get(instance.current_state as usize).unwrap_or(&default);
+	for item in items.iter_mut() {
+		if item.contains_key("value") {
+			continue;
+		}
+		match item.get("key").and_then(Value::as_str) {
+			Some("title") if state.show => item.insert("value".to_owned(), Value::String(state.text.clone())),
+			Some("icon") if !state.image.starts_with("opendeck/") => item.insert("value".to_owned(), Value::String(state.image.clone())),
+			_ => None,
+		};
+	}
+
+	if let Some(feedback) = feedback {
+		apply_feedback(&mut items, &feedback.values);
+	}
+
+	Ok(render_layout(&items))
+}
+
+/// Render the touchscreen segment of an encoder instance and send it to its device.
+pub async fn update_touchscreen(instance: &ActionInstance) -> Result<(), anyhow::Error> {
+	let feedback = FEEDBACK.read().await.get(&instance.context).cloned();
+	let image = render_instance(instance, feedback.as_ref())?;
+	crate::events::outbound::devices::update_touchscreen((&instance.context).into(), Some(image.into())).await
+}
@@ -0,0 +1,264 @@
+//! Rendering of images for device displays in the backend, independently of the frontend.
+
+pub mod animation;
+pub mod fonts;
+pub mod keys;
+pub mod layouts;
+
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont};
+use anyhow::anyhow;
+use base64::Engine as _;
+use image::codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder};
+use image::{AnimationDecoder, DynamicImage, ImageFormat, Rgba, RgbaImage};
+use once_cell::sync::Lazy;
+use resvg::{tiny_skia, usvg};
+
+pub static DEFAULT_FONT: Lazy<FontRef<'static>> = Lazy::new(|| fonts::get_font(fonts::DEFAULT_FAMILY, "Regular"));
+
+static ALERT_IMAGE: Lazy<DynamicImage> = Lazy::new(|| image::load_from_memory(include_bytes!("../../../static/alert.png")).unwrap());
+static OK_IMAGE: Lazy<DynamicImage> = Lazy::new(|| image::load_from_memory(include_bytes!("../../../static/ok.png")).unwrap());
+
+/// Feedback temporarily shown over the image of a key or touchscreen segment at the request of a plugin.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Overlay {
+	Alert,
+	Ok,
+}
+
+/// Draw an overlay centred over an image, or over a blank image the size of the overlay if there is none.
+pub fn apply_overlay(image: Option<&DynamicImage>, overlay: Overlay) -> DynamicImage {
+	let source = match overlay {
+		Overlay::Alert => &*ALERT_IMAGE,
+		Overlay::Ok => &*OK_IMAGE,
+	};
+	let mut canvas = match image {
+		Some(image) => image.to_rgba8(),
+		None => RgbaImage::from_pixel(source.width(), source.height(), Rgba([0, 0, 0, 255])),
+	};
+	let size = canvas.width().min(canvas.height());
+	let resized = source.resize_exact(size, size, image::imageops::FilterType::Triangle).to_rgba8();
+	let (x, y) = ((canvas.width() - size) / 2, (canvas.height() - size) / 2);
+	self::overlay(&mut canvas, &resized, x as i32, y as i32, 1.0);
+	DynamicImage::ImageRgba8(canvas)
+}
+
+/// Read the contents of an image from a data URL or a path on disk.
+fn read_image(source: &str) -> Result<Vec<u8>, anyhow::Error> {
+	let source = source.trim();
+	if let Some(data) = source.strip_prefix("data:") {
+		let (header, data) = data.split_once(',').ok_or_else(|| anyhow!("malformed data URL"))?;
+		if header.ends_with(";base64") {
+			Ok(base64::engine::general_purpose::STANDARD.decode(data)?)
+		} else {
+			Ok(urlencoding::decode_binary(data.as_bytes()).into_owned())
+		}
+	} else {
+		Ok(std::fs::read(source)?)
+	}
+}
+
+/// Options used to parse SVG images, with the bundled fonts available alongside the system fonts for rendering text.
+static SVG_OPTIONS: Lazy<usvg::Options<'static>> = Lazy::new(|| {
+	let mut options = usvg::Options {
+		font_family: fonts::DEFAULT_FAMILY.to_owned(),
+		..Default::default()
+	};
+	let fontdb = Arc::make_mut(&mut options.fontdb);
+	fontdb.load_system_fonts();
+	for face in fonts::all_faces() {
+		fontdb.load_font_data(face.to_vec());
+	}
+	fontdb.set_sans_serif_family(fonts::DEFAULT_FAMILY);
+	fontdb.set_serif_family("Liberation Serif");
+	fontdb.set_monospace_family("Courier Prime");
+	options
+});
+
+fn is_svg(bytes: &[u8]) -> bool {
+	image::guess_format(bytes).is_err() && String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]).contains("<svg")
+}
+
+/// Rasterise an SVG image so that it fits within a square the size of a key.
+fn rasterise_svg(bytes: &[u8]) -> Result<DynamicImage, anyhow::Error> {
+	let tree = usvg::Tree::from_data(bytes, &SVG_OPTIONS)?;
+	let size = tree.size();
+	let scale = keys::KEY_SIZE as f32 / size.width().max(size.height());
+	let (width, height) = ((size.width() * scale).round().max(1.0) as u32, (size.height() * scale).round().max(1.0) as u32);
+
+	let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or_else(|| anyhow!("invalid SVG size"))?;
+	resvg::render(&tree, tiny_skia::Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+
+	// Pixmaps store premultiplied colours, which must be converted back before being used as an image.
+	let pixels = pixmap
+		.pixels()
+		.iter()
+		.flat_map(|pixel| {
+			let colour = pixel.demultiply();
+			[colour.red(), colour.green(), colour.blue(), colour.alpha()]
+		})
+		.collect();
+	Ok(DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, pixels).ok_or_else(|| anyhow!("invalid SVG size"))?))
+}
+
+/// Decode an image in any of the supported raster formats or as SVG.
+fn decode_image(bytes: &[u8]) -> Result<DynamicImage, anyhow::Error> {
+	if is_svg(bytes) {
+		rasterise_svg(bytes)
+	} else {
+		Ok(image::load_from_memory(bytes)?)
+	}
+}
+
+/// Load an image from a data URL or a path on disk.
+pub fn load_image(source: &str) -> Result<DynamicImage, anyhow::Error> {
+	decode_image(&read_image(source)?)
+}
+
+/// Load every frame of an animated GIF, APNG or WebP image from a data URL or a path on disk.
+/// Other images are loaded as a single frame.
+pub fn load_frames(source: &str) -> Result<Vec<animation::Frame>, anyhow::Error> {
+	let bytes = read_image(source)?;
+	let frames = match image::guess_format(&bytes) {
+		Ok(ImageFormat::Gif) => GifDecoder::new(Cursor::new(&bytes))?.into_frames().collect_frames()?,
+		Ok(ImageFormat::Png) => {
+			let decoder = PngDecoder::new(Cursor::new(&bytes))?;
+			if decoder.is_apng()? {
+				decoder.apng()?.into_frames().collect_frames()?
+			} else {
+				vec![]
+			}
+		}
+		Ok(ImageFormat::WebP) => {
+			let decoder = WebPDecoder::new(Cursor::new(&bytes))?;
+			if decoder.has_animation() {
+				decoder.into_frames().collect_frames()?
+			} else {
+				vec![]
+			}
+		}
+		_ => vec![],
+	};
+
+	if frames.len() <= 1 {
+		return Ok(vec![animation::Frame {
+			image: decode_image(&bytes)?,
+			delay: Duration::ZERO,
+		}]);
+	}
+	Ok(frames
+		.into_iter()
+		.map(|frame| {
+			let (numerator, denominator) = frame.delay().numer_denom_ms();
+			let delay = numerator / denominator.max(1);
+			animation::Frame {
+				// Browsers show frames with very short delays for 100ms instead, which many animated images rely on.
+				delay: Duration::from_millis(if delay < 20 { 100 } else { delay as u64 }),
+				image: DynamicImage::ImageRgba8(frame.into_buffer()),
+			}
+		})
+		.collect())
+}
+
+/// Rotate an image counterclockwise by the orientation of a device, so that it appears upright on a device rotated clockwise by that amount.
+pub fn rotate_image(source: &str, orientation: u16) -> Result<String, anyhow::Error> {
+	let image = load_image(source)?;
+	let rotated = match orientation {
+		90 => image.rotate270(),
+		180 => image.rotate180(),
+		270 => image.rotate90(),
+		_ => image,
+	};
+	to_data_url(&rotated)
+}
+
+/// Encode an image as a PNG data URL.
+pub fn to_data_url(image: &DynamicImage) -> Result<String, anyhow::Error> {
+	let mut bytes = Vec::new();
+	image.write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)?;
+	Ok(format!("data:image/png;base64,{}", base64::engine::general_purpose::STANDARD.encode(bytes)))
+}
+
+/// Parse a colour in the `#RGB`, `#RRGGBB` or `#RRGGBBAA` formats.
+pub fn parse_colour(value: &str) -> Option<Rgba<u8>> {
+	let hex = value.trim().strip_prefix('#')?;
+	let channel = |index: usize| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok();
+	match hex.len() {
+		3 => {
+			let mut digits = hex.chars().map(|c| c.to_digit(16).map(|v| v as u8 * 17));
+			Some(Rgba([digits.next()??, digits.next()??, digits.next()??, 255]))
+		}
+		6 => Some(Rgba([channel(0)?, channel(2)?, channel(4)?, 255])),
+		8 => Some(Rgba([channel(0)?, channel(2)?, channel(4)?, channel(6)?])),
+		_ => None,
+	}
+}
+
+/// Blend a colour onto a pixel of the canvas, ignoring pixels outside of its bounds.
+pub fn blend(canvas: &mut RgbaImage, x: i32, y: i32, colour: Rgba<u8>, coverage: f32) {
+	if x < 0 || y < 0 || x >= canvas.width() as i32 || y >= canvas.height() as i32 {
+		return;
+	}
+	let alpha = coverage.clamp(0.0, 1.0) * colour[3] as f32 / 255.0;
+	let pixel = canvas.get_pixel_mut(x as u32, y as u32);
+	for (destination, source) in pixel.0.iter_mut().zip(colour.0).take(3) {
+		*destination = (source as f32 * alpha + *destination as f32 * (1.0 - alpha)).round() as u8;
+	}
+	pixel[3] = (alpha * 255.0 + pixel[3] as f32 * (1.0 - alpha)).round() as u8;
+}
+
+/// Fill a rectangle of the canvas with a colour.
+pub fn fill_rect(canvas: &mut RgbaImage, x: i32, y: i32, width: i32, height: i32, colour: Rgba<u8>) {
+	for py in y..y + height {
+		for px in x..x + width {
+			blend(canvas, px, py, colour, 1.0);
+		}
+	}
+}
+
+/// Draw an image onto the canvas with its top left corner at the given position.
+pub fn overlay(canvas: &mut RgbaImage, image: &RgbaImage, x: i32, y: i32, opacity: f32) {
+	for (px, py, pixel) in image.enumerate_pixels() {
+		blend(canvas, x + px as i32, y + py as i32, *pixel, opacity);
+	}
+}
+
+/// Measure the width of a line of text.
+pub fn text_width(font: &impl Font, scale: PxScale, text: &str) -> f32 {
+	let font = font.as_scaled(scale);
+	let mut width = 0.0;
+	let mut previous = None;
+	for c in text.chars() {
+		let id = font.glyph_id(c);
+		if let Some(previous) = previous {
+			width += font.kern(previous, id);
+		}
+		width += font.h_advance(id);
+		previous = Some(id);
+	}
+	width
+}
+
+/// Draw a line of text with its top left corner at the given position.
+pub fn draw_text(canvas: &mut RgbaImage, font: &impl Font, scale: PxScale, colour: Rgba<u8>, x: f32, y: f32, text: &str) {
+	let scaled = font.as_scaled(scale);
+	let mut caret = ab_glyph::point(x, y + scaled.ascent());
+	let mut previous = None;
+	for c in text.chars() {
+		let id = scaled.glyph_id(c);
+		if let Some(previous) = previous {
+			caret.x += scaled.kern(previous, id);
+		}
+		let glyph = id.with_scale_and_position(scale, caret);
+		caret.x += scaled.h_advance(id);
+		previous = Some(id);
+
+		if let Some(outlined) = font.outline_glyph(glyph) {
+			let bounds = outlined.px_bounds();
+			outlined.draw(|gx, gy, coverage| blend(canvas, bounds.min.x as i32 + gx as i32, bounds.min.y as i32 + gy as i32, colour, coverage));
+		}
+	}
+}
@@ -0,0 +1,235 @@
+//! The built-in action that runs a command when its key is pressed, showing whether it succeeded and optionally what it printed.
+//! Commands are run directly rather than through a shell, so that their arguments do not need quoting.
+
+use crate::events::frontend::instances::update_state;
+use crate::render::Overlay;
+use crate::shared::{ActionContext, ActionInstance, DEVICES};
+use crate::store::profiles::{acquire_locks_mut, get_instance_mut};
+
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use dashmap::{DashMap, DashSet};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use tauri::Emitter;
+
+/// What is done with the output of a command once it has finished.
+enum Output {
+	None,
+	/// Show the last line printed to stdout as the title of the key for a few seconds.
+	Title,
+	/// Show the first state of the key if the command succeeded, and the second if it failed.
+	State,
+}
+
+/// A command to run and how to run it, read from the settings of an instance of the action.
+struct CommandConfig {
+	program: String,
+	args: Vec<String>,
+	cwd: Option<String>,
+	env: Vec<(String, String)>,
+	timeout: Option<Duration>,
+	output: Output,
+}
+
+impl CommandConfig {
+	/// Read the config of an instance from its settings, returning `None` if no command has been set.
+	fn from_settings(settings: &Value) -> Option<Self> {
+		let program = settings["command"].as_str().map(str::trim).filter(|program| !program.is_empty())?.to_owned();
+		let strings = |value: &Value| -> Vec<String> { value.as_array().into_iter().flatten().filter_map(Value::as_str).map(str::to_owned).collect() };
+		Some(Self {
+			program,
+			args: strings(&settings["args"]),
+			cwd: settings["cwd"].as_str().map(str::trim).filter(|cwd| !cwd.is_empty()).map(str::to_owned),
+			env: settings["env"]
+				.as_object()
+				.into_iter()
+				.flatten()
+				.filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_owned())))
+				.collect(),
+			timeout: match settings["timeout"].as_u64().unwrap_or(30) {
+				0 => None,
+				seconds => Some(Duration::from_secs(seconds)),
+			},
+			output: match settings["output"].as_str() {
+				Some("title") => Output::Title,
+				Some("state") => Output::State,
+				_ => Output::None,
+			},
+		})
+	}
+}
+
+/// The instances whose commands are running, which are not run again until they finish.
+static RUNNING: Lazy<DashSet<ActionContext>> = Lazy::new(DashSet::new);
+
+/// How long the output of a command is shown as the title of its key before the key's own title is restored.
+const TITLE_DURATION: Duration = Duration::from_secs(5);
+/// The instances whose keys are showing the output of their command as their title, alongside a number identifying the run that showed it.
+static TITLES: Lazy<DashMap<ActionContext, u64>> = Lazy::new(DashMap::new);
+static TITLE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Kill a command along with any processes it started.
+async fn kill_process_tree(id: u32) {
+	#[cfg(unix)]
+	// SAFETY: killpg has no memory safety requirements, and the process group is that of a command spawned with a new group.
+	unsafe {
+		libc::killpg(id as libc::pid_t, libc::SIGKILL);
+	}
+	#[cfg(target_os = "windows")]
+	{
+		let mut command = tokio::process::Command::new("taskkill");
+		command.args(["/T", "/F", "/PID", &id.to_string()]).stdout(Stdio::null()).stderr(Stdio::null());
+		command.creation_flags(0x08000000);
+		if let Err(error) = command.status().await {
+			log::warn!("Failed to kill process tree of command: {}", error);
+		}
+	}
+}
+
+/// Run a command and wait for it to finish, killing it if it runs for longer than its timeout.
+async fn execute(config: &CommandConfig) -> Result<std::process::Output, anyhow::Error> {
+	let mut command = tokio::process::Command::new(&config.program);
+	command
+		.args(&config.args)
+		.envs(config.env.iter().map(|(key, value)| (key, value)))
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.kill_on_drop(true);
+	if let Some(cwd) = &config.cwd {
+		command.current_dir(cwd);
+	}
+	#[cfg(target_os = "windows")]
+	command.creation_flags(0x08000000);
+	// Processes started by the command join its new process group, so that they can be killed with it.
+	#[cfg(unix)]
+	command.process_group(0);
+
+	let child = command.spawn()?;
+	let id = child.id();
+	let output = child.wait_with_output();
+	tokio::pin!(output);
+	let Some(timeout) = config.timeout else { return Ok(output.await?) };
+	match tokio::time::timeout(timeout, output.as_mut()).await {
+		Ok(output) => Ok(output?),
+		Err(_) => {
+			// The processes started by the command are killed before the command itself, which is killed as its output is dropped.
+			if let Some(id) = id {
+				kill_process_tree(id).await;
+			}
+			Err(anyhow!("timed out after {} seconds", timeout.as_secs()))
+		}
+	}
+}
+
+/// Show a line of output as the title of the key of an instance until it is replaced or `TITLE_DURATION` has passed, without saving it to the instance.
+async fn show_title(context: &ActionContext, line: &str) -> Result<(), anyhow::Error> {
+	let mut locks = acquire_locks_mut().await;
+	if locks.device_stores.get_selected_profile(&context.device)? != context.profile {
+		return Ok(());
+	}
+	let Some(mut instance) = get_instance_mut(context, &mut locks).await?.cloned() else {
+		return Ok(());
+	};
+	drop(locks);
+
+	if let Some(state) = instance.states.get_mut(instance.current_state as usize) {
+		line.clone_into(&mut state.text);
+		state.show = true;
+	}
+	let id = TITLE_COUNTER.fetch_add(1, Ordering::Relaxed);
+	TITLES.insert(context.clone(), id);
+	crate::render::keys::update_key(&instance, false).await?;
+
+	let context = context.clone();
+	tokio::spawn(async move {
+		tokio::time::sleep(TITLE_DURATION).await;
+		// A newer run of the command may have replaced the title, in which case it is responsible for restoring it.
+		if TITLES.remove_if(&context, |_, current| *current == id).is_none() {
+			return;
+		}
+		let mut locks = acquire_locks_mut().await;
+		if !locks.device_stores.get_selected_profile(&context.device).is_ok_and(|selected| selected == context.profile) {
+			return;
+		}
+		let Ok(Some(instance)) = get_instance_mut(&context, &mut locks).await.map(|instance| instance.cloned()) else {
+			return;
+		};
+		drop(locks);
+		if let Err(error) = crate::render::keys::update_key(&instance, false).await {
+			log::warn!("Failed to restore title of command of {}: {}", context, error);
+		}
+	});
+	Ok(())
+}
+
+/// Show the result of a command on the key of its instance.
+async fn report(context: &ActionContext, config: &CommandConfig, result: &Result<std::process::Output, anyhow::Error>) -> Result<(), anyhow::Error> {
+	let success = result.as_ref().is_ok_and(|output| output.status.success());
+	match config.output {
+		Output::Title => {
+			if let Ok(output) = result {
+				let stdout = String::from_utf8_lossy(&output.stdout);
+				let line = stdout.lines().map(str::trim).rev().find(|line| !line.is_empty()).unwrap_or_default();
+				show_title(context, line).await?;
+			}
+		}
+		Output::State => {
+			let mut locks = acquire_locks_mut().await;
+			let Some(instance) = get_instance_mut(context, &mut locks).await? else { return Ok(()) };
+			instance.current_state = if success { 0 } else { 1.min(instance.states.len().saturating_sub(1) as u16) };
+			// The profile of the instance may no longer be selected by the time the command finishes.
+			let device = DEVICES.get(&context.device).ok_or_else(|| anyhow!("device {} not found", context.device))?;
+			locks.profile_stores.get_profile_store(&device, &context.profile)?.save()?;
+			drop(device);
+			if locks.device_stores.get_selected_profile(&context.device)? == context.profile {
+				update_state(crate::APP_HANDLE.get().unwrap(), context.clone(), locks).await?;
+			}
+		}
+		Output::None => {}
+	}
+
+	let overlay = if success { Overlay::Ok } else { Overlay::Alert };
+	crate::events::outbound::devices::show_overlay(context.into(), overlay).await?;
+	let event = if success { "show_ok" } else { "show_alert" };
+	crate::APP_HANDLE.get().unwrap().emit_to("main", event, context)?;
+	Ok(())
+}
+
+/// Run the command of an instance of the action in the background, unless it is already running.
+pub fn run(instance: &ActionInstance) {
+	let context = instance.context.clone();
+	let Some(config) = CommandConfig::from_settings(&instance.settings) else {
+		log::warn!("No command has been set for {}", context);
+		return;
+	};
+	if !RUNNING.insert(context.clone()) {
+		log::debug!("Not running command of {} as it is already running", context);
+		return;
+	}
+
+	tokio::spawn(async move {
+		let result = execute(&config).await;
+		RUNNING.remove(&context);
+		match &result {
+			Ok(output) if !output.status.success() => {
+				log::warn!(
+					"Command {} of {} failed with {}: {}",
+					config.program,
+					context,
+					output.status,
+					String::from_utf8_lossy(&output.stderr).trim()
+				);
+			}
+			Err(error) => log::warn!("Failed to run command {} of {}: {}", config.program, context, error),
+			_ => {}
+		}
+		if let Err(error) = report(&context, &config, &result).await {
+			log::warn!("Failed to show result of command of {}: {}", context, error);
+		}
+	});
+}
@@ -34,14 +34,75 @@ pub struct DeviceInfo {
 	pub columns: u8,
 	pub encoders: u8,
 	pub r#type: u8,
+	/// How far the device is rotated clockwise from its usual orientation, in degrees.
+	/// The rows and columns of devices that are rotated are those of the layout as it is mounted, which is what profiles and plugins see.
+	#[serde_inline_default(0)]
+	pub orientation: u16,
+}
+
+impl DeviceInfo {
+	/// Get the number of keys of the device, which may be more than fit in a single byte.
+	pub fn key_count(&self) -> usize {
+		self.rows as usize * self.columns as usize
+	}
+
+	/// Get the number of rows and columns of keys of the device in its usual orientation.
+	pub fn physical_size(&self) -> (u8, u8) {
+		if self.orientation % 180 == 90 {
+			(self.columns, self.rows)
+		} else {
+			(self.rows, self.columns)
+		}
+	}
+
+	/// Convert the position of a key in the device's usual orientation to its position in the layout as it is mounted.
+	/// Positions that are not on the device have no position in the layout.
+	pub fn logical_position(&self, physical: u8) -> Option<u8> {
+		let (rows, columns) = self.physical_size();
+		if physical as u16 >= rows as u16 * columns as u16 {
+			return None;
+		}
+		let (row, column) = (physical / columns, physical % columns);
+		let (row, column) = match self.orientation {
+			90 => (column, rows - 1 - row),
+			180 => (rows - 1 - row, columns - 1 - column),
+			270 => (columns - 1 - column, row),
+			_ => (row, column),
+		};
+		u8::try_from(row as u16 * self.columns as u16 + column as u16).ok()
+	}
+
+	/// Convert the position of a key in the layout as it is mounted to its position in the device's usual orientation.
+	/// Positions that are not in the layout have no position on the device.
+	pub fn physical_position(&self, logical: u8) -> Option<u8> {
+		let (rows, columns) = self.physical_size();
+		if logical as u16 >= self.rows as u16 * self.columns as u16 {
+			return None;
+		}
+		let (row, column) = (logical / self.columns, logical % self.columns);
+		let (row, column) = match self.orientation {
+			90 => (rows - 1 - column, row),
+			180 => (rows - 1 - row, columns - 1 - column),
+			270 => (column, columns - 1 - row),
+			_ => (row, column),
+		};
+		u8::try_from(row as u16 * columns as u16 + column as u16).ok()
+	}
 }
 
 pub static DEVICES: Lazy<DashMap<String, DeviceInfo>> = Lazy::new(DashMap::new);
 
+/// Whether OpenDeck was started with `--headless`, in which case no windows are created and it is controlled through the local API.
+pub static HEADLESS: Lazy<bool> = Lazy::new(|| std::env::args().any(|arg| arg == "--headless"));
+
 /// Get the application configuration directory.
 pub fn config_dir() -> std::path::PathBuf {
-	let app_handle = crate::APP_HANDLE.get().unwrap();
-	app_handle.path().app_config_dir().unwrap()
+	match crate::APP_HANDLE.get() {
+		Some(app_handle) => app_handle.path().app_config_dir().unwrap(),
+		// Tests run without the app, so they keep their configuration in a temporary directory of their own.
+		None if cfg!(test) => std::env::temp_dir().join(format!("opendeck-test-{}", std::process::id())),
+		None => panic!("the config directory was requested before the app was set up"),
+	}
 }
 
 /// Get the application log directory.
@@ -142,6 +203,33 @@ impl Default for ActionState {
 	}
 }
 
+/// Descriptions of the interactions an encoder action responds to.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TriggerDescription {
+	#[serde(alias = "Rotate")]
+	pub rotate: Option<String>,
+	#[serde(alias = "Push")]
+	pub push: Option<String>,
+	#[serde(alias = "Touch")]
+	pub touch: Option<String>,
+	#[serde(alias = "LongTouch", alias = "longTouch")]
+	pub long_touch: Option<String>,
+}
+
+/// Encoder-specific properties of an action, deserialised from the plugin manifest.
+#[serde_inline_default]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EncoderInfo {
+	#[serde_inline_default("$X1".to_owned())]
+	#[serde(alias = "Layout")]
+	pub layout: String,
+
+	#[serde_inline_default(None)]
+	#[serde(alias = "TriggerDescription")]
+	pub trigger_description: Option<TriggerDescription>,
+}
+
 /// An action, deserialised from the plugin manifest.
 #[serde_inline_default]
 #[derive(Clone, Serialize, Deserialize)]
@@ -185,10 +273,14 @@ pub struct Action {
 
 	#[serde(alias = "States")]
 	pub states: Vec<ActionState>,
+
+	#[serde_inline_default(None)]
+	#[serde(alias = "Encoder")]
+	pub encoder: Option<EncoderInfo>,
 }
 
 /// Location metadata of a slot.
-#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Context {
 	pub device: String,
 	pub profile: String,
@@ -197,7 +289,7 @@ pub struct Context {
 }
 
 /// Information about the slot and index an instance is located in.
-#[derive(Clone, PartialEq, Eq, serde_with::SerializeDisplay, serde_with::DeserializeFromStr)]
+#[derive(Clone, PartialEq, Eq, Hash, serde_with::SerializeDisplay, serde_with::DeserializeFromStr)]
 pub struct ActionContext {
 	pub device: String,
 	pub profile: String,
@@ -272,13 +364,32 @@ pub struct ActionInstance {
 	pub current_state: u16,
 	pub settings: serde_json::Value,
 	pub children: Option<Vec<ActionInstance>>,
+	/// How the rotation of the dial of an instance on an encoder is sent to its plugin, if it has been changed from the defaults.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub dial: Option<DialOptions>,
+}
+
+/// How the rotation of a dial is sent to the plugin of the instance on it.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct DialOptions {
+	/// Whether turning the dial quickly multiplies the ticks it was turned by.
+	pub acceleration: bool,
+	/// How long to gather ticks for before sending them together in one event, in milliseconds, or 0 to send every rotation straight away.
+	pub coalesce: u16,
+}
+
+/// A layout of instances filling the keys and touchscreen segments of a device.
+/// The positions of instances on a page follow on from those of the pages before it, so that they are unique within a profile.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Page {
+	pub keys: Vec<Option<ActionInstance>>,
+	pub sliders: Vec<Option<ActionInstance>>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Profile {
 	pub id: String,
-	pub keys: Vec<Option<ActionInstance>>,
-	pub sliders: Vec<Option<ActionInstance>>,
+	pub pages: Vec<Page>,
 }
 
 /// A map of category names to a list of actions in that category.
@@ -300,6 +411,32 @@ pub static CATEGORIES: Lazy<RwLock<HashMap<String, Vec<Action>>>> = Lazy::new(||
 				}
 			))
 			.unwrap(),
+			serde_json::from_value(serde_json::json!(
+				{
+					"name": "Multi Action Switch",
+					"icon": "opendeck/multi-action-switch.png",
+					"plugin": "opendeck",
+					"uuid": "opendeck.multiactionswitch",
+					"tooltip": "Alternate between executing two sequences of actions",
+					"controllers": [ "Keypad" ],
+					"states": [ { "image": "opendeck/multi-action-switch.png" }, { "image": "opendeck/multi-action-switch.png" } ],
+					"disable_automatic_states": true,
+					"supported_in_multi_actions": false
+				}
+			))
+			.unwrap(),
+			serde_json::from_value(serde_json::json!(
+				{
+					"name": "Wait",
+					"icon": "opendeck/wait.png",
+					"plugin": "opendeck",
+					"uuid": "opendeck.wait",
+					"tooltip": "Wait between the steps of a multi action",
+					"controllers": [ "Keypad" ],
+					"states": [ { "image": "opendeck/wait.png" } ],
+				}
+			))
+			.unwrap(),
 			serde_json::from_value(serde_json::json!(
 				{
 					"name": "Toggle Action",
@@ -313,7 +450,210 @@ pub static CATEGORIES: Lazy<RwLock<HashMap<String, Vec<Action>>>> = Lazy::new(||
 				}
 			))
 			.unwrap(),
+			serde_json::from_value(serde_json::json!(
+				{
+					"name": "Gesture Action",
+					"icon": "opendeck/gesture-action.png",
+					"plugin": "opendeck",
+					"uuid": "opendeck.gestureaction",
+					"tooltip": "Run different actions when a key is tapped, held or pressed twice",
+					"controllers": [ "Keypad" ],
+					"states": [ { "image": "opendeck/gesture-action.png" } ],
+					"supported_in_multi_actions": false
+				}
+			))
+			.unwrap(),
+			serde_json::from_value(serde_json::json!(
+				{
+					"name": "Folder",
+					"icon": "opendeck/folder.png",
+					"plugin": "opendeck",
+					"uuid": "opendeck.folder",
+					"tooltip": "Open a page of more actions",
+					"controllers": [ "Keypad" ],
+					"states": [ { "image": "opendeck/folder.png" } ],
+					"supported_in_multi_actions": false
+				}
+			))
+			.unwrap(),
+			serde_json::from_value(serde_json::json!(
+				{
+					"name": "Back",
+					"icon": "opendeck/back.png",
+					"plugin": "opendeck",
+					"uuid": "opendeck.back",
+					"tooltip": "Return from a folder",
+					"controllers": [ "Keypad" ],
+					"states": [ { "image": "opendeck/back.png" } ],
+					"visible_in_action_list": false,
+					"supported_in_multi_actions": false
+				}
+			))
+			.unwrap(),
+			serde_json::from_value(serde_json::json!(
+				{
+					"name": "Next Page",
+					"icon": "opendeck/next-page.png",
+					"plugin": "opendeck",
+					"uuid": "opendeck.nextpage",
+					"tooltip": "Move to the next page of the profile",
+					"controllers": [ "Keypad" ],
+					"states": [ { "image": "opendeck/next-page.png" } ],
+					"supported_in_multi_actions": false
+				}
+			))
+			.unwrap(),
+			serde_json::from_value(serde_json::json!(
+				{
+					"name": "Previous Page",
+					"icon": "opendeck/previous-page.png",
+					"plugin": "opendeck",
+					"uuid": "opendeck.previouspage",
+					"tooltip": "Move to the previous page of the profile",
+					"controllers": [ "Keypad" ],
+					"states": [ { "image": "opendeck/previous-page.png" } ],
+					"supported_in_multi_actions": false
+				}
+			))
+			.unwrap(),
+			serde_json::from_value(serde_json::json!(
+				{
+					"name": "Go To Page",
+					"icon": "opendeck/go-to-page.png",
+					"plugin": "opendeck",
+					"uuid": "opendeck.gotopage",
+					"tooltip": "Move to a chosen page of the profile",
+					"controllers": [ "Keypad" ],
+					"states": [ { "image": "opendeck/go-to-page.png" } ],
+					"supported_in_multi_actions": false
+				}
+			))
+			.unwrap(),
+			serde_json::from_value(serde_json::json!(
+				{
+					"name": "Page Dial",
+					"icon": "opendeck/page-dial.png",
+					"plugin": "opendeck",
+					"uuid": "opendeck.pagedial",
+					"tooltip": "Turn to move between the pages of the profile and press to return to the first page",
+					"controllers": [ "Encoder" ],
+					"states": [ { "image": "opendeck/page-dial.png" } ],
+					"supported_in_multi_actions": false
+				}
+			))
+			.unwrap(),
+			serde_json::from_value(serde_json::json!(
+				{
+					"name": "Switch Profile",
+					"icon": "opendeck/switch-profile.png",
+					"plugin": "opendeck",
+					"uuid": "opendeck.switchprofile",
+					"tooltip": "Switch to another profile on this or another device",
+					"controllers": [ "Keypad" ],
+					"states": [ { "image": "opendeck/switch-profile.png" } ],
+					"supported_in_multi_actions": false
+				}
+			))
+			.unwrap(),
+			serde_json::from_value(serde_json::json!(
+				{
+					"name": "Run Command",
+					"icon": "opendeck/run-command.png",
+					"plugin": "opendeck",
+					"uuid": "opendeck.runcommand",
+					"tooltip": "Run a program or script",
+					"controllers": [ "Keypad" ],
+					"states": [ { "image": "opendeck/run-command.png" }, { "image": "opendeck/run-command.png" } ],
+					"disable_automatic_states": true,
+					"supported_in_multi_actions": false
+				}
+			))
+			.unwrap(),
+			serde_json::from_value(serde_json::json!(
+				{
+					"name": "Brightness",
+					"icon": "opendeck/brightness.png",
+					"plugin": "opendeck",
+					"uuid": "opendeck.brightness",
+					"tooltip": "Adjust the brightness of this device until the brightness schedule or settings change",
+					"controllers": [ "Keypad", "Encoder" ],
+					"states": [ { "image": "opendeck/brightness.png" } ],
+					"supported_in_multi_actions": false
+				}
+			))
+			.unwrap(),
 		],
 	);
+	// The hotkey action uses a virtual keyboard created with uinput, which is only available on Linux.
+	#[cfg(target_os = "linux")]
+	hashmap.get_mut("OpenDeck").unwrap().push(
+		serde_json::from_value(serde_json::json!(
+			{
+				"name": "Hotkey",
+				"icon": "opendeck/hotkey.png",
+				"plugin": "opendeck",
+				"uuid": "opendeck.hotkey",
+				"tooltip": "Press a key combination or type text",
+				"controllers": [ "Keypad" ],
+				"states": [ { "image": "opendeck/hotkey.png" } ]
+			}
+		))
+		.unwrap(),
+	);
 	RwLock::new(hashmap)
 });
+
+#[cfg(test)]
+mod tests {
+	use super::DeviceInfo;
+
+	fn device(rows: u8, columns: u8, orientation: u16) -> DeviceInfo {
+		// The rows and columns of rotated devices are those of the layout as it is mounted.
+		let (rows, columns) = if orientation % 180 == 90 { (columns, rows) } else { (rows, columns) };
+		DeviceInfo {
+			id: "sd-test".to_owned(),
+			plugin: String::new(),
+			name: "Test".to_owned(),
+			rows,
+			columns,
+			encoders: 0,
+			r#type: 0,
+			orientation,
+		}
+	}
+
+	#[test]
+	fn positions_round_trip() {
+		for orientation in [0, 90, 180, 270] {
+			let device = device(3, 5, orientation);
+			let mut seen = vec![];
+			for physical in 0..15 {
+				let logical = device.logical_position(physical).unwrap();
+				assert!(logical < 15);
+				assert_eq!(device.physical_position(logical), Some(physical));
+				seen.push(logical);
+			}
+			seen.sort();
+			assert_eq!(seen, (0..15).collect::<Vec<_>>());
+		}
+	}
+
+	#[test]
+	fn rotated_positions() {
+		// The top left key of a device rotated clockwise is at the top right of the layout as it is mounted.
+		assert_eq!(device(3, 5, 90).logical_position(0), Some(2));
+		assert_eq!(device(3, 5, 180).logical_position(0), Some(14));
+		assert_eq!(device(3, 5, 270).logical_position(0), Some(12));
+	}
+
+	#[test]
+	fn positions_out_of_range() {
+		for orientation in [0, 90, 180, 270] {
+			assert_eq!(device(3, 5, orientation).logical_position(15), None);
+			assert_eq!(device(3, 5, orientation).physical_position(255), None);
+			assert_eq!(device(0, 0, orientation).logical_position(0), None);
+			assert_eq!(device(0, 5, orientation).physical_position(0), None);
+		}
+		assert_eq!(device(16, 16, 90).logical_position(255), Some(240));
+	}
+}
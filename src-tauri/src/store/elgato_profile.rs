@@ -0,0 +1,352 @@
+//! Conversion of profiles exported from the Elgato Stream Deck software (`.streamDeckProfile` archives) to OpenDeck profiles.
+
+use crate::shared::{Action, ActionContext, ActionInstance, ActionState, DeviceInfo, FontSize, Page, Profile, CATEGORIES};
+
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+
+use anyhow::anyhow;
+use base64::Engine as _;
+use serde_json::{Map, Value};
+
+/// The files contained in a `.streamDeckProfile` archive, keyed by their path.
+struct Archive {
+	files: HashMap<String, Vec<u8>>,
+}
+
+impl Archive {
+	fn new(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+		let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+		let mut files = HashMap::new();
+		for index in 0..archive.len() {
+			let mut file = archive.by_index(index)?;
+			if !file.is_file() {
+				continue;
+			}
+			let mut contents = vec![];
+			file.read_to_end(&mut contents)?;
+			files.insert(file.name().replace('\\', "/"), contents);
+		}
+		Ok(Self { files })
+	}
+
+	/// Get the contents of a file by its path, ignoring case.
+	fn get(&self, path: &str) -> Option<&Vec<u8>> {
+		let path = path.to_lowercase();
+		self.files.iter().find(|(name, _)| name.to_lowercase() == path).map(|(_, contents)| contents)
+	}
+
+	fn json(&self, path: &str) -> Result<Value, anyhow::Error> {
+		let contents = self.get(path).ok_or_else(|| anyhow!("{path} not found in profile archive"))?;
+		Ok(serde_json::from_str(String::from_utf8_lossy(contents).trim_start_matches('\u{feff}'))?)
+	}
+
+	/// Get the directory containing the profile manifest.
+	fn root(&self) -> Result<String, anyhow::Error> {
+		let manifest = self
+			.files
+			.keys()
+			.filter(|name| name.to_lowercase().ends_with(".sdprofile/manifest.json"))
+			.min_by_key(|name| name.matches('/').count())
+			.ok_or_else(|| anyhow!("profile manifest not found"))?;
+		Ok(manifest[..manifest.len() - "manifest.json".len()].to_owned())
+	}
+
+	/// Get the base directory and manifest of each page of the profile, in order.
+	/// Profiles from versions of the Stream Deck software before 6.0 only have a single page, described by the profile manifest.
+	fn pages(&self) -> Result<Vec<(String, Value)>, anyhow::Error> {
+		let root = self.root()?;
+		let manifest = self.json(&format!("{root}manifest.json"))?;
+		let Some(ids) = manifest["Pages"]["Pages"].as_array() else {
+			return Ok(vec![(root, manifest)]);
+		};
+
+		let mut pages = vec![];
+		for id in ids.iter().filter_map(Value::as_str) {
+			let base = format!("{root}Profiles/{id}/");
+			match self.json(&format!("{base}manifest.json")) {
+				Ok(page) => pages.push((base, page)),
+				Err(error) => log::warn!("Failed to read page {id} of profile: {error}"),
+			}
+		}
+		Ok(pages)
+	}
+
+	/// Convert an image referenced by a state to a data URL.
+	fn image(&self, base: &str, coordinates: &str, state: usize, image: &str) -> Option<String> {
+		let is_image = |name: &str| {
+			[".png", ".jpg", ".jpeg", ".gif", ".svg", ".webp", ".bmp"]
+				.iter()
+				.any(|extension| name.to_lowercase().ends_with(extension))
+		};
+		let (name, contents) = if !image.is_empty() {
+			let path = format!("{base}{image}");
+			(path.clone(), self.get(&path)?)
+		} else {
+			// Older profiles store state images alongside each action without referencing them.
+			let prefix = format!("{base}{coordinates}/CS{state}/").to_lowercase();
+			let (name, contents) = self.files.iter().find(|(name, _)| name.to_lowercase().starts_with(&prefix) && is_image(name))?;
+			(name.clone(), contents)
+		};
+		if !is_image(&name) {
+			return None;
+		}
+
+		let mime = match name.rsplit_once('.').map(|(_, extension)| extension.to_lowercase()).as_deref() {
+			Some("jpg") | Some("jpeg") => "image/jpeg",
+			Some("svg") => "image/svg+xml",
+			Some("gif") => "image/gif",
+			Some("webp") => "image/webp",
+			Some("bmp") => "image/bmp",
+			_ => "image/png",
+		};
+		Some(format!("data:{mime};base64,{}", base64::engine::general_purpose::STANDARD.encode(contents)))
+	}
+}
+
+fn non_empty<'a>(value: &'a Value, keys: &[&str]) -> Option<&'a Value> {
+	keys.iter().filter_map(|key| value.get(key)).find(|value| !value.is_null() && value.as_str() != Some(""))
+}
+
+/// Apply the properties of a state from an Elgato profile, whose names differ from those used in plugin manifests, to a state.
+fn apply_state(state: &mut ActionState, elgato: &Value) {
+	if let Some(title) = elgato["Title"].as_str() {
+		state.text = title.to_owned();
+	}
+	if let Some(show) = non_empty(elgato, &["ShowTitle", "TitleShow"]) {
+		state.show = show.as_bool().unwrap_or_else(|| show.as_str() != Some("hide"));
+	}
+	if let Some(colour) = non_empty(elgato, &["TitleColor"]).and_then(Value::as_str) {
+		colour.clone_into(&mut state.colour);
+	}
+	if let Some(alignment) = non_empty(elgato, &["TitleAlignment"]).and_then(Value::as_str) {
+		alignment.clone_into(&mut state.alignment);
+	}
+	if let Some(family) = non_empty(elgato, &["FontFamily", "FFamily"]).and_then(Value::as_str) {
+		family.clone_into(&mut state.family);
+	}
+	if let Some(style) = non_empty(elgato, &["FontStyle", "FStyle"]).and_then(Value::as_str) {
+		style.clone_into(&mut state.style);
+	}
+	if let Some(Ok(size)) = non_empty(elgato, &["FontSize", "FSize"]).map(|size| serde_json::from_value::<FontSize>(size.clone())) {
+		state.size = size;
+	}
+	if let Some(underline) = non_empty(elgato, &["FontUnderline", "FUnderline"]) {
+		state.underline = underline.as_bool().unwrap_or_else(|| underline.as_str() == Some("on"));
+	}
+}
+
+struct Converter<'a> {
+	archive: &'a Archive,
+	actions: Vec<&'a Action>,
+}
+
+impl Converter<'_> {
+	fn action(&self, uuid: &str) -> Option<Action> {
+		self.actions.iter().find(|action| action.uuid == uuid).map(|action| (*action).clone())
+	}
+
+	/// Convert an action from an Elgato profile into an instance, returning `None` if the action is not installed.
+	fn instance(&self, value: &Value, base: &str, coordinates: &str, context: ActionContext) -> Option<ActionInstance> {
+		let uuid = value["UUID"].as_str()?;
+
+		// Multi action switches have a second sequence of actions, and the sequence of each of their children is kept in their settings.
+		let mut sequences = Map::new();
+		let (action, children) = if uuid.starts_with("com.elgato.streamdeck.multiactions.routine") {
+			let switch = uuid == "com.elgato.streamdeck.multiactions.routine2";
+			let mut children: Vec<ActionInstance> = vec![];
+			for (sequence, actions) in value["Actions"].as_array().into_iter().flatten().take(if switch { 2 } else { 1 }).enumerate() {
+				for child in actions["Actions"].as_array().into_iter().flatten() {
+					let Some(mut child) = self.instance(child, base, coordinates, ActionContext { index: 0, ..context.clone() }) else {
+						continue;
+					};
+					child.context.index = children.len() as u16 + 1;
+					if switch {
+						sequences.insert(child.context.index.to_string(), sequence.into());
+					}
+					children.push(child);
+				}
+			}
+			(self.action(if switch { "opendeck.multiactionswitch" } else { "opendeck.multiaction" })?, Some(children))
+		} else {
+			(self.action(uuid)?, None)
+		};
+
+		let elgato_states = value["States"].as_array().cloned().unwrap_or_default();
+		let states = action
+			.states
+			.iter()
+			.enumerate()
+			.map(|(index, default)| {
+				let mut state = default.clone();
+				if let Some(elgato) = elgato_states.get(index) {
+					apply_state(&mut state, elgato);
+					if let Some(image) = self.archive.image(base, coordinates, index, elgato["Image"].as_str().unwrap_or_default()) {
+						state.image = image;
+					}
+				}
+				state
+			})
+			.collect::<Vec<_>>();
+
+		let mut settings = match &value["Settings"] {
+			Value::Object(settings) => settings.clone(),
+			_ => Map::new(),
+		};
+		if !sequences.is_empty() {
+			settings.insert("sequences".to_owned(), Value::Object(sequences));
+		}
+
+		Some(ActionInstance {
+			current_state: (value["State"].as_u64().unwrap_or(0) as u16).min(states.len().saturating_sub(1) as u16),
+			settings: Value::Object(settings),
+			action,
+			context,
+			states,
+			children,
+			dial: Default::default(),
+		})
+	}
+
+	/// Convert a page of an Elgato profile into the page at the given index of a profile for the given device.
+	fn page(&self, base: &str, manifest: &Value, device: &DeviceInfo, id: &str, page: u8) -> Page {
+		let mut keys = vec![None; device.key_count()];
+		let mut sliders = vec![None; device.encoders as usize];
+
+		let controllers = match manifest["Controllers"].as_array() {
+			Some(controllers) => controllers.iter().map(|controller| (controller["Type"].as_str().unwrap_or("Keypad"), &controller["Actions"])).collect(),
+			None => vec![("Keypad", &manifest["Actions"])],
+		};
+
+		for (controller, actions) in controllers {
+			let Some(actions) = actions.as_object() else { continue };
+			for (coordinates, value) in actions {
+				let Some((Ok(column), Ok(row))) = coordinates.split_once(',').map(|(column, row)| (column.trim().parse::<u8>(), row.trim().parse::<u8>())) else {
+					continue;
+				};
+				let (slots, slot, size) = match controller {
+					"Encoder" if column < device.encoders => (&mut sliders, column as usize, device.encoders as usize),
+					"Keypad" if column < device.columns && row < device.rows => (&mut keys, row as usize * device.columns as usize + column as usize, device.key_count()),
+					_ => continue,
+				};
+				let Ok(position) = u8::try_from(page as usize * size + slot) else { continue };
+				let context = ActionContext {
+					device: device.id.clone(),
+					profile: id.to_owned(),
+					controller: controller.to_owned(),
+					position,
+					index: 0,
+				};
+				slots[slot] = self.instance(value, base, coordinates, context);
+			}
+		}
+
+		Page { keys, sliders }
+	}
+}
+
+/// Convert a `.streamDeckProfile` archive into a profile with the given ID for a device.
+/// Actions from plugins that are not installed are left out.
+pub async fn import(bytes: &[u8], device: &DeviceInfo, id: &str) -> Result<Profile, anyhow::Error> {
+	let archive = Archive::new(bytes)?;
+	let pages = archive.pages()?;
+	if pages.is_empty() {
+		return Err(anyhow!("profile has no pages"));
+	}
+
+	let categories = CATEGORIES.read().await;
+	let converter = Converter {
+		archive: &archive,
+		actions: categories.values().flatten().collect(),
+	};
+	// Positions are stored as a single byte, which limits how many pages fit in a profile.
+	let size = device.key_count().max(device.encoders as usize).max(1);
+	if pages.len() * size > 256 {
+		log::warn!("Only the first {} of the {} pages of the imported profile fit on device {}", 256 / size, pages.len(), device.id);
+	}
+	let pages = pages
+		.iter()
+		.take(256 / size)
+		.enumerate()
+		.map(|(page, (base, manifest))| converter.page(base, manifest, device, id, page as u8))
+		.collect();
+
+	Ok(Profile { id: id.to_owned(), pages })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+
+	pub(crate) fn device() -> DeviceInfo {
+		DeviceInfo {
+			id: "sd-elgato".to_owned(),
+			plugin: String::new(),
+			name: "Test".to_owned(),
+			rows: 3,
+			columns: 5,
+			encoders: 0,
+			r#type: 0,
+			orientation: 0,
+		}
+	}
+
+	/// Install the action used by the fixtures, leaving `com.example.elgato.missing` uninstalled.
+	pub(crate) async fn install_action() {
+		let action: Action = serde_json::from_value(serde_json::json!({
+			"name": "Test",
+			"uuid": "com.example.elgato.test",
+			"plugin": "com.example.elgato",
+			"states": [ {}, {} ]
+		}))
+		.unwrap();
+		CATEGORIES.write().await.insert("Elgato Import Test".to_owned(), vec![action]);
+	}
+
+	/// Get the slot, context and title of each instance on a page.
+	fn instances(page: &Page) -> Vec<(usize, String, String)> {
+		page.keys
+			.iter()
+			.enumerate()
+			.filter_map(|(slot, instance)| Some((slot, instance.as_ref()?.context.to_string(), instance.as_ref()?.states[0].text.clone())))
+			.collect()
+	}
+
+	#[tokio::test]
+	async fn import_single_page() {
+		install_action().await;
+		let profile = import(include_bytes!("../../fixtures/elgato/single-page.streamDeckProfile"), &device(), "Imported").await.unwrap();
+		assert_eq!(profile.id, "Imported");
+		assert_eq!(profile.pages.len(), 1);
+		// The action at 2,1 is not installed, so it is left out.
+		assert_eq!(
+			instances(&profile.pages[0]),
+			vec![
+				(0, "sd-elgato.Imported.Keypad.0.0".to_owned(), "First".to_owned()),
+				(14, "sd-elgato.Imported.Keypad.14.0".to_owned(), "Last".to_owned()),
+			]
+		);
+
+		let first = profile.pages[0].keys[0].as_ref().unwrap();
+		assert_eq!(first.settings["title"], "First");
+		// The image stored alongside the action is used for the state it was stored for.
+		assert!(first.states[0].image.starts_with("data:image/png;base64,"));
+		assert!(!first.states[1].image.starts_with("data:"));
+		assert_eq!(profile.pages[0].keys[14].as_ref().unwrap().current_state, 1);
+	}
+
+	#[tokio::test]
+	async fn import_multiple_pages() {
+		install_action().await;
+		let profile = import(include_bytes!("../../fixtures/elgato/multi-page.streamDeckProfile"), &device(), "Imported").await.unwrap();
+		assert_eq!(profile.pages.len(), 2);
+		assert!(profile.pages.iter().all(|page| page.keys.len() == 15 && page.sliders.is_empty()));
+
+		// The action at 3,1 of the first page is not installed.
+		assert_eq!(instances(&profile.pages[0]), vec![(0, "sd-elgato.Imported.Keypad.0.0".to_owned(), "One".to_owned())]);
+		assert!(profile.pages[0].keys[0].as_ref().unwrap().states[0].image.starts_with("data:image/png;base64,"));
+
+		// The instances on the second page are positioned after those on the first, and the action in a column the device does not have is left out.
+		assert_eq!(instances(&profile.pages[1]), vec![(1, "sd-elgato.Imported.Keypad.16.0".to_owned(), "Two".to_owned())]);
+	}
+}
@@ -1,4 +1,6 @@
+pub mod elgato_profile;
 pub mod profiles;
+pub mod remap;
 mod simplified_profile;
 
 use std::fs;
@@ -64,6 +66,14 @@ where
 	}
 }
 
+/// A time of day from which devices are shown at a percentage of their brightness.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ScheduledBrightness {
+	/// The time of day in the `HH:MM` format.
+	pub time: String,
+	pub level: u8,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(default)]
 pub struct Settings {
@@ -73,6 +83,12 @@ pub struct Settings {
 	pub autolaunch: bool,
 	pub darktheme: bool,
 	pub brightness: u8,
+	pub brightnessschedule: Vec<ScheduledBrightness>,
+	/// How long it takes to fade between levels in the brightness schedule, in minutes.
+	pub brightnessfade: u32,
+	/// What swiping across the touchscreen of a device does, which is nothing unless it has been chosen.
+	pub swipeaction: String,
+	pub feedbackduration: u64,
 	pub developer: bool,
 	pub updatecheck: bool,
 	pub statistics: bool,
@@ -87,6 +103,10 @@ impl Default for Settings {
 			autolaunch: false,
 			darktheme: true,
 			brightness: 50,
+			brightnessschedule: vec![],
+			brightnessfade: 15,
+			swipeaction: "none".to_owned(),
+			feedbackduration: 1500,
 			developer: false,
 			updatecheck: true,
 			// Consent is given by the user on install so it is OK to have the default be `true`
@@ -1,8 +1,8 @@
 use super::{
-	simplified_profile::{DiskActionInstance, DiskProfile},
+	simplified_profile::{DiskActionInstance, DiskPage, DiskProfile},
 	Store,
 };
-use crate::shared::{config_dir, Action, ActionInstance, ActionState, DeviceInfo, Profile, DEVICES};
+use crate::shared::{config_dir, Action, ActionInstance, ActionState, DeviceInfo, Page, Profile, DEVICES};
 
 use std::collections::HashMap;
 use std::fs;
@@ -39,13 +39,11 @@ impl ProfileStores {
 		} else {
 			let default = Profile {
 				id: id.to_owned(),
-				keys: Vec::new(),
-				sliders: Vec::new(),
+				pages: vec![Page::default()],
 			};
 
 			let mut store = Store::new(&canonical_id, &config_dir().join("profiles"), default).context(format!("Failed to create store for profile {}", canonical_id))?;
-			store.value.keys.resize((device.rows * device.columns) as usize, None);
-			store.value.sliders.resize(device.encoders as usize, None);
+			crate::pages::fit_pages(&mut store.value, device);
 
 			let categories = crate::shared::CATEGORIES.read().await;
 			let actions = categories.values().flatten().collect::<Vec<_>>();
@@ -55,7 +53,7 @@ impl ProfileStores {
 				instance.action.plugin == "opendeck"
 					|| (plugins_dir.join(&instance.action.plugin).exists() && (!registered.contains(&instance.action.plugin) || actions.iter().any(|v| v.uuid == instance.action.uuid)))
 			};
-			for slot in store.value.keys.iter_mut() {
+			for slot in store.value.pages.iter_mut().flat_map(|page| page.keys.iter_mut()) {
 				if let Some(instance) = slot {
 					if !keep_instance(instance) {
 						*slot = None;
@@ -71,6 +69,33 @@ impl ProfileStores {
 		}
 	}
 
+	/// Replace a profile with one created elsewhere, such as by importing it, and save it to disk.
+	pub fn insert_profile(&mut self, device: &str, profile: Profile) -> Result<(), anyhow::Error> {
+		let canonical_id = Self::canonical_id(device, &profile.id);
+		let mut store = Store::new(&canonical_id, &config_dir().join("profiles"), profile.clone()).context(format!("Failed to create store for profile {}", canonical_id))?;
+		store.value = profile;
+		store.save()?;
+		self.stores.insert(canonical_id, store);
+		Ok(())
+	}
+
+	/// Get a copy of a profile, reading it from disk if it is not loaded, such as when its device is disconnected.
+	pub fn read_profile(&self, device: &str, id: &str) -> Result<Profile, anyhow::Error> {
+		let canonical_id = Self::canonical_id(device, id);
+		if let Some(store) = self.stores.get(&canonical_id) {
+			return Ok(store.value.clone());
+		}
+		let profiles_dir = config_dir().join("profiles");
+		if !profiles_dir.join(format!("{canonical_id}.json")).exists() {
+			return Err(anyhow!("profile not found"));
+		}
+		let default = Profile {
+			id: id.to_owned(),
+			pages: vec![Page::default()],
+		};
+		Ok(Store::new(&canonical_id, &profiles_dir, default).context(format!("Failed to read profile {}", canonical_id))?.value)
+	}
+
 	pub fn remove_profile(&mut self, device: &str, id: &str) {
 		self.stores.remove(&Self::canonical_id(device, id));
 	}
@@ -91,7 +116,7 @@ impl ProfileStores {
 	pub fn all_from_plugin(&self, plugin: &str) -> Vec<crate::shared::ActionContext> {
 		let mut all = vec![];
 		for store in self.stores.values() {
-			for instance in store.value.keys.iter().chain(&store.value.sliders).flatten() {
+			for instance in store.value.pages.iter().flat_map(|page| page.keys.iter().chain(&page.sliders)).flatten() {
 				if instance.action.plugin == plugin {
 					all.push(instance.context.clone());
 				}
@@ -104,6 +129,54 @@ impl ProfileStores {
 #[derive(Serialize, Deserialize)]
 pub struct DeviceConfig {
 	pub selected_profile: String,
+	/// How far the device is rotated clockwise from its usual orientation, in degrees.
+	#[serde(default)]
+	pub orientation: u16,
+	/// The brightness of the device, or `None` to use the brightness in settings.
+	#[serde(default)]
+	pub brightness: Option<u8>,
+	#[serde(default)]
+	pub idle: IdleConfig,
+	/// The metadata of the device when it was last connected, so that its profiles can be copied to other devices while it is disconnected.
+	#[serde(default)]
+	pub info: Option<DeviceInfo>,
+}
+
+impl Default for DeviceConfig {
+	fn default() -> Self {
+		Self {
+			selected_profile: "Default".to_owned(),
+			orientation: 0,
+			brightness: None,
+			idle: IdleConfig::default(),
+			info: None,
+		}
+	}
+}
+
+/// What a device does after going without input for a while.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IdleConfig {
+	/// How long the device must go without input to become idle, in minutes, where 0 disables idling.
+	pub timeout: u32,
+	/// One of `dim`, `image`, `clock` or `off`.
+	pub mode: String,
+	/// The brightness that the device is dimmed to in the `dim` mode.
+	pub level: u8,
+	/// The image shown across the keys of the device in the `image` mode.
+	pub image: String,
+}
+
+impl Default for IdleConfig {
+	fn default() -> Self {
+		Self {
+			timeout: 0,
+			mode: "dim".to_owned(),
+			level: 10,
+			image: String::new(),
+		}
+	}
 }
 
 impl super::NotProfile for DeviceConfig {}
@@ -115,9 +188,7 @@ pub struct DeviceStores {
 impl DeviceStores {
 	pub fn get_selected_profile(&mut self, device: &str) -> Result<String, anyhow::Error> {
 		if !self.stores.contains_key(device) {
-			let default = DeviceConfig {
-				selected_profile: "Default".to_owned(),
-			};
+			let default = DeviceConfig::default();
 
 			let store = Store::new(device, &config_dir().join("profiles"), default).context(format!("Failed to create store for device config {}", device))?;
 			store.save()?;
@@ -140,7 +211,10 @@ impl DeviceStores {
 			store.value.selected_profile = id;
 			store.save()?;
 		} else {
-			let default = DeviceConfig { selected_profile: id };
+			let default = DeviceConfig {
+				selected_profile: id,
+				..Default::default()
+			};
 
 			let store = Store::new(device, &config_dir().join("profiles"), default).context(format!("Failed to create store for device config {}", device))?;
 			store.save()?;
@@ -149,6 +223,26 @@ impl DeviceStores {
 		}
 		Ok(())
 	}
+
+	/// Read a value from the config of a device.
+	/// The config is read without being created if it has not been loaded yet, as creating it requires the device to be registered.
+	pub fn read_config<T>(&self, device: &str, read: impl FnOnce(&DeviceConfig) -> T) -> Result<T, anyhow::Error> {
+		if let Some(store) = self.stores.get(device) {
+			return Ok(read(&store.value));
+		}
+		let store = Store::new(device, &config_dir().join("profiles"), DeviceConfig::default()).context(format!("Failed to read device config {}", device))?;
+		Ok(read(&store.value))
+	}
+
+	/// Modify the config of a device and save it.
+	pub fn update_config(&mut self, device: &str, update: impl FnOnce(&mut DeviceConfig)) -> Result<(), anyhow::Error> {
+		// This creates the store for the device if it does not exist yet.
+		self.get_selected_profile(device)?;
+		let store = self.stores.get_mut(device).unwrap();
+		update(&mut store.value);
+		store.save()?;
+		Ok(())
+	}
 }
 
 #[derive(Deserialize)]
@@ -159,7 +253,39 @@ struct ProfileV1 {
 	sliders: Vec<Vec<ActionInstance>>,
 }
 
-impl From<ProfileV1> for DiskProfile {
+/// The format of profiles on disk before they could hold multiple pages.
+#[derive(Deserialize)]
+struct DiskProfileV3 {
+	keys: Vec<Option<DiskActionInstance>>,
+	sliders: Vec<Option<DiskActionInstance>>,
+}
+
+impl From<DiskProfileV3> for DiskProfile {
+	fn from(val: DiskProfileV3) -> Self {
+		Self {
+			pages: vec![DiskPage { keys: val.keys, sliders: val.sliders }],
+		}
+	}
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct ProfileV2 {
+	id: String,
+	keys: Vec<Option<ActionInstance>>,
+	sliders: Vec<Option<ActionInstance>>,
+}
+
+impl From<ProfileV2> for DiskProfileV3 {
+	fn from(val: ProfileV2) -> Self {
+		Self {
+			keys: val.keys.into_iter().map(|x| x.map(|v| v.into())).collect(),
+			sliders: val.sliders.into_iter().map(|x| x.map(|v| v.into())).collect(),
+		}
+	}
+}
+
+impl From<ProfileV1> for DiskProfileV3 {
 	fn from(val: ProfileV1) -> Self {
 		let mut keys = vec![];
 		for slot in val.keys {
@@ -186,6 +312,7 @@ impl From<ProfileV1> for DiskProfile {
 							image: "opendeck/multi-action.png".to_owned(),
 							..Default::default()
 						}],
+						encoder: None,
 					},
 					context: slot[0].context.clone().into(),
 					states: vec![ActionState {
@@ -195,6 +322,7 @@ impl From<ProfileV1> for DiskProfile {
 					current_state: 0,
 					settings: Value::Object(serde_json::Map::new()),
 					children: Some(children.into_iter().map(|v| v.into()).collect()),
+					dial: Default::default(),
 				}));
 			} else {
 				keys.push(None);
@@ -217,16 +345,18 @@ impl From<ProfileV1> for DiskProfile {
 #[allow(dead_code)]
 enum ProfileVersions {
 	V1(ProfileV1),
-	V2(Profile),
-	V3(DiskProfile),
+	V2(ProfileV2),
+	V3(DiskProfileV3),
+	V4(DiskProfile),
 }
 
 fn migrate_profile(path: PathBuf) -> Result<(), anyhow::Error> {
 	let profile = serde_json::from_slice(&fs::read(&path)?)?;
 	let migrated: DiskProfile = match profile {
-		ProfileVersions::V1(v1) => v1.into(),
-		ProfileVersions::V2(v2) => (&v2).into(),
-		ProfileVersions::V3(v3) => v3,
+		ProfileVersions::V1(v1) => DiskProfileV3::from(v1).into(),
+		ProfileVersions::V2(v2) => DiskProfileV3::from(v2).into(),
+		ProfileVersions::V3(v3) => v3.into(),
+		ProfileVersions::V4(v4) => v4,
 	};
 	let mut as_value = serde_json::to_value(migrated)?;
 	fn replace_old_identifier(value: &mut Value) {
@@ -328,9 +458,11 @@ pub async fn get_slot<'a>(context: &crate::shared::Context, locks: &'a Locks<'_>
 	let device = DEVICES.get(&context.device).ok_or_else(|| anyhow!("device not found"))?;
 	let store = locks.profile_stores.get_profile_store(&device, &context.profile)?;
 
+	let (page, slot) = crate::pages::split_position(&device, &context.controller, context.position);
+	let page = store.value.pages.get(page).ok_or_else(|| anyhow!("index out of bounds"))?;
 	let configured = match &context.controller[..] {
-		"Encoder" => store.value.sliders.get(context.position as usize).ok_or_else(|| anyhow!("index out of bounds"))?,
-		_ => store.value.keys.get(context.position as usize).ok_or_else(|| anyhow!("index out of bounds"))?,
+		"Encoder" => page.sliders.get(slot).ok_or_else(|| anyhow!("index out of bounds"))?,
+		_ => page.keys.get(slot).ok_or_else(|| anyhow!("index out of bounds"))?,
 	};
 
 	Ok(configured)
@@ -340,9 +472,11 @@ pub async fn get_slot_mut<'a>(context: &crate::shared::Context, locks: &'a mut L
 	let device = DEVICES.get(&context.device).ok_or_else(|| anyhow!("device not found"))?;
 	let store = locks.profile_stores.get_profile_store_mut(&device, &context.profile).await?;
 
+	let (page, slot) = crate::pages::split_position(&device, &context.controller, context.position);
+	let page = store.value.pages.get_mut(page).ok_or_else(|| anyhow!("index out of bounds"))?;
 	let configured = match &context.controller[..] {
-		"Encoder" => store.value.sliders.get_mut(context.position as usize).ok_or_else(|| anyhow!("index out of bounds"))?,
-		_ => store.value.keys.get_mut(context.position as usize).ok_or_else(|| anyhow!("index out of bounds"))?,
+		"Encoder" => page.sliders.get_mut(slot).ok_or_else(|| anyhow!("index out of bounds"))?,
+		_ => page.keys.get_mut(slot).ok_or_else(|| anyhow!("index out of bounds"))?,
 	};
 
 	Ok(configured)
@@ -386,3 +520,80 @@ pub async fn save_profile<'a>(device: &str, locks: &'a mut LocksMut<'_>) -> Resu
 	let store = locks.profile_stores.get_profile_store(&device, &selected_profile)?;
 	store.save()
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Migrate a copy of a profile as saved by an earlier version, and read back what was written in its place.
+	fn migrate(name: &str, contents: &str) -> DiskProfile {
+		let dir = std::env::temp_dir().join(format!("opendeck-migration-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let path = dir.join(name);
+		fs::write(&path, contents).unwrap();
+		migrate_profile(path.clone()).unwrap();
+		serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+	}
+
+	fn uuid(slot: &Option<DiskActionInstance>) -> Option<&str> {
+		slot.as_ref().map(|instance| instance.action.uuid.as_str())
+	}
+
+	#[test]
+	fn migrate_v1() {
+		let profile = migrate("v1.json", include_str!("../../fixtures/profiles/v1.json"));
+		assert_eq!(profile.pages.len(), 1);
+		let page = &profile.pages[0];
+
+		assert_eq!(page.keys.len(), 3);
+		assert_eq!(uuid(&page.keys[0]), Some("opendeck.runcommand"));
+		assert_eq!(page.keys[0].as_ref().unwrap().action.plugin, "opendeck");
+		assert_eq!(page.keys[0].as_ref().unwrap().settings["down"], "true");
+		// Slots holding several instances become multi actions.
+		let multi = page.keys[1].as_ref().unwrap();
+		assert_eq!(multi.action.uuid, "opendeck.multiaction");
+		assert_eq!(multi.context.to_string(), "Keypad.1.0");
+		let children = multi.children.as_ref().unwrap();
+		assert_eq!(children.iter().map(|child| child.action.uuid.as_str()).collect::<Vec<_>>(), ["opendeck.runcommand", "opendeck.website"]);
+		assert_eq!(children.iter().map(|child| child.context.to_string()).collect::<Vec<_>>(), ["Keypad.1.1", "Keypad.1.2"]);
+		assert!(page.keys[2].is_none());
+
+		assert_eq!(page.sliders.len(), 2);
+		assert!(page.sliders[0].is_none());
+		assert_eq!(page.sliders[1].as_ref().unwrap().context.to_string(), "Encoder.1.0");
+	}
+
+	#[test]
+	fn migrate_v2() {
+		let profile = migrate("v2.json", include_str!("../../fixtures/profiles/v2.json"));
+		assert_eq!(profile.pages.len(), 1);
+		let page = &profile.pages[0];
+		assert_eq!(page.keys.iter().map(uuid).collect::<Vec<_>>(), [None, Some("opendeck.runcommand")]);
+		assert_eq!(page.keys[1].as_ref().unwrap().context.to_string(), "Keypad.1.0");
+		assert_eq!(page.sliders.len(), 1);
+		assert!(page.sliders[0].is_none());
+	}
+
+	#[test]
+	fn migrate_v3() {
+		let profile = migrate("v3.json", include_str!("../../fixtures/profiles/v3.json"));
+		assert_eq!(profile.pages.len(), 1);
+		let page = &profile.pages[0];
+		assert_eq!(page.keys.iter().map(uuid).collect::<Vec<_>>(), [Some("opendeck.runcommand"), None]);
+		assert_eq!(page.sliders.len(), 2);
+	}
+
+	#[test]
+	fn migrate_v4() {
+		let profile = migrate("v4.json", include_str!("../../fixtures/profiles/v4.json"));
+		assert_eq!(profile.pages.len(), 2);
+		assert_eq!(profile.pages[0].keys.iter().map(uuid).collect::<Vec<_>>(), [Some("opendeck.nextpage"), None]);
+		assert_eq!(profile.pages[1].keys.iter().map(uuid).collect::<Vec<_>>(), [None, Some("opendeck.runcommand")]);
+		assert_eq!(profile.pages[1].keys[1].as_ref().unwrap().context.to_string(), "Keypad.3.0");
+
+		// Migrating a profile that is already in the current format leaves it as it is.
+		let contents = serde_json::to_string_pretty(&profile).unwrap();
+		let again = migrate("v4-again.json", &contents);
+		assert_eq!(serde_json::to_string_pretty(&again).unwrap(), contents);
+	}
+}
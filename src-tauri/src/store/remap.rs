@@ -0,0 +1,441 @@
+//! Copying profiles between devices with different layouts, copying the images of each instance to its new position.
+
+use super::profiles::{get_device_profiles, DEVICE_STORES, PROFILE_STORES};
+use crate::events::frontend::instances::instance_images_dir;
+use crate::shared::{config_dir, ActionContext, ActionInstance, DeviceInfo, Page, Profile, DEVICES};
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+/// How the keys of a profile are placed on a device with a different layout.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RemapStrategy {
+	/// Keep each key in the same row and column, leaving out keys that do not fit.
+	Position,
+	/// Place the keys in reading order, leaving out the empty slots between them if the keys would not fit otherwise.
+	Reflow,
+	/// Keep each key in the same row and column, moving keys that do not fit onto extra pages in reading order.
+	Pages,
+}
+
+#[derive(Serialize)]
+pub struct RemapResult {
+	/// The number of pages of the destination profile, including any extra pages.
+	pub pages: usize,
+	/// The number of instances that did not fit on the destination device.
+	pub dropped: usize,
+}
+
+/// Get the metadata of a device, which is that recorded when it was last connected if it is not connected now.
+async fn device_info(device: &str) -> Result<DeviceInfo, anyhow::Error> {
+	if let Some(info) = DEVICES.get(device) {
+		return Ok(info.clone());
+	}
+	DEVICE_STORES
+		.read()
+		.await
+		.read_config(device, |config| config.info.clone())?
+		.ok_or_else(|| anyhow!("the layout of device {device} is not known as it has not been connected since updating OpenDeck"))
+}
+
+/// Get the metadata of all connected devices and of disconnected devices whose layouts are known, so that profiles can be copied from them.
+pub async fn known_devices() -> Result<Vec<DeviceInfo>, anyhow::Error> {
+	let mut devices: Vec<DeviceInfo> = DEVICES.iter().map(|device| device.value().clone()).collect();
+	let device_stores = DEVICE_STORES.read().await;
+	for entry in fs::read_dir(config_dir().join("profiles"))?.flatten() {
+		let name = entry.file_name().to_string_lossy().into_owned();
+		let Some(device) = name.strip_suffix(".json") else { continue };
+		if devices.iter().any(|info| info.id == device) {
+			continue;
+		}
+		if let Ok(Some(info)) = device_stores.read_config(device, |config| config.info.clone()) {
+			devices.push(info);
+		}
+	}
+	Ok(devices)
+}
+
+/// Place the instances in the key slots of a profile onto pages of a layout with a different size, returning the pages and the number of instances left out.
+fn place_keys(keys: Vec<Option<ActionInstance>>, from: &DeviceInfo, to: &DeviceInfo, strategy: RemapStrategy) -> (Vec<Vec<Option<ActionInstance>>>, usize) {
+	let size = to.key_count();
+	let mut page = vec![None; size];
+	let mut overflow = vec![];
+
+	match strategy {
+		RemapStrategy::Position | RemapStrategy::Pages => {
+			for (position, instance) in keys.into_iter().enumerate() {
+				let Some(instance) = instance else { continue };
+				// Keys recorded for a layout without columns have no row or column to keep.
+				let Some(row) = position.checked_div(from.columns as usize) else {
+					overflow.push(instance);
+					continue;
+				};
+				let column = position % from.columns as usize;
+				if row < to.rows as usize && column < to.columns as usize {
+					page[row * to.columns as usize + column] = Some(instance);
+				} else {
+					overflow.push(instance);
+				}
+			}
+		}
+		RemapStrategy::Reflow => {
+			let used = keys.iter().rposition(Option::is_some).map_or(0, |last| last + 1);
+			if used <= size {
+				for (position, instance) in keys.into_iter().take(used).enumerate() {
+					page[position] = instance;
+				}
+			} else {
+				let mut instances = keys.into_iter().flatten();
+				for (slot, instance) in page.iter_mut().zip(&mut instances) {
+					*slot = Some(instance);
+				}
+				overflow.extend(instances);
+			}
+		}
+	}
+
+	let mut pages = vec![page];
+	if matches!(strategy, RemapStrategy::Pages) && size > 0 {
+		for chunk in overflow.chunks(size) {
+			let mut page: Vec<_> = chunk.iter().cloned().map(Some).collect();
+			page.resize(size, None);
+			pages.push(page);
+		}
+		(pages, 0)
+	} else {
+		(pages, overflow.len())
+	}
+}
+
+/// Place the instances in the slider slots of a profile onto pages with a different number of encoders, returning the pages and the number of instances left out.
+fn place_sliders(sliders: Vec<Option<ActionInstance>>, to: &DeviceInfo, strategy: RemapStrategy) -> (Vec<Vec<Option<ActionInstance>>>, usize) {
+	let size = to.encoders as usize;
+	let mut page = vec![None; size];
+	let mut overflow = vec![];
+	for (position, instance) in sliders.into_iter().enumerate() {
+		let Some(instance) = instance else { continue };
+		if position < size {
+			page[position] = Some(instance);
+		} else {
+			overflow.push(instance);
+		}
+	}
+
+	let mut pages = vec![page];
+	if matches!(strategy, RemapStrategy::Pages) && size > 0 {
+		for chunk in overflow.chunks(size) {
+			let mut page: Vec<_> = chunk.iter().cloned().map(Some).collect();
+			page.resize(size, None);
+			pages.push(page);
+		}
+		(pages, 0)
+	} else {
+		(pages, overflow.len())
+	}
+}
+
+/// Copy the images of an instance to the directory for its new context and point its states at the copies.
+fn copy_images(instance: &mut ActionInstance, old_dir: &Path, new_dir: &Path) {
+	if let Ok(files) = old_dir.read_dir() {
+		let _ = fs::create_dir_all(new_dir);
+		for file in files.flatten() {
+			let _ = fs::copy(file.path(), new_dir.join(file.file_name()));
+		}
+	}
+	for state in instance.states.iter_mut() {
+		let path = Path::new(&state.image);
+		if path.starts_with(old_dir) {
+			state.image = new_dir.join(path.strip_prefix(old_dir).unwrap()).to_string_lossy().into_owned();
+		}
+	}
+}
+
+/// Move an instance and its children to a new context, copying their images.
+pub fn relocate(mut instance: ActionInstance, context: ActionContext) -> ActionInstance {
+	let old_dir = instance_images_dir(&instance.context);
+	copy_images(&mut instance, &old_dir, &instance_images_dir(&context));
+	if let Some(children) = &mut instance.children {
+		for child in children.iter_mut() {
+			let child_context = ActionContext {
+				index: child.context.index,
+				..context.clone()
+			};
+			*child = relocate(child.clone(), child_context);
+		}
+	}
+	instance.context = context;
+	instance
+}
+
+/// Move the instances on a page to the slots of a controller in a new profile, where `context` is that of the first slot of the page.
+fn relocate_page(mut slots: Vec<Option<ActionInstance>>, size: usize, context: &ActionContext) -> Vec<Option<ActionInstance>> {
+	slots.resize(size, None);
+	slots
+		.into_iter()
+		.enumerate()
+		.map(|(position, slot)| {
+			slot.map(|instance| {
+				let context = ActionContext {
+					position: context.position + position as u8,
+					..context.clone()
+				};
+				relocate(instance, context)
+			})
+		})
+		.collect()
+}
+
+/// Place every page of a profile onto a layout with a different size, returning the pages and the number of instances left out.
+/// Each page keeps its index, so that folders and page actions still open the same pages, and the extra pages of the `Pages` strategy are added after them.
+fn remap_pages(pages: Vec<Page>, from: &DeviceInfo, to: &DeviceInfo, strategy: RemapStrategy) -> (Vec<Page>, usize) {
+	let mut remapped = vec![];
+	let mut extra = vec![];
+	let mut dropped = 0;
+	for Page { keys, sliders } in pages {
+		let (key_pages, dropped_keys) = place_keys(keys, from, to, strategy);
+		let (slider_pages, dropped_sliders) = place_sliders(sliders, to, strategy);
+		dropped += dropped_keys + dropped_sliders;
+		let count = key_pages.len().max(slider_pages.len());
+		let (mut key_pages, mut slider_pages) = (key_pages.into_iter(), slider_pages.into_iter());
+		let mut pages = (0..count).map(|_| Page {
+			keys: key_pages.next().unwrap_or_else(|| vec![None; to.key_count()]),
+			sliders: slider_pages.next().unwrap_or_else(|| vec![None; to.encoders as usize]),
+		});
+		remapped.extend(pages.next());
+		extra.extend(pages);
+	}
+	remapped.extend(extra);
+	(remapped, dropped)
+}
+
+/// Copy a profile from one device to a new profile on another device with a different layout.
+/// Every page of the profile is copied, and with the `Pages` strategy, keys that do not fit are placed on extra pages at the end of the new profile.
+pub async fn remap_profile(source_device: &str, source_profile: &str, destination_device: &str, destination_profile: &str, strategy: RemapStrategy) -> Result<RemapResult, anyhow::Error> {
+	let from = device_info(source_device).await?;
+	let to = device_info(destination_device).await?;
+	let mut profile = PROFILE_STORES.read().await.read_profile(source_device, source_profile)?;
+	if get_device_profiles(destination_device)?.iter().any(|id| id == destination_profile) {
+		return Err(anyhow!("profile {destination_profile} already exists on device {destination_device}"));
+	}
+
+	crate::pages::fit_pages(&mut profile, &from);
+	let (pages, dropped) = remap_pages(profile.pages, &from, &to, strategy);
+	let (key_size, slider_size) = (to.key_count(), to.encoders as usize);
+	// Positions are stored as a single byte, which limits how many pages fit in a profile.
+	let limit = 256 / key_size.max(slider_size).max(1);
+	if pages.len() > limit {
+		return Err(anyhow!(
+			"the profile needs {} pages on device {destination_device}, which fits at most {limit} as each key is numbered with a single byte across all of its pages",
+			pages.len()
+		));
+	}
+
+	let context = ActionContext {
+		device: destination_device.to_owned(),
+		profile: destination_profile.to_owned(),
+		controller: "Keypad".to_owned(),
+		position: 0,
+		index: 0,
+	};
+	let pages: Vec<Page> = pages
+		.into_iter()
+		.enumerate()
+		.map(|(index, page)| Page {
+			keys: relocate_page(
+				page.keys,
+				key_size,
+				&ActionContext {
+					position: (index * key_size) as u8,
+					..context.clone()
+				},
+			),
+			sliders: relocate_page(
+				page.sliders,
+				slider_size,
+				&ActionContext {
+					controller: "Encoder".to_owned(),
+					position: (index * slider_size) as u8,
+					..context.clone()
+				},
+			),
+		})
+		.collect();
+
+	let count = pages.len();
+	PROFILE_STORES.write().await.insert_profile(
+		destination_device,
+		Profile {
+			id: destination_profile.to_owned(),
+			pages,
+		},
+	)?;
+
+	Ok(RemapResult { pages: count, dropped })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn device(rows: u8, columns: u8, encoders: u8) -> DeviceInfo {
+		DeviceInfo {
+			id: "sd-test".to_owned(),
+			plugin: String::new(),
+			name: "Test".to_owned(),
+			rows,
+			columns,
+			encoders,
+			r#type: 0,
+			orientation: 0,
+		}
+	}
+
+	/// Create an instance identified by a number in its settings.
+	fn instance(number: u64) -> ActionInstance {
+		let action: crate::shared::Action = serde_json::from_value(serde_json::json!({ "name": "Test", "uuid": "com.example.test", "states": [{}] })).unwrap();
+		ActionInstance {
+			states: action.states.clone(),
+			action,
+			context: "sd-test.Default.Keypad.0.0".parse().unwrap(),
+			current_state: 0,
+			settings: serde_json::json!({ "number": number }),
+			children: None,
+			dial: Default::default(),
+		}
+	}
+
+	/// Lay out instances at positions on a layout with a number of slots, numbering each instance by its position.
+	fn keys(size: usize, positions: &[usize]) -> Vec<Option<ActionInstance>> {
+		(0..size).map(|position| positions.contains(&position).then(|| instance(position as u64))).collect()
+	}
+
+	/// Get the numbers of the instances on each page, with `None` for empty slots.
+	fn numbers(pages: &[Vec<Option<ActionInstance>>]) -> Vec<Vec<Option<u64>>> {
+		pages
+			.iter()
+			.map(|page| page.iter().map(|slot| slot.as_ref().and_then(|instance| instance.settings["number"].as_u64())).collect())
+			.collect()
+	}
+
+	#[test]
+	fn position_keeps_rows_and_columns() {
+		// On a 3x5 layout, 4 is at the end of the first row, 6 is in the second row and 12 is in the third row.
+		let (pages, dropped) = place_keys(keys(15, &[0, 4, 6, 12]), &device(3, 5, 0), &device(2, 3, 0), RemapStrategy::Position);
+		assert_eq!(numbers(&pages), vec![vec![Some(0), None, None, None, Some(6), None]]);
+		assert_eq!(dropped, 2);
+	}
+
+	#[test]
+	fn position_to_larger_layout() {
+		let (pages, dropped) = place_keys(keys(6, &[0, 5]), &device(2, 3, 0), &device(3, 5, 0), RemapStrategy::Position);
+		let mut expected = vec![None; 15];
+		expected[0] = Some(0);
+		expected[7] = Some(5);
+		assert_eq!(numbers(&pages), vec![expected]);
+		assert_eq!(dropped, 0);
+	}
+
+	#[test]
+	fn reflow_keeps_gaps_when_keys_fit() {
+		let (pages, dropped) = place_keys(keys(5, &[0, 3]), &device(1, 5, 0), &device(2, 3, 0), RemapStrategy::Reflow);
+		assert_eq!(numbers(&pages), vec![vec![Some(0), None, None, Some(3), None, None]]);
+		assert_eq!(dropped, 0);
+	}
+
+	#[test]
+	fn reflow_closes_gaps_when_keys_do_not_fit() {
+		let (pages, dropped) = place_keys(keys(15, &[0, 4, 6, 12]), &device(3, 5, 0), &device(2, 3, 0), RemapStrategy::Reflow);
+		assert_eq!(numbers(&pages), vec![vec![Some(0), Some(4), Some(6), Some(12), None, None]]);
+		assert_eq!(dropped, 0);
+
+		let (pages, dropped) = place_keys(keys(15, &[0, 1, 2, 3, 4, 5, 6, 14]), &device(3, 5, 0), &device(2, 3, 0), RemapStrategy::Reflow);
+		assert_eq!(numbers(&pages), vec![vec![Some(0), Some(1), Some(2), Some(3), Some(4), Some(5)]]);
+		assert_eq!(dropped, 2);
+	}
+
+	#[test]
+	fn pages_moves_overflow_to_extra_pages() {
+		let (pages, dropped) = place_keys(keys(15, &[0, 4, 6, 12]), &device(3, 5, 0), &device(2, 3, 0), RemapStrategy::Pages);
+		assert_eq!(numbers(&pages), vec![vec![Some(0), None, None, None, Some(6), None], vec![Some(4), Some(12), None, None, None, None]]);
+		assert_eq!(dropped, 0);
+
+		// Keys that do not fit on a layout with a single key are each moved onto a page of their own.
+		let (pages, dropped) = place_keys(keys(8, &[1, 2, 3, 4, 5, 6, 7]), &device(1, 8, 0), &device(1, 1, 0), RemapStrategy::Pages);
+		assert_eq!(pages.len(), 8);
+		assert_eq!(numbers(&pages)[7], vec![Some(7)]);
+		assert_eq!(dropped, 0);
+	}
+
+	#[test]
+	fn layouts_without_keys() {
+		// Keys recorded for a layout without columns are left out rather than dividing by zero.
+		let (pages, dropped) = place_keys(keys(2, &[0, 1]), &device(1, 0, 0), &device(2, 3, 0), RemapStrategy::Position);
+		assert_eq!(numbers(&pages), vec![vec![None; 6]]);
+		assert_eq!(dropped, 2);
+
+		// Layouts without keys cannot take any keys, even on extra pages.
+		let (pages, dropped) = place_keys(keys(3, &[0, 2]), &device(1, 3, 0), &device(0, 0, 0), RemapStrategy::Pages);
+		assert_eq!(numbers(&pages), vec![Vec::<Option<u64>>::new()]);
+		assert_eq!(dropped, 2);
+	}
+
+	#[test]
+	fn every_page_is_remapped() {
+		// The instances on the second page are numbered from 100 to tell them apart from those on the first.
+		let second: Vec<_> = keys(15, &[0, 12])
+			.into_iter()
+			.map(|slot| slot.map(|first| instance(first.settings["number"].as_u64().unwrap() + 100)))
+			.collect();
+		let source = || {
+			vec![
+				Page {
+					keys: keys(15, &[0, 4, 6]),
+					sliders: vec![],
+				},
+				Page {
+					keys: second.clone(),
+					sliders: vec![],
+				},
+			]
+		};
+
+		let (pages, dropped) = remap_pages(source(), &device(3, 5, 0), &device(2, 3, 0), RemapStrategy::Position);
+		let key_pages = pages.into_iter().map(|page| page.keys).collect::<Vec<_>>();
+		assert_eq!(numbers(&key_pages), vec![vec![Some(0), None, None, None, Some(6), None], vec![Some(100), None, None, None, None, None]]);
+		assert_eq!(dropped, 2);
+
+		// The pages keep their indices, and the keys that do not fit on either are moved onto pages after them.
+		let (pages, dropped) = remap_pages(source(), &device(3, 5, 0), &device(2, 3, 0), RemapStrategy::Pages);
+		let key_pages = pages.into_iter().map(|page| page.keys).collect::<Vec<_>>();
+		assert_eq!(
+			numbers(&key_pages),
+			vec![
+				vec![Some(0), None, None, None, Some(6), None],
+				vec![Some(100), None, None, None, None, None],
+				vec![Some(4), None, None, None, None, None],
+				vec![Some(112), None, None, None, None, None],
+			]
+		);
+		assert_eq!(dropped, 0);
+
+		// The instances on later pages are given the positions that follow on from those on the pages before them.
+		let context: ActionContext = "sd-remap.Copy.Keypad.12.0".parse().unwrap();
+		let relocated = relocate_page(key_pages[2].clone(), 6, &context);
+		assert_eq!(relocated[0].as_ref().unwrap().context.to_string(), "sd-remap.Copy.Keypad.12.0");
+	}
+
+	#[test]
+	fn sliders() {
+		let (pages, dropped) = place_sliders(keys(4, &[0, 3]), &device(2, 4, 2), RemapStrategy::Position);
+		assert_eq!(numbers(&pages), vec![vec![Some(0), None]]);
+		assert_eq!(dropped, 1);
+
+		let (pages, dropped) = place_sliders(keys(4, &[0, 3]), &device(2, 4, 2), RemapStrategy::Pages);
+		assert_eq!(numbers(&pages), vec![vec![Some(0), None], vec![Some(3), None]]);
+		assert_eq!(dropped, 0);
+	}
+}
@@ -1,6 +1,6 @@
 //! Duplicates of many structs to facilitate saving profiles to disk in a format that can be transferred between devices or systems.
 
-use crate::shared::{Action, ActionContext, ActionInstance, ActionState, Profile};
+use crate::shared::{Action, ActionContext, ActionInstance, ActionState, DialOptions, Page, Profile};
 
 use std::{
 	fs,
@@ -68,6 +68,8 @@ pub struct DiskActionInstance {
 	pub current_state: u16,
 	pub settings: serde_json::Value,
 	pub children: Option<Vec<DiskActionInstance>>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub dial: Option<DialOptions>,
 }
 
 impl From<ActionInstance> for DiskActionInstance {
@@ -125,7 +127,12 @@ impl From<ActionInstance> for DiskActionInstance {
 		}
 		value.action.icon = normalise_path(&value.action.icon);
 		value.action.property_inspector = normalise_path(&value.action.property_inspector);
+		if let Some(encoder) = &mut value.action.encoder {
+			encoder.layout = normalise_path(&encoder.layout);
+		}
 
+		// Only instances on encoders have dials, although profiles saved before the options were optional stored them for every instance.
+		let dial = value.dial.filter(|_| disk_context.controller == "Encoder");
 		Self {
 			context: disk_context,
 			action: value.action,
@@ -133,6 +140,7 @@ impl From<ActionInstance> for DiskActionInstance {
 			current_state: value.current_state,
 			settings: value.settings,
 			children: value.children.map(|c| c.into_iter().map(|v| v.into()).collect()),
+			dial,
 		}
 	}
 }
@@ -174,7 +182,13 @@ impl DiskActionInstance {
 		}
 		action.icon = reconstruct_path(&action.icon);
 		action.property_inspector = reconstruct_path(&action.property_inspector);
+		if let Some(encoder) = &mut action.encoder {
+			if !encoder.layout.starts_with('$') {
+				encoder.layout = reconstruct_path(&encoder.layout);
+			}
+		}
 
+		let dial = self.dial.filter(|_| self.context.controller == "Encoder");
 		ActionInstance {
 			context: self.context.into_action_context(device, profile),
 			action,
@@ -182,18 +196,19 @@ impl DiskActionInstance {
 			current_state: self.current_state,
 			settings: self.settings,
 			children: self.children.map(|c| c.into_iter().map(|v| v.into_action_instance(path)).collect()),
+			dial,
 		}
 	}
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct DiskProfile {
+pub struct DiskPage {
 	pub keys: Vec<Option<DiskActionInstance>>,
 	pub sliders: Vec<Option<DiskActionInstance>>,
 }
 
-impl From<&Profile> for DiskProfile {
-	fn from(value: &Profile) -> Self {
+impl From<&Page> for DiskPage {
+	fn from(value: &Page) -> Self {
 		Self {
 			keys: value.keys.clone().into_iter().map(|x| x.map(|v| v.into())).collect(),
 			sliders: value.sliders.clone().into_iter().map(|x| x.map(|v| v.into())).collect(),
@@ -201,6 +216,28 @@ impl From<&Profile> for DiskProfile {
 	}
 }
 
+impl DiskPage {
+	fn into_page(self, path: &Path) -> Page {
+		Page {
+			keys: self.keys.into_iter().map(|x| x.map(|v| v.into_action_instance(path))).collect(),
+			sliders: self.sliders.into_iter().map(|x| x.map(|v| v.into_action_instance(path))).collect(),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DiskProfile {
+	pub pages: Vec<DiskPage>,
+}
+
+impl From<&Profile> for DiskProfile {
+	fn from(value: &Profile) -> Self {
+		Self {
+			pages: value.pages.iter().map(|page| page.into()).collect(),
+		}
+	}
+}
+
 impl DiskProfile {
 	fn into_profile(self, path: &Path) -> Profile {
 		let config_dir = crate::shared::config_dir();
@@ -210,8 +247,7 @@ impl DiskProfile {
 		id = id[..id.len() - 5].to_owned();
 		Profile {
 			id,
-			keys: self.keys.into_iter().map(|x| x.map(|v| v.into_action_instance(path))).collect(),
-			sliders: self.sliders.into_iter().map(|x| x.map(|v| v.into_action_instance(path))).collect(),
+			pages: self.pages.into_iter().map(|page| page.into_page(path)).collect(),
 		}
 	}
 }
@@ -0,0 +1,283 @@
+//! Software devices created by the user, which can be used without any hardware connected.
+//! Their keys are shown in the frontend like those of any other device, and their input comes from the frontend or the local API.
+
+use crate::events::inbound::{devices, PayloadEvent};
+use crate::events::outbound::{encoder, keypad};
+use crate::shared::{config_dir, DeviceInfo, DEVICES};
+use crate::store::{NotProfile, Store};
+
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+/// The prefix of the IDs of virtual devices, which is reserved from use as a plugin device namespace.
+pub const NAMESPACE: &str = "vd";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct VirtualDevice {
+	pub id: String,
+	pub name: String,
+	pub rows: u8,
+	pub columns: u8,
+	pub encoders: u8,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct VirtualDevices {
+	devices: Vec<VirtualDevice>,
+}
+
+impl NotProfile for VirtualDevices {}
+
+/// The images most recently written to the keys of virtual devices.
+static IMAGES: Lazy<DashMap<(String, u8), String>> = Lazy::new(DashMap::new);
+
+fn get_store() -> Result<Store<VirtualDevices>, anyhow::Error> {
+	Store::new("virtual_devices", &config_dir(), VirtualDevices::default())
+}
+
+impl VirtualDevice {
+	fn info(&self) -> DeviceInfo {
+		DeviceInfo {
+			id: self.id.clone(),
+			plugin: String::new(),
+			name: self.name.clone(),
+			rows: self.rows,
+			columns: self.columns,
+			encoders: self.encoders,
+			// Virtual devices are given the type of the smallest Elgato device with the same controllers and at least as many keys.
+			r#type: match (self.encoders, self.rows as u16 * self.columns as u16) {
+				(1.., _) => 7,
+				(_, ..=6) => 1,
+				(_, ..=15) => 0,
+				_ => 2,
+			},
+			orientation: 0,
+		}
+	}
+
+	async fn register(&self) -> Result<(), anyhow::Error> {
+		devices::register_device("", PayloadEvent { payload: self.info() }).await
+	}
+}
+
+pub fn get_virtual_devices() -> Result<Vec<VirtualDevice>, anyhow::Error> {
+	Ok(get_store()?.value.devices)
+}
+
+/// Create a virtual device and register it.
+pub async fn create_virtual_device(name: String, rows: u8, columns: u8, encoders: u8) -> Result<VirtualDevice, anyhow::Error> {
+	if rows == 0 || columns == 0 || rows as u16 * columns as u16 > 64 || encoders > 8 {
+		return Err(anyhow::anyhow!("virtual devices must have between 1 and 64 keys and at most 8 encoders"));
+	}
+
+	let mut store = get_store()?;
+	let number = (1..).find(|number| !store.value.devices.iter().any(|device| device.id == format!("{NAMESPACE}-{number}"))).unwrap();
+	let device = VirtualDevice {
+		id: format!("{NAMESPACE}-{number}"),
+		name: if name.trim().is_empty() { format!("Virtual Deck {number}") } else { name },
+		rows,
+		columns,
+		encoders,
+	};
+	store.value.devices.push(device.clone());
+	store.save()?;
+
+	device.register().await?;
+	Ok(device)
+}
+
+/// Deregister and delete a virtual device. Its profiles are kept in case a device with the same ID is created again.
+pub async fn remove_virtual_device(id: &str) -> Result<(), anyhow::Error> {
+	let mut store = get_store()?;
+	store.value.devices.retain(|device| device.id != id);
+	store.save()?;
+
+	devices::deregister_device("", PayloadEvent { payload: id.to_owned() }).await?;
+	IMAGES.retain(|(device, _), _| device != id);
+	Ok(())
+}
+
+/// Register all virtual devices when OpenDeck starts.
+pub async fn initialise_virtual_devices() {
+	let devices = match get_virtual_devices() {
+		Ok(devices) => devices,
+		Err(error) => {
+			log::error!("Failed to load virtual devices: {}", error);
+			return;
+		}
+	};
+	for device in devices {
+		if let Err(error) = device.register().await {
+			log::warn!("Failed to register virtual device {}: {}", device.id, error);
+		}
+	}
+}
+
+pub fn update_image(device: &str, position: u8, image: Option<&str>) {
+	let key = (device.to_owned(), position);
+	if let Some(image) = image {
+		IMAGES.insert(key, image.to_owned());
+	} else {
+		IMAGES.remove(&key);
+	}
+}
+
+pub fn clear_screen(device: &str) {
+	IMAGES.retain(|(id, _), _| id != device);
+}
+
+/// Press or release a key of a device from software, at its position in the layout as the device is mounted.
+pub async fn key(device: &str, position: u8, pressed: bool) -> Result<(), anyhow::Error> {
+	let Some(physical) = DEVICES.get(device).map(|info| info.physical_position(position)) else {
+		return Err(anyhow::anyhow!("device {device} not found"));
+	};
+	let Some(position) = physical else {
+		return Err(anyhow::anyhow!("key {position} not found on device {device}"));
+	};
+	if pressed {
+		keypad::key_down(device, position).await
+	} else {
+		keypad::key_up(device, position).await
+	}
+}
+
+/// Rotate a dial of a device from software by a number of ticks, which is negative for counter-clockwise rotation.
+pub async fn dial_rotate(device: &str, index: u8, ticks: i16) -> Result<(), anyhow::Error> {
+	if !DEVICES.contains_key(device) {
+		return Err(anyhow::anyhow!("device {device} not found"));
+	}
+	encoder::dial_rotate(device, index, ticks).await
+}
+
+/// Press or release a dial of a device from software.
+pub async fn dial(device: &str, index: u8, pressed: bool) -> Result<(), anyhow::Error> {
+	if !DEVICES.contains_key(device) {
+		return Err(anyhow::anyhow!("device {device} not found"));
+	}
+	encoder::dial_press(device, if pressed { "dialDown" } else { "dialUp" }, index).await
+}
+
+/// Get the image most recently written to a key of a virtual device.
+pub fn get_image(device: &str, position: u8) -> Option<String> {
+	IMAGES.get(&(device.to_owned(), position)).map(|image| image.clone())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+
+	use crate::shared::{Action, ActionContext, ActionInstance, Context, CATEGORIES};
+	use crate::store::profiles::{acquire_locks_mut, get_slot_mut};
+
+	use serde_json::{json, Value};
+
+	/// Connect a virtual device with a single row of keys, named so that tests do not share devices.
+	pub(crate) fn connect(name: &str, columns: u8) -> VirtualDevice {
+		let device = VirtualDevice {
+			id: format!("{NAMESPACE}-test-{name}"),
+			name: "Test".to_owned(),
+			rows: 1,
+			columns,
+			encoders: 0,
+		};
+		DEVICES.insert(device.id.clone(), device.info());
+		device
+	}
+
+	/// Get an action from a plugin that is not running, whose events are queued until it connects.
+	pub(crate) fn action(uuid: &str) -> Action {
+		serde_json::from_value(json!({
+			"name": "Test",
+			"uuid": uuid,
+			"plugin": "com.example.test",
+			"states": [ { "image": "opendeck/cube.png" } ]
+		}))
+		.unwrap()
+	}
+
+	/// Get one of the actions built into OpenDeck.
+	pub(crate) async fn builtin(uuid: &str) -> Action {
+		CATEGORIES.read().await["OpenDeck"].iter().find(|action| action.uuid == uuid).unwrap().clone()
+	}
+
+	/// Place an instance of an action in a key slot of the selected profile of a device, with instances of `children` numbered from 1 as its children.
+	pub(crate) async fn place(device: &str, position: u8, action: Action, settings: Value, children: Option<Vec<Action>>) -> ActionContext {
+		let mut locks = acquire_locks_mut().await;
+		let profile = locks.device_stores.get_selected_profile(device).unwrap();
+		let context = Context {
+			device: device.to_owned(),
+			profile,
+			controller: "Keypad".to_owned(),
+			position,
+		};
+		let instance = |action: Action, index: u16| ActionInstance {
+			states: action.states.clone(),
+			action,
+			context: ActionContext::from_context(context.clone(), index),
+			current_state: 0,
+			settings: json!({}),
+			children: None,
+			dial: Default::default(),
+		};
+		let children = children.map(|children| children.into_iter().zip(1..).map(|(action, index)| instance(action, index)).collect());
+		let instance = ActionInstance {
+			settings,
+			children,
+			..instance(action, 0)
+		};
+		let context = instance.context.clone();
+		*get_slot_mut(&(&context).into(), &mut locks).await.unwrap() = Some(instance);
+		context
+	}
+
+	/// Get the index of the instance and the name of each event queued for the test plugin from instances on a device.
+	pub(crate) async fn queued_events(device: &str) -> Vec<(u16, String)> {
+		let queues = crate::events::PLUGIN_QUEUES.read().await;
+		queues
+			.get("com.example.test")
+			.into_iter()
+			.flatten()
+			.filter_map(|message| serde_json::from_str::<Value>(message.to_text().ok()?).ok())
+			.filter(|event| event["device"] == device)
+			.filter_map(|event| {
+				let context: ActionContext = event["context"].as_str()?.parse().ok()?;
+				Some((context.index, event["event"].as_str()?.to_owned()))
+			})
+			.collect()
+	}
+
+	/// Get the events queued for instances on a device since the last time they were checked, updating the number of events seen.
+	pub(crate) async fn new_events(device: &str, seen: &mut usize) -> Vec<(u16, String)> {
+		let events = queued_events(device).await.split_off(*seen);
+		*seen += events.len();
+		events
+	}
+
+	pub(crate) fn events(events: &[(u16, &str)]) -> Vec<(u16, String)> {
+		events.iter().map(|(index, event)| (*index, (*event).to_owned())).collect()
+	}
+
+	#[tokio::test]
+	async fn virtual_key_press() {
+		let device = connect("press", 2);
+		place(&device.id, 1, action("com.example.test.action"), json!({}), None).await;
+
+		key(&device.id, 1, true).await.unwrap();
+		let pressed = get_image(&device.id, 1).expect("pressed key was not drawn");
+		key(&device.id, 1, false).await.unwrap();
+		let released = get_image(&device.id, 1).expect("released key was not drawn");
+		// Pressed keys are drawn smaller than released keys.
+		assert_ne!(pressed, released);
+
+		// The plugin is not running, so the events sent to it are queued until it connects.
+		assert_eq!(queued_events(&device.id).await, [(0, "keyDown".to_owned()), (0, "keyUp".to_owned())]);
+
+		// Keys outside of the layout and devices that do not exist are rejected.
+		assert!(key(&device.id, 2, true).await.is_err());
+		assert!(key(&format!("{NAMESPACE}-missing"), 0, true).await.is_err());
+		assert!(get_image(&device.id, 0).is_none());
+
+		DEVICES.remove(&device.id);
+	}
+}
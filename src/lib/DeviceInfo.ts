@@ -5,4 +5,5 @@ export type DeviceInfo = {
 	columns: number;
 	encoders: number;
 	type: number;
+	orientation: number;
 };
@@ -1,8 +1,24 @@
 import type { ActionInstance } from "./ActionInstance";
 
+export type Page = {
+	keys: (ActionInstance | null)[];
+	sliders: (ActionInstance | null)[];
+};
+
 export type Profile = {
 	device: string;
 	id: string;
-	keys: (ActionInstance | null)[];
-	sliders: (ActionInstance | null)[];
+	pages: Page[];
 };
+
+// Positions follow on from those on the pages before them, so each position is on page `position / size` in slot `position % size`.
+export function slotsAt(profile: Profile, controller: string, position: number): [(ActionInstance | null)[], number] {
+	const size = controller == "Encoder" ? profile.pages[0].sliders.length : profile.pages[0].keys.length;
+	const page = profile.pages[size ? Math.floor(position / size) : 0] ?? profile.pages[0];
+	return [controller == "Encoder" ? page.sliders : page.keys, size ? position % size : position];
+}
+
+export function instanceAt(profile: Profile, controller: string, position: number): ActionInstance | null {
+	const [slots, slot] = slotsAt(profile, controller, position);
+	return slots[slot] ?? null;
+}
@@ -1,7 +1,4 @@
-import { invoke } from "@tauri-apps/api/core";
-
 import type { ActionState } from "./ActionState";
-import type { Context } from "./Context";
 
 export function getImage(image: string | undefined, fallback: string | undefined): string {
 	if (!image) return fallback ? getImage(fallback, undefined) : "/alert.png";
@@ -22,13 +19,11 @@ export function getImage(image: string | undefined, fallback: string | undefined
 
 export async function renderImage(
 	canvas: HTMLCanvasElement,
-	slotContext: Context,
 	state: ActionState,
 	fallback: string | undefined,
 	showOk: boolean,
 	showAlert: boolean,
 	processImage: boolean,
-	active: boolean,
 	pressed: boolean,
 ) {
 	// Create canvas
@@ -41,10 +36,11 @@ export async function renderImage(
 		scale = canvas.width / 144;
 	}
 
-	let context = canvas.getContext("2d");
+	const context = canvas.getContext("2d");
 	if (!context) return;
 	context.clearRect(0, 0, canvas.width, canvas.height);
 
+	let failed = false;
 	try {
 		// Load image
 		let image = document.createElement("img");
@@ -61,7 +57,7 @@ export async function renderImage(
 		context.drawImage(image, 0, 0, canvas.width, canvas.height);
 	} catch (error: any) {
 		if (!(error instanceof Event)) console.error(error);
-		showAlert = true;
+		failed = true;
 	}
 
 	// Draw text
@@ -99,25 +95,17 @@ export async function renderImage(
 		}
 	}
 
-	if (showOk) {
-		let okImage = document.createElement("img");
-		okImage.crossOrigin = "anonymous";
-		okImage.src = "/ok.png";
+	const drawOverlay = async (source: string) => {
+		let overlayImage = document.createElement("img");
+		overlayImage.crossOrigin = "anonymous";
+		overlayImage.src = source;
 		await new Promise((resolve) => {
-			okImage.onload = resolve;
+			overlayImage.onload = resolve;
 		});
-		context.drawImage(okImage, 0, 0, canvas.width, canvas.height);
-	}
+		context.drawImage(overlayImage, 0, 0, canvas.width, canvas.height);
+	};
 
-	if (showAlert) {
-		let alertImage = document.createElement("img");
-		alertImage.crossOrigin = "anonymous";
-		alertImage.src = "/alert.png";
-		await new Promise((resolve) => {
-			alertImage.onload = resolve;
-		});
-		context.drawImage(alertImage, 0, 0, canvas.width, canvas.height);
-	}
+	if (failed) await drawOverlay("/alert.png");
 
 	// Make the image smaller while the button is pressed.
 	if (pressed) {
@@ -139,7 +127,9 @@ export async function renderImage(
 		}
 	}
 
-	if (active) setTimeout(async () => await invoke("update_image", { context: slotContext, image: canvas.toDataURL("image/jpeg") }), 10);
+	// Device images are rendered by the backend, so overlays requested by plugins are only drawn for display here.
+	if (showOk) await drawOverlay("/ok.png");
+	if (showAlert && !failed) await drawOverlay("/alert.png");
 }
 
 export async function resizeImage(source: string): Promise<string | undefined> {
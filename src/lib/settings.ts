@@ -5,6 +5,10 @@ export type Settings = {
 	autolaunch: boolean;
 	darktheme: boolean;
 	brightness: number;
+	brightnessschedule: { time: string; level: number }[];
+	brightnessfade: number;
+	swipeaction: "none" | "profile" | "page";
+	feedbackduration: number;
 	developer: boolean;
 	updatecheck: boolean;
 	statistics: boolean;